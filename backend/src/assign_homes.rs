@@ -1,16 +1,90 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::ir::x86::{Reg, VarArg, VarBlock, VarInstr, VarProgram};
 
+fn variable(arg: &VarArg) -> Option<&str> {
+    match arg {
+        VarArg::Variable(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// Returns the operands that `instr` reads, and the operand that it writes.
+fn reads_and_writes(instr: &VarInstr) -> (Vec<&VarArg>, Option<&VarArg>) {
+    match instr {
+        // Clears the operand without reading it.
+        VarInstr::Xorq { lhs, rhs } if lhs == rhs => (vec![], Some(lhs)),
+        VarInstr::Addq { lhs, rhs } | VarInstr::Subq { lhs, rhs } | VarInstr::Xorq { lhs, rhs } => {
+            (vec![lhs, rhs], Some(lhs))
+        }
+        VarInstr::Movq { from, to } => (vec![from], Some(to)),
+        VarInstr::Negq { operand } | VarInstr::Incq { operand } | VarInstr::Decq { operand } => {
+            (vec![operand], Some(operand))
+        }
+        VarInstr::Pushq { operand } => (vec![operand], None),
+        VarInstr::Popq { operand } => (vec![], Some(operand)),
+        VarInstr::Callq { callee: _ }
+        | VarInstr::Retq
+        | VarInstr::Jmp { target: _ }
+        | VarInstr::Jo { target: _ } => (vec![], None),
+    }
+}
+
+/// Computes the variables that are live after every instruction of `program`. The variables are
+/// dead when the program returns, and when it jumps to a label that is not defined.
+fn live_after(program: &VarProgram) -> Vec<Vec<HashSet<&str>>> {
+    let find_block = |label: &str| program.body.iter().position(|block| block.label == label);
+    let mut live_before: Vec<HashSet<&str>> = vec![HashSet::new(); program.body.len()];
+    let mut result: Vec<Vec<HashSet<&str>>> = program
+        .body
+        .iter()
+        .map(|block| vec![HashSet::new(); block.instructions.len()])
+        .collect();
+
+    // The live variables only grow, so this terminates.
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (index, block) in program.body.iter().enumerate().rev() {
+            let live_at = |label: &str| find_block(label).map(|block| live_before[block].clone());
+            let mut live = live_before.get(index + 1).cloned().unwrap_or_default();
+            for (position, instr) in block.instructions.iter().enumerate().rev() {
+                result[index][position] = live.clone();
+                match instr {
+                    VarInstr::Jmp { target } => live = live_at(target).unwrap_or_default(),
+                    VarInstr::Jo { target } => live.extend(live_at(target).unwrap_or_default()),
+                    VarInstr::Retq => live.clear(),
+                    _ => {
+                        let (reads, write) = reads_and_writes(instr);
+                        if let Some(name) = write.and_then(variable) {
+                            live.remove(name);
+                        }
+                        live.extend(reads.into_iter().filter_map(variable));
+                    }
+                }
+            }
+            if live != live_before[index] {
+                live_before[index] = live;
+                changed = true;
+            }
+        }
+    }
+
+    result
+}
+
 struct AssignHomesImpl {
     // Map variable to the offset of its storage relative to %rbp.
     variable_locations: HashMap<String, i64>,
+    // The number of 8-byte slots of the stack frame.
+    slot_count: usize,
 }
 
 impl AssignHomesImpl {
     fn new() -> Self {
         Self {
             variable_locations: HashMap::new(),
+            slot_count: 0,
         }
     }
 
@@ -18,44 +92,88 @@ impl AssignHomesImpl {
         VarArg::Deref(Reg::RBP, offset)
     }
 
-    fn assign_homes_for_variables(&mut self, variables: Vec<String>) {
-        let mut offset = -8;
-        variables.into_iter().for_each(|name| {
-            self.variable_locations.insert(name, offset);
-            offset -= 8;
-        });
+    /// Finds the pairs of variables that may hold different values at the same time: a variable
+    /// that is written interferes with the variables that are live after the write, except with
+    /// the source of a `movq`, which holds the same value.
+    fn interferences(program: &VarProgram) -> HashSet<(&str, &str)> {
+        let mut result = HashSet::new();
+        for (block, live_after) in program.body.iter().zip(live_after(program)) {
+            for (instr, live) in block.instructions.iter().zip(live_after) {
+                let Some(written) = reads_and_writes(instr).1.and_then(variable) else {
+                    continue;
+                };
+                let source = match instr {
+                    VarInstr::Movq { from, .. } => variable(from),
+                    _ => None,
+                };
+                for name in live {
+                    if name != written && Some(name) != source {
+                        result.insert((written, name));
+                        result.insert((name, written));
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Gives every variable the first slot that is not used by a variable it interferes with, so
+    /// that the variables whose live ranges are disjoint share slots.
+    fn assign_homes_for_variables(&mut self, program: &VarProgram) {
+        let interferences = Self::interferences(program);
+        let mut slots: HashMap<&str, usize> = HashMap::new();
+        for name in &program.local_variables {
+            let used: HashSet<usize> = slots
+                .iter()
+                .filter(|(other, _)| interferences.contains(&(name.as_str(), **other)))
+                .map(|(_, slot)| *slot)
+                .collect();
+            let slot = (0..).find(|slot| !used.contains(slot)).unwrap();
+            slots.insert(name, slot);
+            self.slot_count = self.slot_count.max(slot + 1);
+            self.variable_locations
+                .insert(name.clone(), -8 * (slot as i64 + 1));
+        }
     }
 
     fn modify_arg(&self, arg: &mut VarArg) {
-        match arg {
-            VarArg::Variable(name) => {
-                *arg = Self::rbp_reg(*self.variable_locations.get(name).unwrap())
-            }
-            _ => (),
-        };
+        if let VarArg::Variable(name) = arg {
+            *arg = Self::rbp_reg(*self.variable_locations.get(name).unwrap())
+        }
     }
 
     fn modify_block(&self, block: &mut VarBlock) {
         block.instructions.iter_mut().for_each(|instr| match instr {
             VarInstr::Addq { lhs, rhs }
             | VarInstr::Subq { lhs, rhs }
+            | VarInstr::Xorq { lhs, rhs }
             | VarInstr::Movq { from: lhs, to: rhs } => {
                 self.modify_arg(lhs);
                 self.modify_arg(rhs);
             }
 
             VarInstr::Negq { operand }
+            | VarInstr::Incq { operand }
+            | VarInstr::Decq { operand }
             | VarInstr::Pushq { operand }
             | VarInstr::Popq { operand } => {
                 self.modify_arg(operand);
             }
 
             // Make sure that we won't miss some cases if we modify the VarInstr enum.
-            VarInstr::Callq { callee: _ } | VarInstr::Retq | VarInstr::Jmp { target: _ } => (),
+            VarInstr::Callq { callee: _ }
+            | VarInstr::Retq
+            | VarInstr::Jmp { target: _ }
+            | VarInstr::Jo { target: _ } => (),
         });
+        // A move of a location to itself, such as between variables that share a slot, does
+        // nothing.
+        block
+            .instructions
+            .retain(|instr| !matches!(instr, VarInstr::Movq { from, to } if from == to));
     }
 
-    fn modify_program(&self, program_body: &mut Vec<VarBlock>) {
+    fn modify_program(&self, program_body: &mut [VarBlock]) {
         program_body
             .iter_mut()
             .for_each(|block| self.modify_block(block));
@@ -64,59 +182,170 @@ impl AssignHomesImpl {
 
 pub(crate) fn assign_homes(mut program: VarProgram) -> VarProgram {
     let mut pass_impl = AssignHomesImpl::new();
-    pass_impl.assign_homes_for_variables(program.local_variables);
+    pass_impl.assign_homes_for_variables(&program);
     program.local_variables = Vec::new();
+    // The stack must stay 16-byte aligned at every `callq`.
+    program.frame_size = (8 * pass_impl.slot_count).next_multiple_of(16);
     pass_impl.modify_program(&mut program.body);
     program
 }
 
 #[cfg(test)]
 mod test {
-    use frontend::parse_expr;
+    use frontend::{parse_expr, ArithmeticSemantics};
 
     use crate::{explicate_control::explicate_control, select_instructions::select_instructions};
 
     use super::*;
 
     fn prepare_program(code: &str) -> VarProgram {
-        select_instructions(explicate_control(parse_expr(code).unwrap()))
+        select_instructions(
+            explicate_control(parse_expr(code).unwrap()),
+            ArithmeticSemantics::Checked,
+        )
     }
 
     #[test]
     fn assign_homes_test() {
+        // `b` is a copy of `a`, so they share a slot and the move is removed. The frame is rounded
+        // up to keep the stack aligned.
+        let program = assign_homes(prepare_program("let ([a 42]) (let ([b a]) b)"));
+        assert_eq!(program.frame_size, 16);
         assert_eq!(
-            assign_homes(prepare_program("let ([a 42]) (let ([b a]) b)"))
-                .to_string()
-                .trim(),
+            program.to_string().trim(),
             r#"
+frame: 16
 main:
     movq    $0x2a, -8(%rbp)
-    movq    -8(%rbp), -16(%rbp)
-    movq    -16(%rbp), %rax
+    movq    -8(%rbp), %rax
     jmp     conclusion
 conclusion:
     "#
             .trim()
         );
 
+        // `y` is assigned when `x1` and `x2` are dead.
+        let program = assign_homes(prepare_program(
+            "let ([y (let ([x1 (- 20)]) (let ([x2 22]) (+ x1 x2)))]) y",
+        ));
+        assert_eq!(program.frame_size, 16);
         assert_eq!(
-            assign_homes(prepare_program(
-                "let ([y (let ([x1 (- 20)]) (let ([x2 22]) (+ x1 x2)))]) y"
-            ))
-            .to_string()
-            .trim(),
+            program.to_string().trim(),
             r#"
+frame: 16
 main:
     movq    $0x14, -8(%rbp)
     negq    -8(%rbp)
     movq    $0x16, -16(%rbp)
-    movq    -8(%rbp), -24(%rbp)
-    addq    -16(%rbp), -24(%rbp)
-    movq    -24(%rbp), %rax
+    movq    -8(%rbp), %rax
+    addq    -16(%rbp), %rax
+    movq    %rax, -8(%rbp)
+    movq    -8(%rbp), %rax
     jmp     conclusion
 conclusion:
     "#
             .trim()
         );
     }
+
+    #[test]
+    fn share_slots_across_blocks() {
+        let variable = |name: &str| VarArg::Variable(name.to_string());
+        let block = |label: &str, instructions| VarBlock {
+            label: label.to_string(),
+            instructions,
+        };
+
+        // `sum` is live around the loop, and `y` on the edge to `overflow`.
+        let program = VarProgram {
+            local_variables: ["sum", "x", "y", "z"].map(String::from).to_vec(),
+            body: vec![
+                block(
+                    "main",
+                    vec![
+                        VarInstr::Movq {
+                            from: VarArg::Imm(0),
+                            to: variable("sum"),
+                        },
+                        VarInstr::Jmp {
+                            target: "loop".to_string(),
+                        },
+                    ],
+                ),
+                block(
+                    "loop",
+                    vec![
+                        VarInstr::Callq {
+                            callee: "read_int".to_string(),
+                        },
+                        VarInstr::Movq {
+                            from: Reg::RAX.into(),
+                            to: variable("x"),
+                        },
+                        VarInstr::Addq {
+                            lhs: variable("sum"),
+                            rhs: variable("x"),
+                        },
+                        VarInstr::Movq {
+                            from: variable("sum"),
+                            to: variable("y"),
+                        },
+                        VarInstr::Negq {
+                            operand: variable("y"),
+                        },
+                        VarInstr::Jo {
+                            target: "overflow".to_string(),
+                        },
+                        VarInstr::Jmp {
+                            target: "loop".to_string(),
+                        },
+                    ],
+                ),
+                block(
+                    "overflow",
+                    vec![
+                        VarInstr::Movq {
+                            from: variable("y"),
+                            to: variable("z"),
+                        },
+                        VarInstr::Addq {
+                            lhs: variable("z"),
+                            rhs: variable("sum"),
+                        },
+                        VarInstr::Movq {
+                            from: variable("z"),
+                            to: Reg::RAX.into(),
+                        },
+                        VarInstr::Retq,
+                    ],
+                ),
+            ],
+            frame_size: 0,
+        };
+
+        let program = assign_homes(program);
+        assert_eq!(program.frame_size, 16);
+        assert_eq!(
+            program.to_string().trim(),
+            r#"
+frame: 16
+main:
+    movq    $0x0, -8(%rbp)
+    jmp     loop
+loop:
+    callq   read_int
+    movq    %rax, -16(%rbp)
+    addq    -16(%rbp), -8(%rbp)
+    movq    -8(%rbp), -16(%rbp)
+    negq    -16(%rbp)
+    jo      overflow
+    jmp     loop
+overflow:
+    addq    -8(%rbp), -16(%rbp)
+    movq    -16(%rbp), %rax
+    retq
+    "#
+            .trim()
+        );
+    }
 }
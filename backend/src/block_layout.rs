@@ -0,0 +1,511 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::x86::{VarBlock, VarInstr, VarProgram};
+
+// A block of the control-flow graph, whose jump to its successor is not part of the instructions
+// yet, so that it can be placed anywhere.
+#[derive(Debug)]
+struct Node {
+    label: String,
+    // The instructions without the unconditional jump at the end.
+    instructions: Vec<VarInstr>,
+    // The block that runs after the instructions, unless a conditional jump is taken, or `None`
+    // if the block returns or exits the program.
+    successor: Option<String>,
+}
+
+impl Node {
+    /// The targets of the conditional jumps of the block.
+    fn branch_targets(&self) -> impl Iterator<Item = &str> {
+        self.instructions.iter().filter_map(|instr| match instr {
+            VarInstr::Jo { target } => Some(target.as_str()),
+            _ => None,
+        })
+    }
+
+    fn targets(&self) -> impl Iterator<Item = &str> {
+        self.branch_targets().chain(self.successor.as_deref())
+    }
+}
+
+/// Checks whether the execution never continues after `instr`.
+fn is_terminator(instr: &VarInstr) -> bool {
+    match instr {
+        VarInstr::Retq | VarInstr::Jmp { .. } => true,
+        // The overflow handlers exit the program.
+        VarInstr::Callq { callee } => callee == "exit",
+        _ => false,
+    }
+}
+
+struct BlockLayoutImpl {
+    local_variables: Vec<String>,
+    frame_size: usize,
+    nodes: Vec<Node>,
+    entry: String,
+    // The last block, if the program ends by falling off it. It has to stay last.
+    exit: Option<String>,
+}
+
+impl BlockLayoutImpl {
+    fn new(program: VarProgram) -> Self {
+        let entry = program
+            .body
+            .iter()
+            .find(|block| block.label == "main")
+            .or(program.body.first())
+            .map(|block| block.label.clone())
+            .unwrap_or_default();
+        let labels: Vec<String> = program
+            .body
+            .iter()
+            .map(|block| block.label.clone())
+            .collect();
+
+        let mut exit = None;
+        let mut nodes = Vec::new();
+        for (index, block) in program.body.into_iter().enumerate() {
+            let mut instructions = block.instructions;
+            let successor = match instructions.last() {
+                Some(VarInstr::Jmp { target }) => {
+                    let target = target.clone();
+                    instructions.pop();
+                    Some(target)
+                }
+                Some(instr) if is_terminator(instr) => None,
+                // Falls through to the next block.
+                _ => match labels.get(index + 1) {
+                    Some(label) => Some(label.clone()),
+                    None => {
+                        exit = Some(block.label.clone());
+                        None
+                    }
+                },
+            };
+            nodes.push(Node {
+                label: block.label,
+                instructions,
+                successor,
+            });
+        }
+
+        Self {
+            local_variables: program.local_variables,
+            frame_size: program.frame_size,
+            nodes,
+            entry,
+            exit,
+        }
+    }
+
+    fn find_node(&self, label: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.label == label)
+    }
+
+    fn remove_unreachable_nodes(&mut self) {
+        let mut reachable = HashSet::from([self.entry.clone()]);
+        let mut worklist = vec![self.entry.clone()];
+        while let Some(label) = worklist.pop() {
+            let Some(index) = self.find_node(&label) else {
+                continue;
+            };
+            for target in self.nodes[index].targets() {
+                if reachable.insert(target.to_string()) {
+                    worklist.push(target.to_string());
+                }
+            }
+        }
+        self.nodes.retain(|node| reachable.contains(&node.label));
+    }
+
+    /// Appends every block that is only reached from the end of another block to it. The entry
+    /// and the exit of the program keep their own blocks.
+    fn merge_nodes(&mut self) {
+        loop {
+            let mut edges: HashMap<&str, usize> = HashMap::new();
+            for target in self.nodes.iter().flat_map(Node::targets) {
+                *edges.entry(target).or_default() += 1;
+            }
+
+            let merge = self.nodes.iter().enumerate().find_map(|(index, node)| {
+                let successor = node.successor.as_deref()?;
+                if successor == node.label
+                    || successor == self.entry
+                    || Some(successor) == self.exit.as_deref()
+                    || edges[successor] != 1
+                {
+                    return None;
+                }
+                Some((index, self.find_node(successor)?))
+            });
+            let Some((index, successor)) = merge else {
+                return;
+            };
+
+            let successor_node = self.nodes.remove(successor);
+            let node = &mut self.nodes[if successor < index { index - 1 } else { index }];
+            node.instructions.extend(successor_node.instructions);
+            node.successor = successor_node.successor;
+        }
+    }
+
+    /// Splits the blocks into chains, in which each block falls through to the next one. A chain
+    /// starts with the entry or with the first block that is not placed yet, and is extended with
+    /// the successor of its last block as long as it is not placed. The entry chain is placed
+    /// first, and the exit is placed last: the chain that ends with it is moved to the end, unless
+    /// it is the entry chain, in which case only the exit is.
+    fn layout(&self) -> Vec<usize> {
+        let mut placed = vec![false; self.nodes.len()];
+        let mut chains: Vec<Vec<usize>> = Vec::new();
+        let entry = self.find_node(&self.entry);
+
+        for head in entry.into_iter().chain(0..self.nodes.len()) {
+            if placed[head] {
+                continue;
+            }
+            placed[head] = true;
+            let mut chain = vec![head];
+            while let Some(next) = self.nodes[*chain.last().unwrap()]
+                .successor
+                .as_deref()
+                .and_then(|label| self.find_node(label))
+                .filter(|&next| !placed[next])
+            {
+                placed[next] = true;
+                chain.push(next);
+            }
+            chains.push(chain);
+        }
+
+        let exit = self.exit.as_deref().and_then(|label| self.find_node(label));
+        let exit_chain =
+            exit.and_then(|exit| chains.iter().position(|chain| chain.last() == Some(&exit)));
+        match exit_chain {
+            Some(0) if chains.len() > 1 => {
+                let exit = chains[0].pop().unwrap();
+                chains.push(vec![exit]);
+            }
+            Some(index) if index > 0 => {
+                let chain = chains.remove(index);
+                chains.push(chain);
+            }
+            _ => (),
+        }
+        chains.into_iter().flatten().collect()
+    }
+
+    fn run(mut self) -> VarProgram {
+        self.remove_unreachable_nodes();
+        self.merge_nodes();
+        let order = self.layout();
+
+        let next_labels: Vec<Option<String>> = order
+            .iter()
+            .skip(1)
+            .map(|&index| Some(self.nodes[index].label.clone()))
+            .chain([None])
+            .collect();
+        let mut nodes: Vec<Option<Node>> = self.nodes.into_iter().map(Some).collect();
+        let mut body = Vec::new();
+        for (index, next_label) in order.into_iter().zip(next_labels) {
+            let node = nodes[index].take().unwrap();
+            let mut block = VarBlock {
+                label: node.label,
+                instructions: node.instructions,
+            };
+            // The jump to the successor is only needed if it is not the next block.
+            if let Some(successor) = node.successor {
+                if next_label.as_ref() != Some(&successor) {
+                    block.add_instr(VarInstr::Jmp { target: successor });
+                }
+            }
+            body.push(block);
+        }
+
+        VarProgram {
+            local_variables: self.local_variables,
+            body,
+            frame_size: self.frame_size,
+        }
+    }
+}
+
+/// Reorders the blocks of `program` so that as many blocks as possible fall through to their
+/// successor instead of jumping to it. Blocks that are unreachable from the entry are removed,
+/// and a block that is only reached from the end of another block is merged into it. Conditional
+/// jumps are left unchanged.
+pub(crate) fn block_layout(program: VarProgram) -> VarProgram {
+    BlockLayoutImpl::new(program).run()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use frontend::{parse_expr, ArithmeticSemantics, Evaluation};
+
+    use crate::{
+        assign_homes::assign_homes,
+        explicate_control::explicate_control,
+        interp::x86::{emulate_x86_with_options, EmulatorError, EmulatorOptions},
+        ir::x86::{Reg, VarArg},
+        patch_instructions::patch_instructions,
+        remove_complex_operands::remove_complex_operands,
+        select_instructions::select_instructions,
+        uniquify::uniquify_expr,
+        verify::{verify_x86, X86Stage},
+        Ir, PassFilter, PassManager, PassManagerOptions,
+    };
+
+    use super::*;
+
+    use VarArg::Imm;
+
+    // Stops the programs that loop forever, e.g. after a wrong layout.
+    fn emulate(
+        program: &VarProgram,
+        input: &mut VecDeque<i64>,
+    ) -> Result<Evaluation, EmulatorError> {
+        let options = EmulatorOptions { fuel: Some(10_000) };
+        emulate_x86_with_options(program, input, options)
+    }
+
+    fn rax() -> VarArg {
+        Reg::RAX.into()
+    }
+
+    fn jmp(target: &str) -> VarInstr {
+        VarInstr::Jmp {
+            target: target.to_string(),
+        }
+    }
+
+    fn program(blocks: Vec<(&str, Vec<VarInstr>)>) -> VarProgram {
+        VarProgram {
+            local_variables: Vec::new(),
+            body: blocks
+                .into_iter()
+                .map(|(label, instructions)| VarBlock {
+                    label: label.to_string(),
+                    instructions,
+                })
+                .collect(),
+            frame_size: 0,
+        }
+    }
+
+    /// Lays out `program`, and checks that the result behaves like it on every input.
+    fn layout(program: VarProgram, inputs: &[Vec<i64>]) -> String {
+        let result = block_layout(program.clone());
+        for inputs in inputs {
+            let expected = emulate(&program, &mut VecDeque::from(inputs.clone()));
+            assert!(expected.is_ok());
+            assert_eq!(
+                emulate(&result, &mut VecDeque::from(inputs.clone())),
+                expected
+            );
+        }
+        result.to_string()
+    }
+
+    #[test]
+    fn merge_blocks() {
+        let program = program(vec![
+            (
+                "third",
+                vec![
+                    VarInstr::Addq {
+                        lhs: rax(),
+                        rhs: Imm(4),
+                    },
+                    jmp("conclusion"),
+                ],
+            ),
+            (
+                "main",
+                vec![
+                    VarInstr::Movq {
+                        from: Imm(1),
+                        to: rax(),
+                    },
+                    jmp("second"),
+                ],
+            ),
+            (
+                "unreachable",
+                vec![
+                    VarInstr::Movq {
+                        from: Imm(0),
+                        to: rax(),
+                    },
+                    jmp("conclusion"),
+                ],
+            ),
+            (
+                "second",
+                vec![
+                    VarInstr::Addq {
+                        lhs: rax(),
+                        rhs: Imm(2),
+                    },
+                    VarInstr::Jo {
+                        target: "overflow".to_string(),
+                    },
+                    jmp("third"),
+                ],
+            ),
+            (
+                "overflow",
+                vec![
+                    VarInstr::Movq {
+                        from: Imm(0x42),
+                        to: Reg::RDI.into(),
+                    },
+                    VarInstr::Callq {
+                        callee: "exit".to_string(),
+                    },
+                ],
+            ),
+            ("conclusion", vec![]),
+        ]);
+        assert_eq!(
+            layout(program, &[vec![]]).trim(),
+            r#"
+main:
+    movq    $0x1, %rax
+    addq    $0x2, %rax
+    jo      overflow
+    addq    $0x4, %rax
+    jmp     conclusion
+overflow:
+    movq    $0x42, %rdi
+    callq   exit
+conclusion:
+            "#
+            .trim()
+        );
+    }
+
+    #[test]
+    fn keep_shared_blocks() {
+        // Adds 1 to the largest 64-bit integer if the input is positive, and 2 otherwise.
+        let program = program(vec![
+            (
+                "positive",
+                vec![
+                    VarInstr::Movq {
+                        from: Imm(1),
+                        to: rax(),
+                    },
+                    jmp("done"),
+                ],
+            ),
+            (
+                "main",
+                vec![
+                    VarInstr::Callq {
+                        callee: "read_int".to_string(),
+                    },
+                    VarInstr::Movq {
+                        from: Imm(i64::MAX),
+                        to: Reg::RCX.into(),
+                    },
+                    VarInstr::Addq {
+                        lhs: rax(),
+                        rhs: Reg::RCX.into(),
+                    },
+                    VarInstr::Jo {
+                        target: "positive".to_string(),
+                    },
+                ],
+            ),
+            (
+                "negative",
+                vec![VarInstr::Movq {
+                    from: Imm(2),
+                    to: rax(),
+                }],
+            ),
+            (
+                "done",
+                vec![
+                    VarInstr::Addq {
+                        lhs: rax(),
+                        rhs: Imm(10),
+                    },
+                    jmp("conclusion"),
+                ],
+            ),
+            ("conclusion", vec![]),
+        ]);
+        assert_eq!(
+            layout(program, &[vec![5], vec![-5]]).trim(),
+            r#"
+main:
+    callq   read_int
+    movq    $0x7fffffffffffffff, %rcx
+    addq    %rcx, %rax
+    jo      positive
+    movq    $0x2, %rax
+done:
+    addq    $0xa, %rax
+    jmp     conclusion
+positive:
+    movq    $0x1, %rax
+    jmp     done
+conclusion:
+            "#
+            .trim()
+        );
+
+        // A block that jumps to itself keeps its jump.
+        let looping = self::program(vec![("main", vec![jmp("main")]), ("conclusion", vec![])]);
+        assert_eq!(
+            block_layout(looping).to_string().trim(),
+            "main:\n    jmp     main"
+        );
+    }
+
+    #[test]
+    fn layout_compiled_programs() {
+        for (code, inputs) in [
+            ("(let ([x read]) (- x 1))", vec![vec![5], vec![i64::MIN]]),
+            ("(+ (- read) read)", vec![vec![1, 2], vec![i64::MIN, 0]]),
+        ] {
+            for semantics in [ArithmeticSemantics::Checked, ArithmeticSemantics::Trapping] {
+                let program = patch_instructions(assign_homes(select_instructions(
+                    explicate_control(remove_complex_operands(
+                        uniquify_expr(parse_expr(code).unwrap()).unwrap(),
+                    )),
+                    semantics,
+                )));
+                let result = block_layout(program.clone());
+                assert_eq!(verify_x86(&result, X86Stage::Patched), Ok(()));
+                // The entry comes first, and only jumps over the trap handlers to the exit.
+                assert_eq!(result.body[0].label, "main");
+                assert!(result
+                    .to_string()
+                    .lines()
+                    .filter(|line| line.contains("jmp"))
+                    .all(|line| line.trim() == "jmp     conclusion"));
+                for inputs in &inputs {
+                    assert_eq!(
+                        emulate(&result, &mut VecDeque::from(inputs.clone())),
+                        emulate(&program, &mut VecDeque::from(inputs.clone()))
+                    );
+                }
+            }
+        }
+
+        let options = PassManagerOptions {
+            optimize: PassFilter::All,
+            ..PassManagerOptions::default()
+        };
+        let mut manager =
+            PassManager::with_default_pipeline(options, ArithmeticSemantics::Trapping);
+        let result = manager
+            .run(Ir::Source(parse_expr("(- (- read) 1)").unwrap()))
+            .unwrap();
+        assert_eq!(result.into_x86("test").unwrap().body[0].label, "main");
+    }
+}
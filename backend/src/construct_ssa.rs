@@ -0,0 +1,484 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{
+    cvar::{Atom, Block as CBlock, Expr, Program as CProgram, Stmt},
+    ssa::{Block, Phi, Program},
+};
+
+/// The control-flow graph of the blocks that are reachable from the entry, which are numbered in
+/// reverse postorder, so that the entry is 0.
+struct Cfg<'p> {
+    blocks: Vec<&'p CBlock>,
+    predecessors: Vec<Vec<usize>>,
+    successors: Vec<Vec<usize>>,
+}
+
+impl<'p> Cfg<'p> {
+    fn new(program: &'p CProgram) -> Self {
+        let by_label: HashMap<&str, &CBlock> = program
+            .blocks
+            .iter()
+            .map(|block| (block.label.as_str(), block))
+            .collect();
+
+        let mut postorder = Vec::new();
+        let mut visited = HashSet::from([program.blocks[0].label.as_str()]);
+        // The blocks being visited, with the index of their next successor.
+        let mut stack = vec![(&program.blocks[0], 0)];
+        while let Some(&(block, next)) = stack.last() {
+            match block.successors().get(next) {
+                Some(successor) => {
+                    stack.last_mut().unwrap().1 += 1;
+                    if let Some(successor) = by_label.get(successor) {
+                        if visited.insert(&successor.label) {
+                            stack.push((successor, 0));
+                        }
+                    }
+                }
+                None => {
+                    postorder.push(block);
+                    stack.pop();
+                }
+            }
+        }
+        postorder.reverse();
+        let blocks = postorder;
+
+        let index: HashMap<&str, usize> = blocks
+            .iter()
+            .enumerate()
+            .map(|(index, block)| (block.label.as_str(), index))
+            .collect();
+        let successors: Vec<Vec<usize>> = blocks
+            .iter()
+            .map(|block| {
+                block
+                    .successors()
+                    .into_iter()
+                    .filter_map(|label| index.get(label).copied())
+                    .collect()
+            })
+            .collect();
+        let mut predecessors = vec![Vec::new(); blocks.len()];
+        for (block, successors) in successors.iter().enumerate() {
+            for &successor in successors {
+                predecessors[successor].push(block);
+            }
+        }
+
+        Self {
+            blocks,
+            predecessors,
+            successors,
+        }
+    }
+
+    /// Computes the immediate dominator of every block with the algorithm of Cooper, Harvey and
+    /// Kennedy. The entry is its own immediate dominator.
+    fn immediate_dominators(&self) -> Vec<usize> {
+        let mut idom: Vec<Option<usize>> = vec![None; self.blocks.len()];
+        idom[0] = Some(0);
+
+        let intersect = |idom: &[Option<usize>], mut lhs: usize, mut rhs: usize| {
+            while lhs != rhs {
+                while lhs > rhs {
+                    lhs = idom[lhs].unwrap();
+                }
+                while rhs > lhs {
+                    rhs = idom[rhs].unwrap();
+                }
+            }
+            lhs
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in 1..self.blocks.len() {
+                let new_idom = self.predecessors[block]
+                    .iter()
+                    .filter(|&&predecessor| idom[predecessor].is_some())
+                    .fold(None, |result, &predecessor| match result {
+                        None => Some(predecessor),
+                        Some(other) => Some(intersect(&idom, predecessor, other)),
+                    });
+                if new_idom != idom[block] {
+                    idom[block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        idom.into_iter().map(Option::unwrap).collect()
+    }
+
+    fn dominance_frontiers(&self, idom: &[usize]) -> Vec<HashSet<usize>> {
+        let mut frontiers = vec![HashSet::new(); self.blocks.len()];
+        for block in 0..self.blocks.len() {
+            let predecessors = &self.predecessors[block];
+            // The entry is also entered at the start of the program, so it is a join as soon as
+            // it has a predecessor.
+            if block == 0 {
+                for &predecessor in predecessors {
+                    let mut runner = predecessor;
+                    loop {
+                        frontiers[runner].insert(block);
+                        if runner == 0 {
+                            break;
+                        }
+                        runner = idom[runner];
+                    }
+                }
+            } else if predecessors.len() >= 2 {
+                for &predecessor in predecessors {
+                    let mut runner = predecessor;
+                    while runner != idom[block] {
+                        frontiers[runner].insert(block);
+                        runner = idom[runner];
+                    }
+                }
+            }
+        }
+        frontiers
+    }
+
+    /// Computes the variables that are live at the start of every block.
+    fn live_in(&self) -> Vec<HashSet<&'p str>> {
+        let mut live_in = vec![HashSet::new(); self.blocks.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in (0..self.blocks.len()).rev() {
+                let mut live: HashSet<&str> = self.successors[block]
+                    .iter()
+                    .flat_map(|&successor| live_in[successor].iter().copied())
+                    .collect();
+                for stmt in self.blocks[block].body.iter().rev() {
+                    match stmt {
+                        Stmt::Assign { lhs, rhs } => {
+                            live.remove(lhs.as_str());
+                            live.extend(rhs.variables());
+                        }
+                        Stmt::Return(expr) => live.extend(expr.variables()),
+                        Stmt::Goto(_) => (),
+                    }
+                }
+                if live != live_in[block] {
+                    live_in[block] = live;
+                    changed = true;
+                }
+            }
+        }
+        live_in
+    }
+}
+
+struct ConstructSsaImpl<'p> {
+    cfg: Cfg<'p>,
+    // The children of every block in the dominator tree.
+    dominated: Vec<Vec<usize>>,
+    // The phi nodes of every block, with the variable of the program they define a version of.
+    phis: Vec<Vec<(String, Phi)>>,
+    bodies: Vec<Vec<Stmt>>,
+    // The current version of every variable, while the dominator tree is visited.
+    versions: HashMap<String, Vec<String>>,
+    version_counts: HashMap<String, usize>,
+    locals: Vec<String>,
+}
+
+impl<'p> ConstructSsaImpl<'p> {
+    fn new(program: &'p CProgram) -> Self {
+        let cfg = Cfg::new(program);
+        let idom = cfg.immediate_dominators();
+        let mut dominated = vec![Vec::new(); cfg.blocks.len()];
+        for block in 1..cfg.blocks.len() {
+            dominated[idom[block]].push(block);
+        }
+
+        let mut result = Self {
+            phis: vec![Vec::new(); cfg.blocks.len()],
+            bodies: cfg.blocks.iter().map(|block| block.body.clone()).collect(),
+            dominated,
+            versions: HashMap::new(),
+            version_counts: HashMap::new(),
+            locals: program.locals.clone(),
+            cfg,
+        };
+        result.place_phis(&idom);
+        result
+    }
+
+    /// Places a phi node for a variable at the iterated dominance frontier of its assignments, if
+    /// the variable is live there.
+    fn place_phis(&mut self, idom: &[usize]) {
+        let frontiers = self.cfg.dominance_frontiers(idom);
+        let live_in = self.cfg.live_in();
+
+        let mut assignments: Vec<(&str, Vec<usize>)> = Vec::new();
+        for (index, block) in self.cfg.blocks.iter().enumerate() {
+            for stmt in &block.body {
+                if let Stmt::Assign { lhs, .. } = stmt {
+                    match assignments.iter_mut().find(|(name, _)| name == lhs) {
+                        Some((_, blocks)) => blocks.push(index),
+                        None => assignments.push((lhs, vec![index])),
+                    }
+                }
+            }
+        }
+
+        for (variable, mut worklist) in assignments {
+            let mut has_phi = HashSet::new();
+            while let Some(block) = worklist.pop() {
+                for &frontier in &frontiers[block] {
+                    if live_in[frontier].contains(variable) && has_phi.insert(frontier) {
+                        self.phis[frontier].push((
+                            variable.to_string(),
+                            Phi {
+                                lhs: variable.to_string(),
+                                arguments: Vec::new(),
+                            },
+                        ));
+                        worklist.push(frontier);
+                    }
+                }
+            }
+        }
+    }
+
+    // The first version of a variable keeps its name, so that programs without joins are not
+    // changed.
+    fn new_version(&mut self, variable: &str) -> String {
+        let name = loop {
+            let count = self.version_counts.entry(variable.to_string()).or_insert(0);
+            *count += 1;
+            let name = match *count {
+                1 => variable.to_string(),
+                count => format!("{}.{}", variable, count - 1),
+            };
+            if *count == 1 || !self.locals.contains(&name) {
+                break name;
+            }
+        };
+
+        if !self.locals.contains(&name) {
+            self.locals.push(name.clone());
+        }
+        self.versions
+            .entry(variable.to_string())
+            .or_default()
+            .push(name.clone());
+        name
+    }
+
+    // A variable that is used before any assignment keeps its name.
+    fn current_version(&self, variable: &str) -> String {
+        self.versions
+            .get(variable)
+            .and_then(|versions| versions.last())
+            .cloned()
+            .unwrap_or_else(|| variable.to_string())
+    }
+
+    fn rename_expr(&self, expr: &mut Expr) {
+        for atom in expr.atoms_mut() {
+            if let Atom::Variable(name) = atom {
+                *name = self.current_version(name);
+            }
+        }
+    }
+
+    /// Renames the variables of `block` and of the blocks it dominates, and fills in the arguments
+    /// of the phi nodes of their successors.
+    fn rename(&mut self, block: usize) {
+        let mut defined = Vec::new();
+
+        let mut phis = std::mem::take(&mut self.phis[block]);
+        for (variable, phi) in &mut phis {
+            phi.lhs = self.new_version(variable);
+            defined.push(variable.clone());
+        }
+        self.phis[block] = phis;
+
+        let mut body = std::mem::take(&mut self.bodies[block]);
+        for stmt in &mut body {
+            match stmt {
+                Stmt::Assign { lhs, rhs } => {
+                    self.rename_expr(rhs);
+                    defined.push(lhs.clone());
+                    *lhs = self.new_version(lhs);
+                }
+                Stmt::Return(expr) => self.rename_expr(expr),
+                Stmt::Goto(_) => (),
+            }
+        }
+        self.bodies[block] = body;
+
+        let label = &self.cfg.blocks[block].label;
+        for &successor in &self.cfg.successors[block] {
+            let mut phis = std::mem::take(&mut self.phis[successor]);
+            for (variable, phi) in &mut phis {
+                phi.arguments.push((
+                    label.clone(),
+                    Atom::Variable(self.current_version(variable)),
+                ));
+            }
+            self.phis[successor] = phis;
+        }
+
+        for child in self.dominated[block].clone() {
+            self.rename(child);
+        }
+
+        for variable in defined {
+            self.versions.get_mut(&variable).unwrap().pop();
+        }
+    }
+}
+
+/// Converts `program` into SSA form, in which every variable is assigned exactly once, and values
+/// that come from different predecessors are merged by phi nodes. Phi nodes are only placed where
+/// the variable is live, and the blocks that are not reachable from the entry are removed.
+pub(crate) fn construct_ssa(program: CProgram) -> Program {
+    let mut construct = ConstructSsaImpl::new(&program);
+    construct.rename(0);
+
+    // The blocks are kept in the order of the program.
+    let mut blocks: HashMap<&str, Block> = construct
+        .cfg
+        .blocks
+        .iter()
+        .zip(construct.phis)
+        .zip(construct.bodies)
+        .map(|((block, phis), body)| {
+            (
+                block.label.as_str(),
+                Block {
+                    label: block.label.clone(),
+                    phis: phis.into_iter().map(|(_, phi)| phi).collect(),
+                    body,
+                },
+            )
+        })
+        .collect();
+
+    Program {
+        locals: construct.locals,
+        blocks: program
+            .blocks
+            .iter()
+            .filter_map(|block| blocks.remove(block.label.as_str()))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use frontend::parse_expr;
+
+    use crate::{
+        explicate_control::explicate_control, remove_complex_operands::remove_complex_operands,
+        uniquify::uniquify_expr,
+    };
+
+    use crate::ir::cvar::BinaryOpKind;
+
+    use super::*;
+
+    fn variable(name: &str) -> Atom {
+        Atom::Variable(name.to_string())
+    }
+
+    // A loop that reads inputs until there are none left, and an unreachable block.
+    fn loop_program() -> CProgram {
+        let mut program = CProgram::new();
+        for name in ["x", "y"] {
+            program.create_local_variable(name.to_string());
+        }
+        program.create_assign("x".to_string(), Expr::Read);
+        program.create_assign("y".to_string(), Atom::Integer(1).into());
+        program.create_goto("loop".to_string());
+        program.create_block("loop".to_string());
+        program.create_assign(
+            "y".to_string(),
+            Expr::BinaryOperation {
+                kind: BinaryOpKind::Add,
+                left_operand: variable("y"),
+                right_operand: variable("x"),
+            },
+        );
+        program.create_goto("next".to_string());
+        program.create_block("next".to_string());
+        program.create_assign("x".to_string(), Expr::Read);
+        program.create_goto("loop".to_string());
+        program.create_block("unused".to_string());
+        program.create_terminator(variable("y").into());
+        program
+    }
+
+    #[test]
+    fn construct_single_block() {
+        let program = explicate_control(remove_complex_operands(
+            uniquify_expr(parse_expr("(let ([x read]) (+ x (- read)))").unwrap()).unwrap(),
+        ));
+        assert_eq!(
+            construct_ssa(program.clone()).to_string(),
+            program.to_string()
+        );
+    }
+
+    #[test]
+    fn construct_loop() {
+        assert_eq!(
+            construct_ssa(loop_program()).to_string().trim(),
+            r#"
+local: [x, y, x.1, y.1, y.2, x.2]
+start:
+    x = read;
+    y = 1;
+    goto loop;
+loop:
+    x.1 = phi(start: x, next: x.2);
+    y.1 = phi(start: y, next: y.2);
+    y.2 = (+ y.1 x.1);
+    goto next;
+next:
+    x.2 = read;
+    goto loop;
+            "#
+            .trim()
+        );
+
+        // A variable that is redefined in the loop, but not used after the join, has no phi.
+        let mut program = loop_program();
+        program.blocks[2].body.insert(
+            0,
+            Stmt::Assign {
+                lhs: "y".to_string(),
+                rhs: Atom::Integer(2).into(),
+            },
+        );
+        program.blocks[1].body[0] = Stmt::Assign {
+            lhs: "y".to_string(),
+            rhs: variable("x").into(),
+        };
+        assert_eq!(
+            construct_ssa(program).to_string().trim(),
+            r#"
+local: [x, y, x.1, y.1, y.2, x.2]
+start:
+    x = read;
+    y = 1;
+    goto loop;
+loop:
+    x.1 = phi(start: x, next: x.2);
+    y.1 = x.1;
+    goto next;
+next:
+    y.2 = 2;
+    x.2 = read;
+    goto loop;
+            "#
+            .trim()
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::{HashMap, HashSet};
+
+use frontend::ArithmeticSemantics;
+
+use crate::ir::cvar::{Atom, BinaryOpKind, Expr, Program, Stmt, UnaryOpKind};
+
+fn substitute_atom(atom: &mut Atom, copies: &HashMap<String, Atom>) {
+    if let Atom::Variable(name) = atom {
+        if let Some(value) = copies.get(name) {
+            *atom = value.clone();
+        }
+    }
+}
+
+fn substitute_expr(expr: &mut Expr, copies: &HashMap<String, Atom>) {
+    for atom in expr.atoms_mut() {
+        substitute_atom(atom, copies);
+    }
+}
+
+struct CopyPropagationImpl {
+    semantics: ArithmeticSemantics,
+}
+
+impl CopyPropagationImpl {
+    /// Replaces the uses of variables that hold a copy of an atom by that atom. Copies are only
+    /// tracked inside a block.
+    fn propagate(&self, program: &mut Program) {
+        for block in &mut program.blocks {
+            // The atom that each variable is known to be equal to.
+            let mut copies: HashMap<String, Atom> = HashMap::new();
+
+            for stmt in &mut block.body {
+                match stmt {
+                    Stmt::Assign { lhs, rhs } => {
+                        substitute_expr(rhs, &copies);
+
+                        // The copies of the previous value of `lhs` are no longer valid.
+                        let previous = Atom::Variable(lhs.clone());
+                        copies.retain(|name, value| name != lhs && *value != previous);
+                        match rhs {
+                            Expr::Atom(atom) if *atom != previous => {
+                                copies.insert(lhs.clone(), atom.clone());
+                            }
+                            _ => (),
+                        }
+                    }
+                    Stmt::Return(expr) => substitute_expr(expr, &copies),
+                    Stmt::Goto(_) => (),
+                }
+            }
+        }
+    }
+
+    // An assignment can only be removed if computing its value has no effect, i.e. it does not
+    // read an input and it cannot overflow.
+    fn has_effect(&self, expr: &Expr) -> bool {
+        if self.semantics == ArithmeticSemantics::Wrapping {
+            return *expr == Expr::Read;
+        }
+
+        match expr {
+            Expr::Atom(_) => false,
+            Expr::Read => true,
+            Expr::UnaryOperation {
+                kind: UnaryOpKind::Minus,
+                operand: Atom::Integer(value),
+            } => self.semantics.neg(*value).is_err(),
+            Expr::BinaryOperation {
+                kind,
+                left_operand: Atom::Integer(lhs),
+                right_operand: Atom::Integer(rhs),
+            } => match kind {
+                BinaryOpKind::Add => self.semantics.add(*lhs, *rhs).is_err(),
+                BinaryOpKind::Sub => self.semantics.sub(*lhs, *rhs).is_err(),
+            },
+            // The value of a variable may overflow.
+            _ => true,
+        }
+    }
+
+    /// Removes the assignments to variables that are never used, until there are none left.
+    fn eliminate_dead_code(&self, program: &mut Program) {
+        loop {
+            let used: HashSet<String> = {
+                let mut used = HashSet::new();
+                for stmt in program.blocks.iter().flat_map(|block| &block.body) {
+                    match stmt {
+                        Stmt::Assign { rhs: expr, .. } | Stmt::Return(expr) => {
+                            used.extend(expr.variables())
+                        }
+                        Stmt::Goto(_) => (),
+                    }
+                }
+                used.into_iter().map(str::to_string).collect()
+            };
+
+            let mut changed = false;
+            for block in &mut program.blocks {
+                block.body.retain(|stmt| match stmt {
+                    Stmt::Assign { lhs, rhs } if !used.contains(lhs) && !self.has_effect(rhs) => {
+                        changed = true;
+                        false
+                    }
+                    _ => true,
+                });
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        let assigned: HashSet<&str> = program
+            .blocks
+            .iter()
+            .flat_map(|block| &block.body)
+            .filter_map(|stmt| match stmt {
+                Stmt::Assign { lhs, .. } => Some(lhs.as_str()),
+                _ => None,
+            })
+            .collect();
+        let locals = std::mem::take(&mut program.locals);
+        program.locals = locals
+            .into_iter()
+            .filter(|name| assigned.contains(name.as_str()))
+            .collect();
+    }
+}
+
+/// Propagates copies and constants into the uses of variables, then removes the assignments that
+/// are no longer needed and their local variables. The behavior of the program under `semantics`
+/// is unchanged.
+pub(crate) fn copy_propagation(mut program: Program, semantics: ArithmeticSemantics) -> Program {
+    let pass = CopyPropagationImpl { semantics };
+    pass.propagate(&mut program);
+    pass.eliminate_dead_code(&mut program);
+    program
+}
+
+#[cfg(test)]
+mod test {
+    use frontend::parse_expr;
+
+    use crate::{
+        explicate_control::explicate_control, remove_complex_operands::remove_complex_operands,
+        uniquify::uniquify_expr,
+    };
+
+    use super::*;
+
+    fn optimize(code: &str, semantics: ArithmeticSemantics) -> String {
+        let program = explicate_control(remove_complex_operands(
+            uniquify_expr(parse_expr(code).unwrap()).unwrap(),
+        ));
+        copy_propagation(program, semantics).to_string()
+    }
+
+    #[test]
+    fn propagate_copies() {
+        assert_eq!(
+            optimize(
+                "(let ([y (let ([x1 20]) x1)]) y)",
+                ArithmeticSemantics::Checked
+            )
+            .trim(),
+            r#"
+start:
+    return 20;
+            "#
+            .trim()
+        );
+
+        assert_eq!(
+            optimize(
+                "(let ([x read]) (let ([y x]) (let ([z (+ y 1)]) (- z y))))",
+                ArithmeticSemantics::Checked
+            )
+            .trim(),
+            r#"
+local: [x0, x2]
+start:
+    x0 = read;
+    x2 = (+ x0 1);
+    return (- x2 x0);
+            "#
+            .trim()
+        );
+
+        // Unused inputs are still read.
+        assert_eq!(
+            optimize(
+                "(let ([x read]) (let ([y read]) y))",
+                ArithmeticSemantics::Checked
+            )
+            .trim(),
+            r#"
+local: [x0, x1]
+start:
+    x0 = read;
+    x1 = read;
+    return x1;
+            "#
+            .trim()
+        );
+    }
+
+    #[test]
+    fn keep_overflows() {
+        let code = "(let ([x read]) (let ([y (+ x 1)]) (let ([z (- 9223372036854775807 -1)]) x)))";
+
+        for semantics in [ArithmeticSemantics::Checked, ArithmeticSemantics::Trapping] {
+            assert_eq!(
+                optimize(code, semantics).trim(),
+                r#"
+local: [x0, x1, tmp0, x2]
+start:
+    x0 = read;
+    x1 = (+ x0 1);
+    tmp0 = (- 1);
+    x2 = (- 9223372036854775807 tmp0);
+    return x0;
+                "#
+                .trim()
+            );
+        }
+
+        assert_eq!(
+            optimize(code, ArithmeticSemantics::Wrapping).trim(),
+            r#"
+local: [x0]
+start:
+    x0 = read;
+    return x0;
+            "#
+            .trim()
+        );
+    }
+
+    #[test]
+    fn propagate_across_assignments() {
+        let mut program = Program::new();
+        for name in ["x", "y"] {
+            program.create_local_variable(name.to_string());
+        }
+        program.create_assign("x".to_string(), Expr::Read);
+        program.create_assign("y".to_string(), Atom::Variable("x".to_string()).into());
+        program.create_assign("x".to_string(), Expr::Read);
+        program.create_goto("next".to_string());
+        program.create_block("next".to_string());
+        program.create_terminator(Expr::BinaryOperation {
+            kind: BinaryOpKind::Sub,
+            left_operand: Atom::Variable("x".to_string()),
+            right_operand: Atom::Variable("y".to_string()),
+        });
+
+        // `y` is not replaced after `x` changes, nor in another block.
+        assert_eq!(
+            copy_propagation(program.clone(), ArithmeticSemantics::Checked),
+            program
+        );
+    }
+}
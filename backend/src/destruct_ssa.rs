@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use crate::{
+    ir::{
+        cvar::{Atom, Block as CBlock, Program as CProgram, Stmt},
+        ssa::Program,
+    },
+    NameGenerator,
+};
+
+/// Orders the parallel copies `copies`, which all read their source before any destination is
+/// written, as a sequence of assignments. A cycle of copies is broken with a temporary variable.
+fn sequentialize(
+    mut copies: Vec<(String, Atom)>,
+    mut new_temporary: impl FnMut() -> String,
+) -> Vec<Stmt> {
+    copies.retain(|(lhs, rhs)| *rhs != Atom::Variable(lhs.clone()));
+
+    let mut result = Vec::new();
+    while !copies.is_empty() {
+        // A copy whose destination is not read by another copy can be done first.
+        let ready = copies.iter().position(|(lhs, _)| {
+            copies
+                .iter()
+                .all(|(_, rhs)| *rhs != Atom::Variable(lhs.clone()))
+        });
+
+        match ready {
+            Some(index) => {
+                let (lhs, rhs) = copies.remove(index);
+                result.push(Stmt::Assign {
+                    lhs,
+                    rhs: rhs.into(),
+                });
+            }
+            None => {
+                // Every destination is read by another copy, so they form cycles. The value of
+                // one destination is saved, so that it can be written.
+                let saved = Atom::Variable(copies[0].0.clone());
+                let temporary = new_temporary();
+                result.push(Stmt::Assign {
+                    lhs: temporary.clone(),
+                    rhs: saved.clone().into(),
+                });
+                for (_, rhs) in &mut copies {
+                    if *rhs == saved {
+                        *rhs = Atom::Variable(temporary.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// Converts an SSA program back into C_var, by replacing the phi nodes of a block with copies at
+/// the end of its predecessors.
+pub(crate) fn destruct_ssa(program: Program) -> CProgram {
+    let mut locals = program.locals;
+    let mut names = NameGenerator::new("phi.tmp".to_string());
+    let mut new_temporary = || loop {
+        let name = names.generate();
+        if !locals.contains(&name) {
+            locals.push(name.clone());
+            break name;
+        }
+    };
+
+    // The copies to add at the end of every block.
+    let mut copies: HashMap<String, Vec<(String, Atom)>> = HashMap::new();
+    for block in &program.blocks {
+        for phi in &block.phis {
+            for (predecessor, atom) in &phi.arguments {
+                copies
+                    .entry(predecessor.clone())
+                    .or_default()
+                    .push((phi.lhs.clone(), atom.clone()));
+            }
+        }
+    }
+
+    let mut blocks = Vec::new();
+    for block in program.blocks {
+        let mut body = block.body;
+        if let Some(copies) = copies.remove(&block.label) {
+            // The copies are only correct on the edge to the block with the phi nodes if it is the
+            // only successor, which is always the case as long as C_var has no conditional jumps.
+            debug_assert!(matches!(body.last(), Some(Stmt::Goto(_))));
+            let terminator = body.pop().unwrap();
+            body.extend(sequentialize(copies, &mut new_temporary));
+            body.push(terminator);
+        }
+        blocks.push(CBlock {
+            label: block.label,
+            body,
+        });
+    }
+
+    CProgram { locals, blocks }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use frontend::InterpreterError;
+
+    use crate::{
+        construct_ssa::construct_ssa,
+        interp::cvar::{interp_cvar, CVarInterpError},
+        ir::{
+            cvar::{BinaryOpKind, Expr},
+            ssa::{Block, Phi},
+        },
+    };
+
+    use super::*;
+
+    fn variable(name: &str) -> Atom {
+        Atom::Variable(name.to_string())
+    }
+
+    fn copies(copies: &[(&str, Atom)]) -> String {
+        let mut names = NameGenerator::new("tmp".to_string());
+        let copies = copies
+            .iter()
+            .map(|(lhs, rhs)| (lhs.to_string(), rhs.clone()))
+            .collect();
+        sequentialize(copies, || names.generate())
+            .iter()
+            .map(Stmt::to_string)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn sequentialize_copies() {
+        assert_eq!(
+            copies(&[
+                ("a", variable("b")),
+                ("b", variable("c")),
+                ("c", Atom::Integer(1))
+            ]),
+            "a = b; b = c; c = 1;"
+        );
+        assert_eq!(
+            copies(&[
+                ("a", variable("b")),
+                ("b", variable("a")),
+                ("c", variable("a"))
+            ]),
+            "c = a; tmp0 = a; a = b; b = tmp0;"
+        );
+        assert_eq!(
+            copies(&[
+                ("a", variable("a")),
+                ("b", variable("c")),
+                ("c", variable("b"))
+            ]),
+            "tmp0 = b; b = c; c = tmp0;"
+        );
+    }
+
+    #[test]
+    fn destruct_phis() {
+        // Swaps `x` and `y` on every iteration, until the input runs out.
+        let program = Program {
+            locals: vec!["x".to_string(), "y".to_string()],
+            blocks: vec![
+                Block {
+                    label: "start".to_string(),
+                    phis: vec![],
+                    body: vec![
+                        Stmt::Assign {
+                            lhs: "x".to_string(),
+                            rhs: Expr::Read,
+                        },
+                        Stmt::Assign {
+                            lhs: "y".to_string(),
+                            rhs: Expr::Read,
+                        },
+                        Stmt::Goto("loop".to_string()),
+                    ],
+                },
+                Block {
+                    label: "loop".to_string(),
+                    phis: vec![
+                        Phi {
+                            lhs: "x".to_string(),
+                            arguments: vec![
+                                ("start".to_string(), variable("x")),
+                                ("loop".to_string(), variable("y")),
+                            ],
+                        },
+                        Phi {
+                            lhs: "y".to_string(),
+                            arguments: vec![
+                                ("start".to_string(), variable("y")),
+                                ("loop".to_string(), variable("x")),
+                            ],
+                        },
+                    ],
+                    body: vec![Stmt::Goto("loop".to_string())],
+                },
+            ],
+        };
+
+        assert_eq!(
+            destruct_ssa(program).to_string().trim(),
+            r#"
+local: [x, y, phi.tmp0]
+start:
+    x = read;
+    y = read;
+    goto loop;
+loop:
+    phi.tmp0 = x;
+    x = y;
+    y = phi.tmp0;
+    goto loop;
+            "#
+            .trim()
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        // Adds the inputs until they run out.
+        let mut program = CProgram::new();
+        for name in ["x", "sum"] {
+            program.create_local_variable(name.to_string());
+        }
+        program.create_assign("sum".to_string(), Atom::Integer(0).into());
+        program.create_goto("loop".to_string());
+        program.create_block("loop".to_string());
+        program.create_assign("x".to_string(), Expr::Read);
+        program.create_assign(
+            "sum".to_string(),
+            Expr::BinaryOperation {
+                kind: BinaryOpKind::Add,
+                left_operand: variable("sum"),
+                right_operand: variable("x"),
+            },
+        );
+        program.create_goto("loop".to_string());
+
+        let destructed = destruct_ssa(construct_ssa(program.clone()));
+        assert_eq!(
+            destructed.to_string().trim(),
+            r#"
+local: [x, sum, sum.1, sum.2]
+start:
+    sum = 0;
+    sum.1 = sum;
+    goto loop;
+loop:
+    x = read;
+    sum.2 = (+ sum.1 x);
+    sum.1 = sum.2;
+    goto loop;
+            "#
+            .trim()
+        );
+
+        for result in [&program, &destructed]
+            .map(|program| interp_cvar(program, &mut VecDeque::from(vec![1, 2, 3])))
+        {
+            assert_eq!(
+                result,
+                Err(CVarInterpError::Frontend(InterpreterError::EndOfInput {
+                    consumed: 3
+                }))
+            );
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+
+use frontend::{ArithmeticSemantics, Evaluation, InputSource, InterpreterError};
+
+use crate::ir::cvar::{Atom, BinaryOpKind, Block, Expr, Program, Stmt, UnaryOpKind};
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum CVarInterpError {
+    // An error that `frontend::interp_expr` reports as well, e.g. an arithmetic overflow.
+    Frontend(InterpreterError),
+    // The variable is not in the `locals` of the program.
+    UndeclaredVariable(String),
+    UninitializedVariable(String),
+    UnknownLabel(String),
+    // The program has no blocks, so it has no entry.
+    MissingEntry,
+    // The block ends without a `return` or a `goto`.
+    MissingTerminator(String),
+}
+
+impl From<InterpreterError> for CVarInterpError {
+    fn from(value: InterpreterError) -> Self {
+        CVarInterpError::Frontend(value)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CVarInterpOptions {
+    // The maximum number of statements to execute, or `None` for no limit.
+    pub fuel: Option<u64>,
+    pub semantics: ArithmeticSemantics,
+}
+
+struct CVarInterpreter<'p, 'i> {
+    program: &'p Program,
+    // Map every declared variable to its value, or `None` if it has not been assigned yet.
+    variables: HashMap<&'p str, Option<i64>>,
+    input: &'i mut dyn InputSource,
+    inputs_consumed: usize,
+    fuel: Option<u64>,
+    semantics: ArithmeticSemantics,
+}
+
+impl<'p, 'i> CVarInterpreter<'p, 'i> {
+    fn new(
+        program: &'p Program,
+        input: &'i mut dyn InputSource,
+        options: CVarInterpOptions,
+    ) -> Self {
+        Self {
+            program,
+            variables: program
+                .locals
+                .iter()
+                .map(|name| (name.as_str(), None))
+                .collect(),
+            input,
+            inputs_consumed: 0,
+            fuel: options.fuel,
+            semantics: options.semantics,
+        }
+    }
+
+    fn read_input(&mut self) -> Result<i64, CVarInterpError> {
+        match self.input.read_int()? {
+            Some(value) => {
+                self.inputs_consumed += 1;
+                Ok(value)
+            }
+            None => Err(InterpreterError::EndOfInput {
+                consumed: self.inputs_consumed,
+            }
+            .into()),
+        }
+    }
+
+    fn evaluate_atom(&self, atom: &Atom) -> Result<i64, CVarInterpError> {
+        match atom {
+            Atom::Integer(val) => Ok(*val),
+            Atom::Variable(name) => match self.variables.get(name.as_str()) {
+                Some(Some(value)) => Ok(*value),
+                Some(None) => Err(CVarInterpError::UninitializedVariable(name.clone())),
+                None => Err(CVarInterpError::UndeclaredVariable(name.clone())),
+            },
+        }
+    }
+
+    fn evaluate_expr(&mut self, expr: &Expr) -> Result<i64, CVarInterpError> {
+        let result = match expr {
+            Expr::Atom(atom) => self.evaluate_atom(atom)?,
+
+            Expr::Read => self.read_input()?,
+
+            Expr::UnaryOperation {
+                kind: UnaryOpKind::Minus,
+                operand,
+            } => self
+                .semantics
+                .neg(self.evaluate_atom(operand)?)
+                .map_err(InterpreterError::from)?,
+
+            Expr::BinaryOperation {
+                kind,
+                left_operand,
+                right_operand,
+            } => {
+                let lhs = self.evaluate_atom(left_operand)?;
+                let rhs = self.evaluate_atom(right_operand)?;
+                match kind {
+                    BinaryOpKind::Add => self.semantics.add(lhs, rhs),
+                    BinaryOpKind::Sub => self.semantics.sub(lhs, rhs),
+                }
+                .map_err(InterpreterError::from)?
+            }
+        };
+
+        Ok(result)
+    }
+
+    fn find_block(&self, label: &str) -> Result<&'p Block, CVarInterpError> {
+        self.program
+            .find_block(label)
+            .ok_or_else(|| CVarInterpError::UnknownLabel(label.to_string()))
+    }
+
+    fn run(&mut self) -> Result<i64, CVarInterpError> {
+        let mut block = self.program.entry().ok_or(CVarInterpError::MissingEntry)?;
+
+        'next_block: loop {
+            for stmt in &block.body {
+                if let Some(fuel) = &mut self.fuel {
+                    if *fuel == 0 {
+                        return Err(InterpreterError::StepLimitExceeded.into());
+                    }
+                    *fuel -= 1;
+                }
+
+                match stmt {
+                    Stmt::Assign { lhs, rhs } => {
+                        let value = self.evaluate_expr(rhs)?;
+                        match self.variables.get_mut(lhs.as_str()) {
+                            Some(slot) => *slot = Some(value),
+                            None => return Err(CVarInterpError::UndeclaredVariable(lhs.clone())),
+                        }
+                    }
+
+                    Stmt::Return(expr) => return self.evaluate_expr(expr),
+
+                    Stmt::Goto(label) => {
+                        block = self.find_block(label)?;
+                        continue 'next_block;
+                    }
+                }
+            }
+
+            return Err(CVarInterpError::MissingTerminator(block.label.clone()));
+        }
+    }
+}
+
+/// Executes a C_var program from its entry block, with the same semantics as
+/// `frontend::interp_expr`.
+pub fn interp_cvar(
+    program: &Program,
+    input: &mut dyn InputSource,
+) -> Result<Evaluation, CVarInterpError> {
+    interp_cvar_with_semantics(program, input, ArithmeticSemantics::default())
+}
+
+pub fn interp_cvar_with_semantics(
+    program: &Program,
+    input: &mut dyn InputSource,
+    semantics: ArithmeticSemantics,
+) -> Result<Evaluation, CVarInterpError> {
+    let options = CVarInterpOptions {
+        semantics,
+        ..CVarInterpOptions::default()
+    };
+    interp_cvar_with_options(program, input, options)
+}
+
+pub fn interp_cvar_with_options(
+    program: &Program,
+    input: &mut dyn InputSource,
+    options: CVarInterpOptions,
+) -> Result<Evaluation, CVarInterpError> {
+    let mut interpreter = CVarInterpreter::new(program, input, options);
+    let value = interpreter.run()?;
+    Ok(Evaluation {
+        value,
+        inputs_consumed: interpreter.inputs_consumed,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use frontend::{interp_expr, parse_expr, OverflowKind};
+
+    use crate::{
+        explicate_control::explicate_control, remove_complex_operands::remove_complex_operands,
+        uniquify::uniquify_expr,
+    };
+
+    use super::*;
+
+    fn prepare_program(code: &str) -> Program {
+        explicate_control(remove_complex_operands(
+            uniquify_expr(parse_expr(code).unwrap()).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn interp_cvar_test() {
+        for (code, inputs) in [
+            ("42", vec![]),
+            ("(- 10 1 2 3)", vec![]),
+            ("(let ([x read] [y read]) (- x y))", vec![10, 3]),
+            (
+                "(let ([y (let ([x1 (- 20)]) (let ([x2 22]) (+ x1 x2)))]) y)",
+                vec![],
+            ),
+            ("(+ (let ([x read]) (- x)) (- read read))", vec![1, 2, 3]),
+        ] {
+            let expr = parse_expr(code).unwrap();
+            assert_eq!(
+                interp_cvar(&prepare_program(code), &mut VecDeque::from(inputs.clone())),
+                Ok(interp_expr(&expr, &mut VecDeque::from(inputs)).unwrap()),
+            );
+        }
+    }
+
+    #[test]
+    fn interp_cvar_blocks() {
+        let mut program = Program::new();
+        program.create_local_variable("x".to_string());
+        program.create_assign("x".to_string(), Expr::Read);
+        program.create_goto("second".to_string());
+        program.create_block("third".to_string());
+        program.create_terminator(Expr::UnaryOperation {
+            kind: UnaryOpKind::Minus,
+            operand: Atom::Variable("x".to_string()),
+        });
+        program.create_block("second".to_string());
+        program.create_assign(
+            "x".to_string(),
+            Expr::BinaryOperation {
+                kind: BinaryOpKind::Add,
+                left_operand: Atom::Variable("x".to_string()),
+                right_operand: Atom::Integer(1),
+            },
+        );
+        program.create_goto("third".to_string());
+
+        assert_eq!(
+            interp_cvar(&program, &mut VecDeque::from([41])),
+            Ok(Evaluation {
+                value: -42,
+                inputs_consumed: 1
+            })
+        );
+
+        // The program starts at the first block, whatever its label.
+        program.blocks[0].label = "begin".to_string();
+        assert_eq!(
+            interp_cvar(&program, &mut VecDeque::from([41])).map(|evaluation| evaluation.value),
+            Ok(-42)
+        );
+    }
+
+    #[test]
+    fn interp_cvar_error() {
+        assert_eq!(
+            interp_cvar(
+                &prepare_program("(- (- 9223372036854775807) 2)"),
+                &mut VecDeque::new()
+            ),
+            Err(CVarInterpError::Frontend(
+                InterpreterError::ArithmeticOverflow(OverflowKind::SubOverflow(
+                    -9223372036854775807,
+                    2
+                ))
+            ))
+        );
+
+        assert_eq!(
+            interp_cvar(&prepare_program("(+ read read)"), &mut VecDeque::from([1])),
+            Err(CVarInterpError::Frontend(InterpreterError::EndOfInput {
+                consumed: 1
+            }))
+        );
+
+        let mut program = Program::new();
+        program.create_assign("x".to_string(), Atom::Integer(1).into());
+        program.create_terminator(Atom::Variable("x".to_string()).into());
+        assert_eq!(
+            interp_cvar(&program, &mut VecDeque::new()),
+            Err(CVarInterpError::UndeclaredVariable("x".to_string()))
+        );
+
+        let mut program = Program::new();
+        program.create_local_variable("x".to_string());
+        program.create_terminator(Atom::Variable("x".to_string()).into());
+        assert_eq!(
+            interp_cvar(&program, &mut VecDeque::new()),
+            Err(CVarInterpError::UninitializedVariable("x".to_string()))
+        );
+
+        let mut program = Program::new();
+        program.create_goto("nowhere".to_string());
+        assert_eq!(
+            interp_cvar(&program, &mut VecDeque::new()),
+            Err(CVarInterpError::UnknownLabel("nowhere".to_string()))
+        );
+
+        let mut program = Program::new();
+        program.create_block("end".to_string());
+        assert_eq!(
+            interp_cvar(&program, &mut VecDeque::new()),
+            Err(CVarInterpError::MissingTerminator("start".to_string()))
+        );
+
+        let mut program = Program::new();
+        program.blocks.clear();
+        assert_eq!(
+            interp_cvar(&program, &mut VecDeque::new()),
+            Err(CVarInterpError::MissingEntry)
+        );
+
+        // A loop that never reads its input only stops when it runs out of fuel.
+        let mut program = Program::new();
+        program.create_goto("start".to_string());
+        let options = CVarInterpOptions {
+            fuel: Some(100),
+            ..CVarInterpOptions::default()
+        };
+        assert_eq!(
+            interp_cvar_with_options(&program, &mut VecDeque::new(), options),
+            Err(InterpreterError::StepLimitExceeded.into())
+        );
+    }
+}
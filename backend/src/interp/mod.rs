@@ -0,0 +1,2 @@
+pub mod cvar;
+pub mod x86;
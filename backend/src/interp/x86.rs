@@ -0,0 +1,674 @@
+use std::collections::HashMap;
+
+use frontend::{Evaluation, InputSource, InterpreterError};
+
+use crate::ir::x86::{Reg, VarArg, VarBlock, VarInstr, VarProgram};
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum EmulatorError {
+    // An error that `frontend::interp_expr` reports as well, e.g. the input is exhausted.
+    Frontend(InterpreterError),
+    UninitializedRegister(Reg),
+    UninitializedVariable(String),
+    // The memory referred to by the operand has never been written.
+    UninitializedMemory(VarArg),
+    // The address referred to by the operand is not a multiple of 8.
+    MisalignedAccess(VarArg),
+    // The operand cannot be written, e.g. an immediate.
+    InvalidDestination(VarArg),
+    // `popq` is executed when the stack is empty.
+    StackUnderflow,
+    // The program ends with a stack pointer that differs from the initial one by `offset` bytes.
+    UnbalancedStack { offset: i64 },
+    UnknownLabel(String),
+    UnknownFunction(String),
+    // `jo` is executed when the overflow flag is undefined, e.g. after a `callq`.
+    UndefinedOverflowFlag,
+    // The program calls `exit`, e.g. in an overflow handler.
+    Exit { code: i64 },
+}
+
+impl From<InterpreterError> for EmulatorError {
+    fn from(value: InterpreterError) -> Self {
+        EmulatorError::Frontend(value)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct EmulatorOptions {
+    // The maximum number of instructions to execute, or `None` for no limit.
+    pub fuel: Option<u64>,
+}
+
+enum BlockExit<'p> {
+    Jump(&'p str),
+    FallThrough,
+    Return,
+}
+
+struct Emulator<'p, 'i> {
+    program: &'p VarProgram,
+    registers: HashMap<Reg, i64>,
+    variables: HashMap<&'p str, i64>,
+    // Map the address of each 8-byte memory cell to its value.
+    memory: HashMap<i64, i64>,
+    // Whether the last arithmetic instruction overflowed, or `None` if the flag is undefined.
+    overflow: Option<bool>,
+    input: &'i mut dyn InputSource,
+    inputs_consumed: usize,
+    fuel: Option<u64>,
+}
+
+impl<'p, 'i> Emulator<'p, 'i> {
+    // The value of %rsp when the program starts. The addresses below it are used as the stack.
+    const STACK_BASE: i64 = 0x7fff_0000;
+
+    fn read_int_func_name() -> &'static str {
+        "read_int"
+    }
+
+    fn exit_func_name() -> &'static str {
+        "exit"
+    }
+
+    fn new(
+        program: &'p VarProgram,
+        input: &'i mut dyn InputSource,
+        options: EmulatorOptions,
+    ) -> Self {
+        let mut emulator = Self {
+            program,
+            registers: HashMap::new(),
+            variables: HashMap::new(),
+            memory: HashMap::new(),
+            overflow: None,
+            input,
+            inputs_consumed: 0,
+            fuel: options.fuel,
+        };
+
+        // The program starts with the stack pointer and the callee-saved registers set up by its
+        // caller. The values of the other registers are unspecified.
+        emulator.registers.insert(Reg::RSP, Self::STACK_BASE);
+        emulator.registers.insert(Reg::RBP, Self::STACK_BASE);
+        for reg in Self::callee_saved_regs() {
+            emulator.registers.insert(reg, 0);
+        }
+
+        emulator
+    }
+
+    fn callee_saved_regs() -> [Reg; 5] {
+        [Reg::RBX, Reg::R12, Reg::R13, Reg::R14, Reg::R15]
+    }
+
+    fn caller_saved_regs() -> [Reg; 9] {
+        use Reg::*;
+        [RAX, RCX, RDX, RSI, RDI, R8, R9, R10, R11]
+    }
+
+    fn read_reg(&self, reg: Reg) -> Result<i64, EmulatorError> {
+        self.registers
+            .get(&reg)
+            .copied()
+            .ok_or(EmulatorError::UninitializedRegister(reg))
+    }
+
+    fn address(&self, arg: &VarArg, reg: Reg, offset: i64) -> Result<i64, EmulatorError> {
+        let address = self.read_reg(reg)?.wrapping_add(offset);
+        if address % 8 == 0 {
+            Ok(address)
+        } else {
+            Err(EmulatorError::MisalignedAccess(arg.clone()))
+        }
+    }
+
+    fn read_arg(&self, arg: &VarArg) -> Result<i64, EmulatorError> {
+        match arg {
+            VarArg::Imm(value) => Ok(*value),
+            VarArg::Reg(reg) => self.read_reg(*reg),
+            VarArg::Deref(reg, offset) => self
+                .memory
+                .get(&self.address(arg, *reg, *offset)?)
+                .copied()
+                .ok_or_else(|| EmulatorError::UninitializedMemory(arg.clone())),
+            VarArg::Variable(name) => self
+                .variables
+                .get(name.as_str())
+                .copied()
+                .ok_or_else(|| EmulatorError::UninitializedVariable(name.clone())),
+        }
+    }
+
+    fn write_arg(&mut self, arg: &'p VarArg, value: i64) -> Result<(), EmulatorError> {
+        match arg {
+            VarArg::Imm(_) => return Err(EmulatorError::InvalidDestination(arg.clone())),
+            VarArg::Reg(reg) => {
+                self.registers.insert(*reg, value);
+            }
+            VarArg::Deref(reg, offset) => {
+                let address = self.address(arg, *reg, *offset)?;
+                self.memory.insert(address, value);
+            }
+            VarArg::Variable(name) => {
+                self.variables.insert(name, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, value: i64) -> Result<(), EmulatorError> {
+        let rsp = self.read_reg(Reg::RSP)?.wrapping_sub(8);
+        self.registers.insert(Reg::RSP, rsp);
+        self.memory.insert(rsp, value);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<i64, EmulatorError> {
+        let rsp = self.read_reg(Reg::RSP)?;
+        if rsp >= Self::STACK_BASE {
+            return Err(EmulatorError::StackUnderflow);
+        }
+        let value = self
+            .memory
+            .get(&rsp)
+            .copied()
+            .ok_or(EmulatorError::UninitializedMemory(VarArg::Deref(
+                Reg::RSP,
+                0,
+            )))?;
+        self.registers.insert(Reg::RSP, rsp + 8);
+        Ok(value)
+    }
+
+    fn call(&mut self, callee: &str) -> Result<(), EmulatorError> {
+        if callee == Self::exit_func_name() {
+            return Err(EmulatorError::Exit {
+                code: self.read_reg(Reg::RDI)?,
+            });
+        }
+        if callee != Self::read_int_func_name() {
+            return Err(EmulatorError::UnknownFunction(callee.to_string()));
+        }
+
+        let value = match self.input.read_int()? {
+            Some(value) => value,
+            None => {
+                return Err(InterpreterError::EndOfInput {
+                    consumed: self.inputs_consumed,
+                }
+                .into())
+            }
+        };
+        self.inputs_consumed += 1;
+
+        // The callee may clobber all caller-saved registers and the flags.
+        for reg in Self::caller_saved_regs() {
+            self.registers.remove(&reg);
+        }
+        self.overflow = None;
+        self.registers.insert(Reg::RAX, value);
+        Ok(())
+    }
+
+    fn find_block(&self, label: &str) -> Result<usize, EmulatorError> {
+        self.program
+            .body
+            .iter()
+            .position(|block| block.label == label)
+            .ok_or_else(|| EmulatorError::UnknownLabel(label.to_string()))
+    }
+
+    /// Executes a block, and returns where the execution continues.
+    fn run_block(&mut self, block: &'p VarBlock) -> Result<BlockExit<'p>, EmulatorError> {
+        for instr in &block.instructions {
+            if let Some(fuel) = &mut self.fuel {
+                if *fuel == 0 {
+                    return Err(InterpreterError::StepLimitExceeded.into());
+                }
+                *fuel -= 1;
+            }
+
+            match instr {
+                VarInstr::Addq { lhs, rhs } => {
+                    let (value, overflow) =
+                        self.read_arg(lhs)?.overflowing_add(self.read_arg(rhs)?);
+                    self.write_arg(lhs, value)?;
+                    self.overflow = Some(overflow);
+                }
+
+                VarInstr::Subq { lhs, rhs } => {
+                    let (value, overflow) =
+                        self.read_arg(lhs)?.overflowing_sub(self.read_arg(rhs)?);
+                    self.write_arg(lhs, value)?;
+                    self.overflow = Some(overflow);
+                }
+
+                VarInstr::Negq { operand } => {
+                    let (value, overflow) = self.read_arg(operand)?.overflowing_neg();
+                    self.write_arg(operand, value)?;
+                    self.overflow = Some(overflow);
+                }
+
+                VarInstr::Incq { operand } => {
+                    let (value, overflow) = self.read_arg(operand)?.overflowing_add(1);
+                    self.write_arg(operand, value)?;
+                    self.overflow = Some(overflow);
+                }
+
+                VarInstr::Decq { operand } => {
+                    let (value, overflow) = self.read_arg(operand)?.overflowing_sub(1);
+                    self.write_arg(operand, value)?;
+                    self.overflow = Some(overflow);
+                }
+
+                VarInstr::Xorq { lhs, rhs } => {
+                    // `xorq r, r` clears `r` without depending on its value.
+                    let value = if lhs == rhs {
+                        0
+                    } else {
+                        self.read_arg(lhs)? ^ self.read_arg(rhs)?
+                    };
+                    self.write_arg(lhs, value)?;
+                    self.overflow = Some(false);
+                }
+
+                VarInstr::Movq { from, to } => {
+                    let value = self.read_arg(from)?;
+                    self.write_arg(to, value)?;
+                }
+
+                VarInstr::Pushq { operand } => {
+                    let value = self.read_arg(operand)?;
+                    self.push(value)?;
+                }
+
+                VarInstr::Popq { operand } => {
+                    let value = self.pop()?;
+                    self.write_arg(operand, value)?;
+                }
+
+                VarInstr::Callq { callee } => self.call(callee)?,
+
+                VarInstr::Retq => return Ok(BlockExit::Return),
+
+                VarInstr::Jmp { target } => return Ok(BlockExit::Jump(target)),
+
+                VarInstr::Jo { target } => {
+                    if self.overflow.ok_or(EmulatorError::UndefinedOverflowFlag)? {
+                        return Ok(BlockExit::Jump(target));
+                    }
+                }
+            }
+        }
+
+        Ok(BlockExit::FallThrough)
+    }
+
+    fn run(&mut self) -> Result<i64, EmulatorError> {
+        let mut index = self.find_block("main").unwrap_or(0);
+
+        while let Some(block) = self.program.body.get(index) {
+            match self.run_block(block)? {
+                BlockExit::Jump(target) => index = self.find_block(target)?,
+                BlockExit::FallThrough => index += 1,
+                BlockExit::Return => break,
+            }
+        }
+
+        let offset = Self::STACK_BASE - self.read_reg(Reg::RSP)?;
+        if offset != 0 {
+            return Err(EmulatorError::UnbalancedStack { offset });
+        }
+
+        self.read_reg(Reg::RAX)
+    }
+}
+
+/// Executes an x86 program from its `main` block, or from its first block if there is no `main`
+/// block. The program ends when it returns or falls off its last block, and the result is the
+/// value of %rax.
+pub fn emulate_x86(
+    program: &VarProgram,
+    input: &mut dyn InputSource,
+) -> Result<Evaluation, EmulatorError> {
+    emulate_x86_with_options(program, input, EmulatorOptions::default())
+}
+
+pub fn emulate_x86_with_options(
+    program: &VarProgram,
+    input: &mut dyn InputSource,
+    options: EmulatorOptions,
+) -> Result<Evaluation, EmulatorError> {
+    let mut emulator = Emulator::new(program, input, options);
+    let value = emulator.run()?;
+    Ok(Evaluation {
+        value,
+        inputs_consumed: emulator.inputs_consumed,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use frontend::{interp_expr, parse_expr, ArithmeticSemantics};
+
+    use crate::{
+        assign_homes::assign_homes, explicate_control::explicate_control,
+        patch_instructions::patch_instructions, remove_complex_operands::remove_complex_operands,
+        select_instructions::select_instructions, uniquify::uniquify_expr,
+    };
+
+    use super::*;
+
+    fn generate_test_program(instructions: Vec<VarInstr>) -> VarProgram {
+        VarProgram {
+            local_variables: Vec::new(),
+            body: vec![VarBlock {
+                label: "main".to_string(),
+                instructions,
+            }],
+            frame_size: 0,
+        }
+    }
+
+    #[test]
+    fn emulate_backend_stages() {
+        for (code, inputs) in [
+            ("42", vec![]),
+            ("(- 10 1 2 3)", vec![]),
+            ("(let ([x 100000]) (+ x (- 70000)))", vec![]),
+            ("(let ([x read] [y read]) (- x y))", vec![10, 3]),
+            (
+                "(let ([y (let ([x1 (- 20)]) (let ([x2 22]) (+ x1 x2)))]) y)",
+                vec![],
+            ),
+            ("(+ (let ([x read]) (- x)) (- read read))", vec![1, 2, 3]),
+        ] {
+            let expected = interp_expr(
+                &parse_expr(code).unwrap(),
+                &mut VecDeque::from(inputs.clone()),
+            );
+
+            let program = select_instructions(
+                explicate_control(remove_complex_operands(
+                    uniquify_expr(parse_expr(code).unwrap()).unwrap(),
+                )),
+                ArithmeticSemantics::Checked,
+            );
+            assert_eq!(
+                emulate_x86(&program, &mut VecDeque::from(inputs.clone())),
+                Ok(expected.clone().unwrap())
+            );
+
+            let program = assign_homes(program);
+            assert_eq!(
+                emulate_x86(&program, &mut VecDeque::from(inputs.clone())),
+                Ok(expected.clone().unwrap())
+            );
+
+            let program = patch_instructions(program);
+            assert_eq!(
+                emulate_x86(&program, &mut VecDeque::from(inputs)),
+                Ok(expected.unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn emulate_control_flow() {
+        use VarArg::{Deref, Imm};
+
+        let program = VarProgram {
+            local_variables: Vec::new(),
+            body: vec![
+                VarBlock {
+                    label: "second".to_string(),
+                    instructions: vec![
+                        VarInstr::Popq {
+                            operand: Reg::RAX.into(),
+                        },
+                        VarInstr::Negq {
+                            operand: Reg::RAX.into(),
+                        },
+                        VarInstr::Retq,
+                    ],
+                },
+                VarBlock {
+                    label: "main".to_string(),
+                    instructions: vec![
+                        VarInstr::Movq {
+                            from: Imm(3),
+                            to: Deref(Reg::RBP, -8),
+                        },
+                        VarInstr::Pushq {
+                            operand: Deref(Reg::RBP, -8),
+                        },
+                    ],
+                },
+                VarBlock {
+                    label: "fallthrough".to_string(),
+                    instructions: vec![VarInstr::Jmp {
+                        target: "second".to_string(),
+                    }],
+                },
+            ],
+            frame_size: 0,
+        };
+
+        assert_eq!(
+            emulate_x86(&program, &mut VecDeque::new()),
+            Ok(Evaluation {
+                value: -3,
+                inputs_consumed: 0
+            })
+        );
+    }
+
+    #[test]
+    fn emulate_error() {
+        use VarArg::{Deref, Imm, Variable};
+
+        let emulate =
+            |instructions| emulate_x86(&generate_test_program(instructions), &mut VecDeque::new());
+
+        assert_eq!(
+            emulate(vec![VarInstr::Retq]),
+            Err(EmulatorError::UninitializedRegister(Reg::RAX))
+        );
+
+        // %rcx is clobbered by the call.
+        assert_eq!(
+            emulate_x86(
+                &generate_test_program(vec![
+                    VarInstr::Movq {
+                        from: Imm(1),
+                        to: Reg::RCX.into()
+                    },
+                    VarInstr::Callq {
+                        callee: "read_int".to_string()
+                    },
+                    VarInstr::Addq {
+                        lhs: Reg::RAX.into(),
+                        rhs: Reg::RCX.into()
+                    },
+                ]),
+                &mut VecDeque::from([1])
+            ),
+            Err(EmulatorError::UninitializedRegister(Reg::RCX))
+        );
+
+        assert_eq!(
+            emulate(vec![VarInstr::Callq {
+                callee: "read_int".to_string()
+            }]),
+            Err(EmulatorError::Frontend(InterpreterError::EndOfInput {
+                consumed: 0
+            }))
+        );
+
+        assert_eq!(
+            emulate(vec![VarInstr::Movq {
+                from: Variable("x".to_string()),
+                to: Reg::RAX.into()
+            }]),
+            Err(EmulatorError::UninitializedVariable("x".to_string()))
+        );
+
+        assert_eq!(
+            emulate(vec![VarInstr::Movq {
+                from: Deref(Reg::RBP, -16),
+                to: Reg::RAX.into()
+            }]),
+            Err(EmulatorError::UninitializedMemory(Deref(Reg::RBP, -16)))
+        );
+
+        assert_eq!(
+            emulate(vec![VarInstr::Movq {
+                from: Imm(1),
+                to: Deref(Reg::RBP, -4)
+            }]),
+            Err(EmulatorError::MisalignedAccess(Deref(Reg::RBP, -4)))
+        );
+
+        assert_eq!(
+            emulate(vec![VarInstr::Movq {
+                from: Reg::RBX.into(),
+                to: Imm(1)
+            }]),
+            Err(EmulatorError::InvalidDestination(Imm(1)))
+        );
+
+        assert_eq!(
+            emulate(vec![VarInstr::Popq {
+                operand: Reg::RAX.into()
+            }]),
+            Err(EmulatorError::StackUnderflow)
+        );
+
+        let options = EmulatorOptions { fuel: Some(100) };
+        assert_eq!(
+            emulate_x86_with_options(
+                &generate_test_program(vec![VarInstr::Jmp {
+                    target: "main".to_string()
+                }]),
+                &mut VecDeque::new(),
+                options
+            ),
+            Err(EmulatorError::Frontend(InterpreterError::StepLimitExceeded))
+        );
+
+        assert_eq!(
+            emulate(vec![
+                VarInstr::Pushq { operand: Imm(1) },
+                VarInstr::Pushq { operand: Imm(2) },
+                VarInstr::Popq {
+                    operand: Reg::RAX.into()
+                },
+            ]),
+            Err(EmulatorError::UnbalancedStack { offset: 8 })
+        );
+
+        assert_eq!(
+            emulate(vec![VarInstr::Jmp {
+                target: "nowhere".to_string()
+            }]),
+            Err(EmulatorError::UnknownLabel("nowhere".to_string()))
+        );
+
+        assert_eq!(
+            emulate(vec![VarInstr::Callq {
+                callee: "print_int".to_string()
+            }]),
+            Err(EmulatorError::UnknownFunction("print_int".to_string()))
+        );
+
+        assert_eq!(
+            emulate(vec![
+                VarInstr::Callq {
+                    callee: "exit".to_string()
+                },
+                VarInstr::Jo {
+                    target: "main".to_string()
+                }
+            ]),
+            Err(EmulatorError::UninitializedRegister(Reg::RDI))
+        );
+
+        assert_eq!(
+            emulate(vec![VarInstr::Jo {
+                target: "main".to_string()
+            }]),
+            Err(EmulatorError::UndefinedOverflowFlag)
+        );
+    }
+
+    #[test]
+    fn emulate_arithmetic() {
+        use VarArg::Imm;
+
+        let rcx = || VarArg::Reg(Reg::RCX);
+        let program = generate_test_program(vec![
+            VarInstr::Movq {
+                from: Imm(i64::MAX),
+                to: rcx(),
+            },
+            VarInstr::Decq { operand: rcx() },
+            VarInstr::Incq { operand: rcx() },
+            // Does not change the flags, which are set by `incq`.
+            VarInstr::Movq {
+                from: rcx(),
+                to: Reg::RAX.into(),
+            },
+            VarInstr::Jo {
+                target: "overflow".to_string(),
+            },
+        ]);
+        assert_eq!(
+            emulate_x86(&program, &mut VecDeque::new()).map(|evaluation| evaluation.value),
+            Ok(i64::MAX)
+        );
+
+        for (value, instr) in [
+            (i64::MAX, VarInstr::Incq { operand: rcx() }),
+            (i64::MIN, VarInstr::Decq { operand: rcx() }),
+        ] {
+            let program = generate_test_program(vec![
+                VarInstr::Movq {
+                    from: Imm(value),
+                    to: rcx(),
+                },
+                instr,
+                VarInstr::Jo {
+                    target: "overflow".to_string(),
+                },
+            ]);
+            assert_eq!(
+                emulate_x86(&program, &mut VecDeque::new()),
+                Err(EmulatorError::UnknownLabel("overflow".to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn emulate_overflow() {
+        let expr = parse_expr("(- (- read 9223372036854775807))").unwrap();
+        let emulate = |semantics, input: i64| {
+            let program = crate::compile_with_semantics(expr.clone(), semantics).unwrap();
+            emulate_x86(&program, &mut VecDeque::from([input])).map(|evaluation| evaluation.value)
+        };
+
+        assert_eq!(emulate(ArithmeticSemantics::Checked, -1), Ok(i64::MIN));
+        assert_eq!(emulate(ArithmeticSemantics::Wrapping, -1), Ok(i64::MIN));
+        assert_eq!(emulate(ArithmeticSemantics::Trapping, 1), Ok(i64::MAX - 1));
+        assert_eq!(
+            emulate(ArithmeticSemantics::Trapping, -1),
+            Err(EmulatorError::Exit { code: 65 })
+        );
+        assert_eq!(
+            emulate(ArithmeticSemantics::Trapping, -2),
+            Err(EmulatorError::Exit { code: 67 })
+        );
+    }
+}
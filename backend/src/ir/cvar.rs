@@ -1,24 +1,34 @@
 use std::fmt::{Display, Formatter};
 
 #[derive(Debug, Eq, PartialEq, Clone)]
-pub(crate) enum Atom {
+pub enum Atom {
     Integer(i64),
     Variable(String),
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
-pub(crate) enum UnaryOpKind {
+pub enum UnaryOpKind {
     Minus, // -
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
-pub(crate) enum BinaryOpKind {
+pub enum BinaryOpKind {
     Add, // +
     Sub, // -
 }
 
+impl BinaryOpKind {
+    /// Checks whether the operands of the operation can be swapped.
+    pub fn is_commutative(&self) -> bool {
+        match self {
+            BinaryOpKind::Add => true,
+            BinaryOpKind::Sub => false,
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
-pub(crate) enum Expr {
+pub enum Expr {
     Atom(Atom),
     Read,
     UnaryOperation {
@@ -32,39 +42,143 @@ pub(crate) enum Expr {
     },
 }
 
+impl Atom {
+    /// Returns the name of the variable, if the atom is one.
+    pub fn as_variable(&self) -> Option<&str> {
+        match self {
+            Atom::Variable(name) => Some(name),
+            Atom::Integer(_) => None,
+        }
+    }
+}
+
+impl Expr {
+    /// Returns the operands of the expression, from left to right.
+    pub fn atoms(&self) -> Vec<&Atom> {
+        match self {
+            Expr::Atom(atom) | Expr::UnaryOperation { operand: atom, .. } => vec![atom],
+            Expr::Read => vec![],
+            Expr::BinaryOperation {
+                left_operand,
+                right_operand,
+                ..
+            } => vec![left_operand, right_operand],
+        }
+    }
+
+    pub fn atoms_mut(&mut self) -> Vec<&mut Atom> {
+        match self {
+            Expr::Atom(atom) | Expr::UnaryOperation { operand: atom, .. } => vec![atom],
+            Expr::Read => vec![],
+            Expr::BinaryOperation {
+                left_operand,
+                right_operand,
+                ..
+            } => vec![left_operand, right_operand],
+        }
+    }
+
+    /// Returns the variables that the expression reads, from left to right.
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.atoms().into_iter().filter_map(Atom::as_variable)
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
-pub(crate) enum Stmt {
+pub enum Stmt {
     Assign { lhs: String, rhs: Expr },
+    // Terminators, which must be the last statement of a block.
     Return(Expr),
+    Goto(String),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Block {
+    pub label: String,
+    pub body: Vec<Stmt>,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
-pub(crate) struct Program {
-    pub(crate) locals: Vec<String>,
-    pub(crate) body: Vec<Stmt>,
+pub struct Program {
+    pub locals: Vec<String>,
+    // The program starts at the first block, whatever its label. The passes, the interpreter and
+    // the analyses all rely on this, so a pass that reorders the blocks keeps the entry first.
+    pub blocks: Vec<Block>,
+}
+
+impl Block {
+    pub fn new(label: String) -> Self {
+        Self {
+            label,
+            body: Vec::new(),
+        }
+    }
+
+    /// Returns the labels of the blocks that can run right after this one.
+    pub fn successors(&self) -> Vec<&str> {
+        self.body
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::Goto(label) => Some(label.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
 }
 
 impl Program {
-    pub(crate) fn new() -> Self {
+    /// The label that `new` gives to the entry block.
+    pub fn entry_label() -> String {
+        "start".to_string()
+    }
+
+    pub fn new() -> Self {
         Self {
             locals: Vec::new(),
-            body: Vec::new(),
+            blocks: vec![Block::new(Self::entry_label())],
         }
     }
 
-    pub(crate) fn create_terminator(&mut self, expr: Expr) {
-        self.body.push(Stmt::Return(expr));
+    /// Starts a new block, to which the following statements are added.
+    pub fn create_block(&mut self, label: String) {
+        self.blocks.push(Block::new(label));
+    }
+
+    fn current_block(&mut self) -> &mut Block {
+        self.blocks.last_mut().unwrap()
+    }
+
+    pub fn create_terminator(&mut self, expr: Expr) {
+        self.current_block().body.push(Stmt::Return(expr));
+    }
+
+    pub fn create_goto(&mut self, label: String) {
+        self.current_block().body.push(Stmt::Goto(label));
     }
 
-    pub(crate) fn create_assign(&mut self, lhs: String, rhs: Expr) {
-        self.body.push(Stmt::Assign { lhs, rhs });
+    pub fn create_assign(&mut self, lhs: String, rhs: Expr) {
+        self.current_block().body.push(Stmt::Assign { lhs, rhs });
     }
 
-    pub(crate) fn create_local_variable(&mut self, name: String) {
+    pub fn entry(&self) -> Option<&Block> {
+        self.blocks.first()
+    }
+
+    pub fn find_block(&self, label: &str) -> Option<&Block> {
+        self.blocks.iter().find(|block| block.label == label)
+    }
+
+    pub fn create_local_variable(&mut self, name: String) {
         self.locals.push(name);
     }
 }
 
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl From<Atom> for Expr {
     fn from(value: Atom) -> Self {
         Self::Atom(value)
@@ -136,19 +250,28 @@ impl Display for Stmt {
         match self {
             Stmt::Assign { lhs, rhs } => write!(f, "{} = {};", lhs, rhs),
             Stmt::Return(expr) => write!(f, "return {};", expr),
+            Stmt::Goto(label) => write!(f, "goto {};", label),
         }
     }
 }
 
+impl Display for Block {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}:", self.label)?;
+        self.body
+            .iter()
+            .try_for_each(|stmt| writeln!(f, "    {}", stmt))
+    }
+}
+
 impl Display for Program {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         if !self.locals.is_empty() {
             writeln!(f, "local: [{}]", self.locals.join(", "))?;
         }
 
-        writeln!(f, "start:")?;
-        self.body
+        self.blocks
             .iter()
-            .try_for_each(|stmt| writeln!(f, "    {}", stmt))
+            .try_for_each(|block| write!(f, "{}", block))
     }
 }
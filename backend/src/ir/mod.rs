@@ -1,2 +1,3 @@
-pub(crate) mod cvar;
+pub mod cvar;
+pub mod ssa;
 pub mod x86;
@@ -0,0 +1,104 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+};
+
+use crate::ir::cvar::{Atom, Stmt};
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Phi {
+    pub lhs: String,
+    // The value of `lhs` when the block is entered from each predecessor, given by its label.
+    pub arguments: Vec<(String, Atom)>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Block {
+    pub label: String,
+    // The phi nodes, which are all evaluated at the same time when the block is entered.
+    pub phis: Vec<Phi>,
+    // The statements of C_var, in which every variable is assigned exactly once.
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Program {
+    pub locals: Vec<String>,
+    // The first block is the entry of the program, as in C_var.
+    pub blocks: Vec<Block>,
+}
+
+impl Block {
+    pub fn new(label: String) -> Self {
+        Self {
+            label,
+            phis: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Returns the labels of the blocks that can run right after this one.
+    pub fn successors(&self) -> Vec<&str> {
+        self.body
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::Goto(label) => Some(label.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Program {
+    /// Returns the labels of the predecessors of every block, in the order of the blocks.
+    pub fn predecessors(&self) -> HashMap<&str, Vec<&str>> {
+        let mut result: HashMap<&str, Vec<&str>> = self
+            .blocks
+            .iter()
+            .map(|block| (block.label.as_str(), Vec::new()))
+            .collect();
+        for block in &self.blocks {
+            for successor in block.successors() {
+                if let Some(predecessors) = result.get_mut(successor) {
+                    predecessors.push(&block.label);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Display for Phi {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let arguments: Vec<_> = self
+            .arguments
+            .iter()
+            .map(|(label, atom)| format!("{}: {}", label, atom))
+            .collect();
+        write!(f, "{} = phi({});", self.lhs, arguments.join(", "))
+    }
+}
+
+impl Display for Block {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}:", self.label)?;
+        self.phis
+            .iter()
+            .try_for_each(|phi| writeln!(f, "    {}", phi))?;
+        self.body
+            .iter()
+            .try_for_each(|stmt| writeln!(f, "    {}", stmt))
+    }
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if !self.locals.is_empty() {
+            writeln!(f, "local: [{}]", self.locals.join(", "))?;
+        }
+
+        self.blocks
+            .iter()
+            .try_for_each(|block| write!(f, "{}", block))
+    }
+}
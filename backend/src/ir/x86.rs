@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
 #[rustfmt::skip]
 pub enum Reg {
     RSP, RBP, RAX, RBX, RCX, RDX, RSI, RDI,
@@ -22,6 +22,10 @@ pub enum Instruction<Arg> {
     // Note that for `subq a, b`, b is `lhs` and a is `rhs`, since it represents b - a
     Subq { lhs: Arg, rhs: Arg },
     Negq { operand: Arg },
+    Incq { operand: Arg },
+    Decq { operand: Arg },
+    // Note that for `xorq a, b`, b is `lhs` and a is `rhs`.
+    Xorq { lhs: Arg, rhs: Arg },
     // Note that for `movq a, b`, a is `from` and b is `to`.
     Movq { from: Arg, to: Arg },
     Pushq { operand: Arg },
@@ -29,10 +33,42 @@ pub enum Instruction<Arg> {
     Callq { callee: String },
     Retq,
     Jmp { target: String },
+    // Jumps to `target` if the last arithmetic instruction overflowed.
+    Jo { target: String },
 }
 
 pub type VarInstr = Instruction<VarArg>;
 
+impl<Arg> Instruction<Arg> {
+    pub fn operands(&self) -> Vec<&Arg> {
+        use Instruction::*;
+        match self {
+            Addq { lhs, rhs } | Subq { lhs, rhs } | Xorq { lhs, rhs } => vec![lhs, rhs],
+            Movq { from, to } => vec![from, to],
+            Negq { operand }
+            | Incq { operand }
+            | Decq { operand }
+            | Pushq { operand }
+            | Popq { operand } => vec![operand],
+            Callq { .. } | Retq | Jmp { .. } | Jo { .. } => vec![],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Arg> {
+        use Instruction::*;
+        match self {
+            Addq { lhs, rhs } | Subq { lhs, rhs } | Xorq { lhs, rhs } => vec![lhs, rhs],
+            Movq { from, to } => vec![from, to],
+            Negq { operand }
+            | Incq { operand }
+            | Decq { operand }
+            | Pushq { operand }
+            | Popq { operand } => vec![operand],
+            Callq { .. } | Retq | Jmp { .. } | Jo { .. } => vec![],
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Block<ArgType> {
     pub label: String,
@@ -58,6 +94,9 @@ impl<ArgType> Block<ArgType> {
 pub struct Program<ArgType> {
     pub local_variables: Vec<String>,
     pub body: Vec<Block<ArgType>>,
+    // The number of bytes below %rbp that hold the homes of the variables, which is a multiple of
+    // 16 so that the stack stays aligned.
+    pub frame_size: usize,
 }
 
 pub type VarProgram = Program<VarArg>;
@@ -67,10 +106,17 @@ impl<ArgType> Program<ArgType> {
         Self {
             local_variables: Vec::new(),
             body: Vec::new(),
+            frame_size: 0,
         }
     }
 }
 
+impl<ArgType> Default for Program<ArgType> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl From<Reg> for VarArg {
     fn from(value: Reg) -> Self {
         VarArg::Reg(value)
@@ -114,12 +160,16 @@ impl<ArgType: Display> Display for Instruction<ArgType> {
             Addq { lhs, rhs } => write!(f, "addq    {}, {}", rhs, lhs),
             Subq { lhs, rhs } => write!(f, "subq    {}, {}", rhs, lhs),
             Negq { operand } => write!(f, "negq    {}", operand),
+            Incq { operand } => write!(f, "incq    {}", operand),
+            Decq { operand } => write!(f, "decq    {}", operand),
+            Xorq { lhs, rhs } => write!(f, "xorq    {}, {}", rhs, lhs),
             Movq { from, to } => write!(f, "movq    {}, {}", from, to),
             Pushq { operand } => write!(f, "pushq   {}", operand),
             Popq { operand } => write!(f, "popq    {}", operand),
             Callq { callee } => write!(f, "callq   {}", callee),
             Retq => write!(f, "retq"),
             Jmp { target } => write!(f, "jmp     {}", target),
+            Jo { target } => write!(f, "jo      {}", target),
         }
     }
 }
@@ -138,6 +188,9 @@ impl<ArgType: Display> Display for Program<ArgType> {
         if !self.local_variables.is_empty() {
             writeln!(f, "locals: [{}]", self.local_variables.join(", "))?;
         }
+        if self.frame_size != 0 {
+            writeln!(f, "frame: {}", self.frame_size)?;
+        }
 
         self.body
             .iter()
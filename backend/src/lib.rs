@@ -1,10 +1,60 @@
 mod assign_homes;
+mod block_layout;
+mod construct_ssa;
+mod copy_propagation;
+mod destruct_ssa;
 mod explicate_control;
-mod ir;
+pub mod interp;
+pub mod ir;
+mod partial_eval;
+mod pass_manager;
 mod patch_instructions;
+mod peephole;
+mod range_analysis;
 mod remove_complex_operands;
+mod rewrite;
+mod runtime;
 mod select_instructions;
+mod sparse_constant_propagation;
 mod uniquify;
+mod validate;
+mod value_numbering;
+mod verify;
+
+use frontend::{ArithmeticSemantics, Expr};
+use ir::x86::VarProgram;
+
+pub use pass_manager::{
+    DumpPoint, FnPass, Ir, IrDump, IrForm, IrKind, Pass, PassDiagnostic, PassFilter, PassManager,
+    PassManagerOptions, PassStatistics, Severity, Verifier,
+};
+pub use rewrite::{algebraic_rules, Condition, RewriteResult, RewriteStep, Rewriter, Rule};
+pub use runtime::OverflowTrap;
+pub use validate::{
+    validate_optimized_passes, validate_passes, validate_passes_with_options,
+    validate_passes_with_semantics, Divergence, Outcome, ValidateOptions,
+};
+pub use verify::{
+    pipeline_verifier, verify_cvar, verify_monadic, verify_x86, Location, VerifyError,
+    VerifyErrorKind, X86Stage,
+};
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum PassError {
+    UnknownIdentifier(String),
+    // A pass is given a program in another IR than the one it works on.
+    UnexpectedIr {
+        pass: String,
+        expected: IrKind,
+        found: IrKind,
+    },
+    // The pass manager is configured with a pass that is not in its pipeline.
+    UnknownPass(String),
+    VerificationFailed {
+        pass: String,
+        message: String,
+    },
+}
 
 pub(crate) struct NameGenerator {
     prefix: String,
@@ -22,3 +72,18 @@ impl NameGenerator {
         result
     }
 }
+
+/// Runs every pass of the compiler on `expr`, and produces the x86 program in which all variables
+/// have been assigned to stack locations.
+pub fn compile(expr: Expr) -> Result<VarProgram, PassError> {
+    compile_with_semantics(expr, ArithmeticSemantics::default())
+}
+
+pub fn compile_with_semantics(
+    expr: Expr,
+    semantics: ArithmeticSemantics,
+) -> Result<VarProgram, PassError> {
+    PassManager::with_default_pipeline(PassManagerOptions::default(), semantics)
+        .run(Ir::Source(expr))?
+        .into_x86("compile")
+}
@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+
+use frontend::{ArithmeticSemantics, BinaryOpKind, Expr, UnaryOpKind};
+
+/// The partially evaluated value of an expression, i.e. `constant + dynamic`, where `dynamic` is
+/// the residual code that can only be evaluated at runtime.
+struct PeValue {
+    constant: i64,
+    dynamic: Option<Expr>,
+}
+
+impl PeValue {
+    fn constant(value: i64) -> Self {
+        Self {
+            constant: value,
+            dynamic: None,
+        }
+    }
+
+    fn dynamic(expr: Expr) -> Self {
+        Self {
+            constant: 0,
+            dynamic: Some(expr),
+        }
+    }
+}
+
+fn negate(expr: Expr) -> Expr {
+    Expr::UnaryOperation {
+        kind: UnaryOpKind::Minus,
+        operand: Box::new(expr),
+    }
+}
+
+fn binary(kind: BinaryOpKind, lhs: Expr, rhs: Expr) -> Expr {
+    Expr::BinaryOperation {
+        kind,
+        left_operand: Box::new(lhs),
+        right_operand: Box::new(rhs),
+    }
+}
+
+// Only valid when arithmetic wraps around, since `(- (- x))` overflows if `x` is `i64::MIN`.
+fn cancel_negation(expr: Expr) -> Expr {
+    match expr {
+        Expr::UnaryOperation {
+            kind: UnaryOpKind::Minus,
+            operand,
+        } => *operand,
+        other => negate(other),
+    }
+}
+
+/// Produces an expression that evaluates to `value` without overflowing.
+fn integer_expr(value: i64) -> Expr {
+    match value {
+        // `i64::MIN` cannot be written as the negation of a literal.
+        i64::MIN => binary(
+            BinaryOpKind::Sub,
+            negate(Expr::Integer(i64::MAX as u64)),
+            Expr::Integer(1),
+        ),
+        value if value < 0 => negate(Expr::Integer(value.unsigned_abs())),
+        value => Expr::Integer(value as u64),
+    }
+}
+
+struct PartialEvaluator {
+    semantics: ArithmeticSemantics,
+    // The constant value of each variable in scope, or `None` if it is only known at runtime.
+    symbol_table: Vec<HashMap<String, Option<i64>>>,
+}
+
+impl PartialEvaluator {
+    fn new(semantics: ArithmeticSemantics) -> Self {
+        Self {
+            semantics,
+            symbol_table: Vec::new(),
+        }
+    }
+
+    // Moving constants across operations only preserves the behavior of programs when arithmetic
+    // wraps around. Otherwise, the operands reported by an overflow would change.
+    fn can_reassociate(&self) -> bool {
+        self.semantics == ArithmeticSemantics::Wrapping
+    }
+
+    fn lookup(&self, name: &str) -> Option<Option<i64>> {
+        self.symbol_table
+            .iter()
+            .rev()
+            .find_map(|table| table.get(name))
+            .copied()
+    }
+
+    fn residualize(&self, value: PeValue) -> Expr {
+        match value.dynamic {
+            None => integer_expr(value.constant),
+            Some(dynamic) if value.constant == 0 => dynamic,
+            // Like in `pe_Lint`, the constant comes first, e.g. `(+ 15 read)`.
+            Some(dynamic) if value.constant > 0 => {
+                binary(BinaryOpKind::Add, integer_expr(value.constant), dynamic)
+            }
+            Some(dynamic) if value.constant != i64::MIN => {
+                binary(BinaryOpKind::Sub, dynamic, integer_expr(-value.constant))
+            }
+            Some(dynamic) => binary(BinaryOpKind::Add, integer_expr(value.constant), dynamic),
+        }
+    }
+
+    fn pe_neg(&self, operand: PeValue) -> PeValue {
+        match operand {
+            PeValue {
+                constant,
+                dynamic: None,
+            } => match self.semantics.neg(constant) {
+                Ok(result) => PeValue::constant(result),
+                // Keep the operation, so that the overflow happens at runtime.
+                Err(_) => PeValue::dynamic(negate(integer_expr(constant))),
+            },
+
+            PeValue {
+                constant,
+                dynamic: Some(dynamic),
+            } if self.can_reassociate() => PeValue {
+                constant: constant.wrapping_neg(),
+                dynamic: Some(cancel_negation(dynamic)),
+            },
+
+            other => PeValue::dynamic(negate(self.residualize(other))),
+        }
+    }
+
+    fn pe_binary(&self, kind: BinaryOpKind, lhs: PeValue, rhs: PeValue) -> PeValue {
+        if lhs.dynamic.is_none() && rhs.dynamic.is_none() {
+            let result = match kind {
+                BinaryOpKind::Add => self.semantics.add(lhs.constant, rhs.constant),
+                BinaryOpKind::Sub => self.semantics.sub(lhs.constant, rhs.constant),
+            };
+            if let Ok(result) = result {
+                return PeValue::constant(result);
+            }
+        } else if self.can_reassociate() {
+            // (c1 + d1) + (c2 + d2) = (c1 + c2) + (d1 + d2), and similarly for subtraction. The
+            // dynamic parts are still evaluated from left to right.
+            let (constant, dynamic) = match kind {
+                BinaryOpKind::Add => (
+                    lhs.constant.wrapping_add(rhs.constant),
+                    match (lhs.dynamic, rhs.dynamic) {
+                        (Some(lhs), Some(rhs)) => binary(BinaryOpKind::Add, lhs, rhs),
+                        (Some(dynamic), None) | (None, Some(dynamic)) => dynamic,
+                        (None, None) => unreachable!(),
+                    },
+                ),
+                BinaryOpKind::Sub => (
+                    lhs.constant.wrapping_sub(rhs.constant),
+                    match (lhs.dynamic, rhs.dynamic) {
+                        (Some(lhs), Some(rhs)) => binary(BinaryOpKind::Sub, lhs, rhs),
+                        (Some(dynamic), None) => dynamic,
+                        (None, Some(dynamic)) => cancel_negation(dynamic),
+                        (None, None) => unreachable!(),
+                    },
+                ),
+            };
+            return PeValue {
+                constant,
+                dynamic: Some(dynamic),
+            };
+        }
+
+        PeValue::dynamic(binary(kind, self.residualize(lhs), self.residualize(rhs)))
+    }
+
+    fn pe_expr(&mut self, expr: Expr) -> PeValue {
+        match expr {
+            Expr::Integer(value) => match i64::try_from(value) {
+                Ok(value) => PeValue::constant(value),
+                // The conversion error is reported at runtime.
+                Err(_) => PeValue::dynamic(Expr::Integer(value)),
+            },
+
+            Expr::Read => PeValue::dynamic(Expr::Read),
+
+            Expr::Identifier(name) => match self.lookup(&name) {
+                Some(Some(value)) => PeValue::constant(value),
+                _ => PeValue::dynamic(Expr::Identifier(name)),
+            },
+
+            Expr::UnaryOperation {
+                kind: UnaryOpKind::Minus,
+                operand,
+            } => {
+                let operand = self.pe_expr(*operand);
+                self.pe_neg(operand)
+            }
+
+            Expr::BinaryOperation {
+                kind,
+                left_operand,
+                right_operand,
+            } => {
+                let lhs = self.pe_expr(*left_operand);
+                let rhs = self.pe_expr(*right_operand);
+                self.pe_binary(kind, lhs, rhs)
+            }
+
+            Expr::Let {
+                variable_name,
+                init_expr,
+                body,
+            } => {
+                let init = self.pe_expr(*init_expr);
+                let constant = match init.dynamic {
+                    None => Some(init.constant),
+                    Some(_) => None,
+                };
+
+                self.symbol_table
+                    .push(HashMap::from([(variable_name.clone(), constant)]));
+                let body = self.pe_expr(*body);
+                self.symbol_table.pop();
+
+                // A constant is propagated into the body, so its binding is no longer needed.
+                match constant {
+                    Some(_) => body,
+                    None => PeValue::dynamic(Expr::Let {
+                        variable_name,
+                        init_expr: Box::new(self.residualize(init)),
+                        body: Box::new(self.residualize(body)),
+                    }),
+                }
+            }
+        }
+    }
+}
+
+/// Evaluates the parts of `expr` that do not depend on the input, without changing the value,
+/// the inputs read or the errors of the program under `semantics`.
+pub(crate) fn partial_evaluate(expr: Expr, semantics: ArithmeticSemantics) -> Expr {
+    let mut evaluator = PartialEvaluator::new(semantics);
+    let result = evaluator.pe_expr(expr);
+    evaluator.residualize(result)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use frontend::{
+        interp_expr_with_options, parse_expr, ExprGenerator, GeneratorConfig, InterpOptions,
+    };
+
+    use crate::{compile_with_semantics, interp::x86::emulate_x86};
+
+    use super::*;
+
+    fn pe(code: &str, semantics: ArithmeticSemantics) -> String {
+        partial_evaluate(parse_expr(code).unwrap(), semantics).to_string()
+    }
+
+    #[test]
+    fn fold_constants() {
+        for semantics in [
+            ArithmeticSemantics::Wrapping,
+            ArithmeticSemantics::Checked,
+            ArithmeticSemantics::Trapping,
+        ] {
+            assert_eq!(pe("(+ 10 (- (+ 5 3)))", semantics), "2");
+            assert_eq!(
+                pe("(let ([x 5]) (let ([y (- x 8)]) (+ x y)))", semantics),
+                "2"
+            );
+            assert_eq!(
+                pe("(- 0 9223372036854775807 1)", semantics),
+                "(- (- 9223372036854775807) 1)"
+            );
+            assert_eq!(
+                pe("(let ([x read]) (let ([y (+ 1 2)]) (- x y)))", semantics),
+                "(let ([x read]) (- x 3))"
+            );
+        }
+
+        // The variable refers to the inner declaration.
+        assert_eq!(
+            pe(
+                "(let ([x 1]) (+ (let ([x read]) x) x))",
+                ArithmeticSemantics::Checked
+            ),
+            "(+ (let ([x read]) x) 1)"
+        );
+
+        // Overflowing operations are kept, so that the error happens at runtime.
+        assert_eq!(
+            pe(
+                "(+ read (+ 9223372036854775807 1))",
+                ArithmeticSemantics::Checked
+            ),
+            "(+ read (+ 9223372036854775807 1))"
+        );
+        assert_eq!(
+            pe(
+                "(- (- 9223372036854775807 -1) read)",
+                ArithmeticSemantics::Trapping
+            ),
+            "(- (- 9223372036854775807 (- 1)) read)"
+        );
+        assert_eq!(
+            pe("(+ 9223372036854775807 1)", ArithmeticSemantics::Wrapping),
+            "(- (- 9223372036854775807) 1)"
+        );
+    }
+
+    #[test]
+    fn reassociate() {
+        assert_eq!(
+            pe("(+ 10 (+ read 5))", ArithmeticSemantics::Wrapping),
+            "(+ 15 read)"
+        );
+        assert_eq!(
+            pe(
+                "(let ([x read]) (- (+ 1 x) (- (+ read 3))))",
+                ArithmeticSemantics::Wrapping
+            ),
+            "(let ([x read]) (+ 4 (- x (- read))))"
+        );
+        assert_eq!(
+            pe("(- 2 (- 5 (- read)))", ArithmeticSemantics::Wrapping),
+            "(- (- read) 3)"
+        );
+
+        // `(+ read 5)` may overflow when `(+ read 15)` does not, with other operands.
+        assert_eq!(
+            pe("(+ 10 (+ read 5))", ArithmeticSemantics::Checked),
+            "(+ 10 (+ read 5))"
+        );
+    }
+
+    // Compares the partially evaluated programs with the original ones, both in the interpreter
+    // and after compiling them.
+    #[test]
+    fn partial_evaluate_differential() {
+        let mut generator = ExprGenerator::new(41, GeneratorConfig::default());
+        for _ in 0..500 {
+            let expr = generator.generate();
+            let inputs = generator.generate_inputs();
+
+            for semantics in [
+                ArithmeticSemantics::Wrapping,
+                ArithmeticSemantics::Checked,
+                ArithmeticSemantics::Trapping,
+            ] {
+                let evaluated = partial_evaluate(expr.clone(), semantics);
+                let interp = |expr: &Expr| {
+                    let options = InterpOptions {
+                        semantics,
+                        ..InterpOptions::default()
+                    };
+                    interp_expr_with_options(expr, &mut VecDeque::from(inputs.clone()), options)
+                };
+                assert_eq!(
+                    interp(&evaluated),
+                    interp(&expr),
+                    "{} -> {}",
+                    expr,
+                    evaluated
+                );
+
+                // Compiled programs that overflow only have a specified result in these modes.
+                if semantics != ArithmeticSemantics::Checked || interp(&expr).is_ok() {
+                    let emulate = |expr: Expr| {
+                        emulate_x86(
+                            &compile_with_semantics(expr, semantics).unwrap(),
+                            &mut VecDeque::from(inputs.clone()),
+                        )
+                    };
+                    assert_eq!(
+                        emulate(evaluated.clone()),
+                        emulate(expr.clone()),
+                        "{} -> {}",
+                        expr,
+                        evaluated
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1134 @@
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use frontend::{analyze_ranges, ArithmeticSemantics, Expr, OverflowWarning};
+
+use crate::{
+    assign_homes::assign_homes,
+    block_layout::block_layout,
+    construct_ssa::construct_ssa,
+    copy_propagation::copy_propagation,
+    destruct_ssa::destruct_ssa,
+    explicate_control::explicate_control,
+    ir::{cvar::Program as CProgram, ssa::Program as SsaProgram, x86::VarProgram},
+    partial_eval::partial_evaluate,
+    patch_instructions::patch_instructions,
+    peephole::peephole,
+    remove_complex_operands::remove_complex_operands,
+    rewrite::{algebraic_rules, RewriteStep, Rewriter},
+    select_instructions::select_instructions,
+    sparse_constant_propagation::sparse_constant_propagation,
+    uniquify::uniquify_expr,
+    value_numbering::local_value_numbering,
+    verify::{pipeline_verifier, X86Stage},
+    PassError,
+};
+
+/// The program in one of the IRs of the compiler.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Ir {
+    Source(Expr),
+    CVar(CProgram),
+    Ssa(SsaProgram),
+    X86(VarProgram),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum IrKind {
+    Source,
+    CVar,
+    Ssa,
+    X86,
+}
+
+fn expr_size(expr: &Expr) -> usize {
+    match expr {
+        Expr::Integer(_) | Expr::Read | Expr::Identifier(_) => 1,
+        Expr::UnaryOperation { operand, .. } => expr_size(operand) + 1,
+        Expr::BinaryOperation {
+            left_operand,
+            right_operand,
+            ..
+        } => expr_size(left_operand) + expr_size(right_operand) + 1,
+        Expr::Let {
+            init_expr, body, ..
+        } => expr_size(init_expr) + expr_size(body) + 1,
+    }
+}
+
+impl Ir {
+    pub fn kind(&self) -> IrKind {
+        match self {
+            Ir::Source(_) => IrKind::Source,
+            Ir::CVar(_) => IrKind::CVar,
+            Ir::Ssa(_) => IrKind::Ssa,
+            Ir::X86(_) => IrKind::X86,
+        }
+    }
+
+    /// Returns the number of expressions, statements or instructions of the program.
+    pub fn size(&self) -> usize {
+        match self {
+            Ir::Source(expr) => expr_size(expr),
+            Ir::CVar(program) => program.blocks.iter().map(|block| block.body.len()).sum(),
+            Ir::Ssa(program) => program
+                .blocks
+                .iter()
+                .map(|block| block.phis.len() + block.body.len())
+                .sum(),
+            Ir::X86(program) => program
+                .body
+                .iter()
+                .map(|block| block.instructions.len())
+                .sum(),
+        }
+    }
+
+    fn unexpected(self, pass: &str, expected: IrKind) -> PassError {
+        PassError::UnexpectedIr {
+            pass: pass.to_string(),
+            expected,
+            found: self.kind(),
+        }
+    }
+
+    pub fn into_source(self, pass: &str) -> Result<Expr, PassError> {
+        match self {
+            Ir::Source(expr) => Ok(expr),
+            other => Err(other.unexpected(pass, IrKind::Source)),
+        }
+    }
+
+    pub fn into_cvar(self, pass: &str) -> Result<CProgram, PassError> {
+        match self {
+            Ir::CVar(program) => Ok(program),
+            other => Err(other.unexpected(pass, IrKind::CVar)),
+        }
+    }
+
+    pub fn into_ssa(self, pass: &str) -> Result<SsaProgram, PassError> {
+        match self {
+            Ir::Ssa(program) => Ok(program),
+            other => Err(other.unexpected(pass, IrKind::Ssa)),
+        }
+    }
+
+    pub fn into_x86(self, pass: &str) -> Result<VarProgram, PassError> {
+        match self {
+            Ir::X86(program) => Ok(program),
+            other => Err(other.unexpected(pass, IrKind::X86)),
+        }
+    }
+}
+
+/// What the verifier can check about the IR besides its kind, which depends on the passes that
+/// produced it.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct IrForm {
+    // Whether a source program is in monadic normal form.
+    pub monadic: bool,
+    pub x86_stage: X86Stage,
+}
+
+impl Default for IrForm {
+    fn default() -> Self {
+        Self {
+            monadic: false,
+            x86_stage: X86Stage::Selected,
+        }
+    }
+}
+
+impl Display for Ir {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ir::Source(expr) => writeln!(f, "{}", expr),
+            Ir::CVar(program) => write!(f, "{}", program),
+            Ir::Ssa(program) => write!(f, "{}", program),
+            Ir::X86(program) => write!(f, "{}", program),
+        }
+    }
+}
+
+pub trait Pass {
+    fn name(&self) -> &str;
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError>;
+
+    /// Returns the form of the output of the pass when its input has the form `input`. Most
+    /// passes keep it.
+    fn output_form(&self, input: IrForm) -> IrForm {
+        input
+    }
+
+    /// Returns the diagnostics of the last run, e.g. the warnings found by an analysis.
+    fn take_diagnostics(&mut self) -> Vec<(Severity, String)> {
+        Vec::new()
+    }
+}
+
+/// A pass defined by a closure, e.g. a custom pass inserted into the default pipeline.
+pub struct FnPass<F> {
+    name: String,
+    function: F,
+}
+
+impl<F: FnMut(Ir) -> Result<Ir, PassError>> FnPass<F> {
+    pub fn new(name: &str, function: F) -> Self {
+        Self {
+            name: name.to_string(),
+            function,
+        }
+    }
+}
+
+impl<F: FnMut(Ir) -> Result<Ir, PassError>> Pass for FnPass<F> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        (self.function)(ir)
+    }
+}
+
+struct AnalyzeRanges {
+    semantics: ArithmeticSemantics,
+    warnings: Vec<String>,
+}
+
+impl Pass for AnalyzeRanges {
+    fn name(&self) -> &str {
+        "range_analysis"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        let expr = ir.into_source(self.name())?;
+        self.warnings = analyze_ranges(&expr, self.semantics)
+            .warnings
+            .iter()
+            .map(OverflowWarning::to_string)
+            .collect();
+        Ok(Ir::Source(expr))
+    }
+
+    fn take_diagnostics(&mut self) -> Vec<(Severity, String)> {
+        std::mem::take(&mut self.warnings)
+            .into_iter()
+            .map(|message| (Severity::Warning, message))
+            .collect()
+    }
+}
+
+struct PartialEvaluate {
+    semantics: ArithmeticSemantics,
+}
+
+impl Pass for PartialEvaluate {
+    fn name(&self) -> &str {
+        "partial_eval"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        Ok(Ir::Source(partial_evaluate(
+            ir.into_source(self.name())?,
+            self.semantics,
+        )))
+    }
+}
+
+struct Uniquify;
+
+impl Pass for Uniquify {
+    fn name(&self) -> &str {
+        "uniquify"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        Ok(Ir::Source(uniquify_expr(ir.into_source(self.name())?)?))
+    }
+}
+
+struct Rewrite {
+    semantics: ArithmeticSemantics,
+    // The rules that fired in the last run, in order.
+    steps: Vec<String>,
+}
+
+impl Pass for Rewrite {
+    fn name(&self) -> &str {
+        "rewrite"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        let result =
+            Rewriter::new(algebraic_rules(), self.semantics).rewrite(ir.into_source(self.name())?);
+        self.steps = result.steps.iter().map(RewriteStep::to_string).collect();
+        Ok(Ir::Source(result.expr))
+    }
+
+    fn take_diagnostics(&mut self) -> Vec<(Severity, String)> {
+        std::mem::take(&mut self.steps)
+            .into_iter()
+            .map(|step| (Severity::Note, step))
+            .collect()
+    }
+}
+
+struct RemoveComplexOperands;
+
+impl Pass for RemoveComplexOperands {
+    fn name(&self) -> &str {
+        "remove_complex_operands"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        Ok(Ir::Source(remove_complex_operands(
+            ir.into_source(self.name())?,
+        )))
+    }
+
+    fn output_form(&self, input: IrForm) -> IrForm {
+        IrForm {
+            monadic: true,
+            ..input
+        }
+    }
+}
+
+struct ExplicateControl;
+
+impl Pass for ExplicateControl {
+    fn name(&self) -> &str {
+        "explicate_control"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        Ok(Ir::CVar(explicate_control(ir.into_source(self.name())?)))
+    }
+}
+
+struct LocalValueNumbering;
+
+impl Pass for LocalValueNumbering {
+    fn name(&self) -> &str {
+        "local_value_numbering"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        Ok(Ir::CVar(local_value_numbering(ir.into_cvar(self.name())?)))
+    }
+}
+
+struct ConstructSsa;
+
+impl Pass for ConstructSsa {
+    fn name(&self) -> &str {
+        "construct_ssa"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        Ok(Ir::Ssa(construct_ssa(ir.into_cvar(self.name())?)))
+    }
+}
+
+struct SparseConstantPropagation {
+    semantics: ArithmeticSemantics,
+}
+
+impl Pass for SparseConstantPropagation {
+    fn name(&self) -> &str {
+        "sparse_constant_propagation"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        Ok(Ir::Ssa(sparse_constant_propagation(
+            ir.into_ssa(self.name())?,
+            self.semantics,
+        )))
+    }
+}
+
+struct DestructSsa;
+
+impl Pass for DestructSsa {
+    fn name(&self) -> &str {
+        "destruct_ssa"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        Ok(Ir::CVar(destruct_ssa(ir.into_ssa(self.name())?)))
+    }
+}
+
+struct CopyPropagation {
+    semantics: ArithmeticSemantics,
+}
+
+impl Pass for CopyPropagation {
+    fn name(&self) -> &str {
+        "copy_propagation"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        Ok(Ir::CVar(copy_propagation(
+            ir.into_cvar(self.name())?,
+            self.semantics,
+        )))
+    }
+}
+
+struct SelectInstructions {
+    semantics: ArithmeticSemantics,
+}
+
+impl Pass for SelectInstructions {
+    fn name(&self) -> &str {
+        "select_instructions"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        Ok(Ir::X86(select_instructions(
+            ir.into_cvar(self.name())?,
+            self.semantics,
+        )))
+    }
+
+    fn output_form(&self, input: IrForm) -> IrForm {
+        IrForm {
+            x86_stage: X86Stage::Selected,
+            ..input
+        }
+    }
+}
+
+struct AssignHomes;
+
+impl Pass for AssignHomes {
+    fn name(&self) -> &str {
+        "assign_homes"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        Ok(Ir::X86(assign_homes(ir.into_x86(self.name())?)))
+    }
+
+    fn output_form(&self, input: IrForm) -> IrForm {
+        IrForm {
+            x86_stage: X86Stage::HomesAssigned,
+            ..input
+        }
+    }
+}
+
+struct PatchInstructions;
+
+impl Pass for PatchInstructions {
+    fn name(&self) -> &str {
+        "patch_instructions"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        Ok(Ir::X86(patch_instructions(ir.into_x86(self.name())?)))
+    }
+
+    fn output_form(&self, input: IrForm) -> IrForm {
+        IrForm {
+            x86_stage: X86Stage::Patched,
+            ..input
+        }
+    }
+}
+
+struct BlockLayout;
+
+impl Pass for BlockLayout {
+    fn name(&self) -> &str {
+        "block_layout"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        Ok(Ir::X86(block_layout(ir.into_x86(self.name())?)))
+    }
+}
+
+struct Peephole;
+
+impl Pass for Peephole {
+    fn name(&self) -> &str {
+        "peephole"
+    }
+
+    fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        Ok(Ir::X86(peephole(ir.into_x86(self.name())?)))
+    }
+}
+
+/// Selects passes by name, e.g. the passes around which the IR is dumped.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub enum PassFilter {
+    #[default]
+    None,
+    All,
+    Passes(Vec<String>),
+}
+
+impl PassFilter {
+    fn matches(&self, pass: &str) -> bool {
+        match self {
+            PassFilter::None => false,
+            PassFilter::All => true,
+            PassFilter::Passes(passes) => passes.iter().any(|name| name == pass),
+        }
+    }
+
+    fn add(&mut self, passes: &str) {
+        for pass in passes.split(',') {
+            match self {
+                _ if pass == "all" => *self = PassFilter::All,
+                PassFilter::All => (),
+                PassFilter::None => *self = PassFilter::Passes(vec![pass.to_string()]),
+                PassFilter::Passes(names) => names.push(pass.to_string()),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct PassManagerOptions {
+    pub dump_before: PassFilter,
+    pub dump_after: PassFilter,
+    // Whether the verifier runs after every pass.
+    pub verify: bool,
+    // The optional optimization passes that are added to the default pipeline.
+    pub optimize: PassFilter,
+}
+
+impl PassManagerOptions {
+    /// Parses `--dump-before=<passes>`, `--dump-after=<passes>`, `--optimize=<passes>` and
+    /// `--verify`, where `<passes>` is a comma-separated list of pass names or `all`. Returns the
+    /// arguments that are not options of the pass manager.
+    pub fn parse_args<'a>(&mut self, args: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+        let mut rest = Vec::new();
+        for arg in args {
+            if let Some(passes) = arg.strip_prefix("--dump-before=") {
+                self.dump_before.add(passes);
+            } else if let Some(passes) = arg.strip_prefix("--dump-after=") {
+                self.dump_after.add(passes);
+            } else if let Some(passes) = arg.strip_prefix("--optimize=") {
+                self.optimize.add(passes);
+            } else if arg == "--verify" {
+                self.verify = true;
+            } else {
+                rest.push(arg);
+            }
+        }
+        rest
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum DumpPoint {
+    Before,
+    After,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct IrDump {
+    pub pass: String,
+    pub point: DumpPoint,
+    pub ir: String,
+}
+
+impl Display for IrDump {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let point = match self.point {
+            DumpPoint::Before => "before",
+            DumpPoint::After => "after",
+        };
+        writeln!(f, ";; IR dump {} {}", point, self.pass)?;
+        write!(f, "{}", self.ir)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct PassStatistics {
+    pub pass: String,
+    pub duration: Duration,
+    pub size_before: usize,
+    pub size_after: usize,
+}
+
+impl Display for PassStatistics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<24} {:>10.3}ms {:>6} -> {}",
+            self.pass,
+            self.duration.as_secs_f64() * 1000.0,
+            self.size_before,
+            self.size_after
+        )
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Severity {
+    // Information for debugging the passes, e.g. the rewrite rules that fired.
+    Note,
+    // A problem in the program, which does not stop the compilation.
+    Warning,
+}
+
+/// A message of a pass about the program it compiled.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct PassDiagnostic {
+    pub pass: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Display for PassDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{} in {}: {}", severity, self.pass, self.message)
+    }
+}
+
+/// Checks the output of a pass, whose form is given by the passes that have run, and describes the
+/// problem if it is malformed.
+pub type Verifier = dyn FnMut(&str, &Ir, IrForm) -> Result<(), String>;
+
+/// Runs a pipeline of passes, and records the IR dumps, the statistics and the diagnostics of the
+/// last run.
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    options: PassManagerOptions,
+    verifier: Option<Box<Verifier>>,
+    dumps: Vec<IrDump>,
+    statistics: Vec<PassStatistics>,
+    diagnostics: Vec<PassDiagnostic>,
+}
+
+impl PassManager {
+    pub fn new(options: PassManagerOptions) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+
+    /// Creates a pass manager that runs every pass of the compiler, from the source program to
+    /// the x86 program, with `pipeline_verifier` as its verifier. The `range_analysis` pass comes
+    /// first, and warns about the operations that always overflow. The optimization passes
+    /// selected by `PassManagerOptions::optimize` are added at their place in the pipeline.
+    pub fn with_default_pipeline(
+        options: PassManagerOptions,
+        semantics: ArithmeticSemantics,
+    ) -> Self {
+        let optimize = options.optimize.clone();
+        let mut result = Self::new(options);
+        result.add_pass(AnalyzeRanges {
+            semantics,
+            warnings: Vec::new(),
+        });
+        if optimize.matches("partial_eval") {
+            result.add_pass(PartialEvaluate { semantics });
+        }
+        result.add_pass(Uniquify);
+        if optimize.matches("rewrite") {
+            result.add_pass(Rewrite {
+                semantics,
+                steps: Vec::new(),
+            });
+        }
+        result.add_pass(RemoveComplexOperands);
+        result.add_pass(ExplicateControl);
+        if optimize.matches("local_value_numbering") {
+            result.add_pass(LocalValueNumbering);
+        }
+        // The SSA form only exists around the optimizations that work on it.
+        if optimize.matches("sparse_constant_propagation") {
+            result.add_pass(ConstructSsa);
+            result.add_pass(SparseConstantPropagation { semantics });
+            result.add_pass(DestructSsa);
+        }
+        if optimize.matches("copy_propagation") {
+            result.add_pass(CopyPropagation { semantics });
+        }
+        result.add_pass(SelectInstructions { semantics });
+        result.add_pass(AssignHomes);
+        result.add_pass(PatchInstructions);
+        if optimize.matches("block_layout") {
+            result.add_pass(BlockLayout);
+        }
+        if optimize.matches("peephole") {
+            result.add_pass(Peephole);
+        }
+        result.set_verifier(pipeline_verifier);
+        result
+    }
+
+    pub fn pass_names(&self) -> Vec<&str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    pub fn add_pass(&mut self, pass: impl Pass + 'static) {
+        self.passes.push(Box::new(pass));
+    }
+
+    fn find_pass(&self, name: &str) -> Result<usize, PassError> {
+        self.passes
+            .iter()
+            .position(|pass| pass.name() == name)
+            .ok_or_else(|| PassError::UnknownPass(name.to_string()))
+    }
+
+    pub fn insert_before(
+        &mut self,
+        name: &str,
+        pass: impl Pass + 'static,
+    ) -> Result<(), PassError> {
+        let index = self.find_pass(name)?;
+        self.passes.insert(index, Box::new(pass));
+        Ok(())
+    }
+
+    pub fn insert_after(&mut self, name: &str, pass: impl Pass + 'static) -> Result<(), PassError> {
+        let index = self.find_pass(name)?;
+        self.passes.insert(index + 1, Box::new(pass));
+        Ok(())
+    }
+
+    /// Sets the verifier that runs after every pass if `PassManagerOptions::verify` is set.
+    pub fn set_verifier(
+        &mut self,
+        verifier: impl FnMut(&str, &Ir, IrForm) -> Result<(), String> + 'static,
+    ) {
+        self.verifier = Some(Box::new(verifier));
+    }
+
+    pub fn dumps(&self) -> &[IrDump] {
+        &self.dumps
+    }
+
+    pub fn statistics(&self) -> &[PassStatistics] {
+        &self.statistics
+    }
+
+    pub fn diagnostics(&self) -> &[PassDiagnostic] {
+        &self.diagnostics
+    }
+
+    fn check_pass_filter(&self, filter: &PassFilter) -> Result<(), PassError> {
+        if let PassFilter::Passes(names) = filter {
+            for name in names {
+                self.find_pass(name)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn dump(&mut self, pass: &str, point: DumpPoint, ir: &Ir) {
+        let filter = match point {
+            DumpPoint::Before => &self.options.dump_before,
+            DumpPoint::After => &self.options.dump_after,
+        };
+        if filter.matches(pass) {
+            self.dumps.push(IrDump {
+                pass: pass.to_string(),
+                point,
+                ir: ir.to_string(),
+            });
+        }
+    }
+
+    fn run_pass(
+        &mut self,
+        pass: &mut dyn Pass,
+        (ir, form): (Ir, IrForm),
+    ) -> Result<(Ir, IrForm), PassError> {
+        let name = pass.name().to_string();
+        self.dump(&name, DumpPoint::Before, &ir);
+
+        let size_before = ir.size();
+        let start = Instant::now();
+        let ir = pass.run(ir)?;
+        let form = pass.output_form(form);
+        self.statistics.push(PassStatistics {
+            pass: name.clone(),
+            duration: start.elapsed(),
+            size_before,
+            size_after: ir.size(),
+        });
+        self.diagnostics.extend(
+            pass.take_diagnostics()
+                .into_iter()
+                .map(|(severity, message)| PassDiagnostic {
+                    pass: name.clone(),
+                    severity,
+                    message,
+                }),
+        );
+
+        self.dump(&name, DumpPoint::After, &ir);
+
+        if let Some(verifier) = &mut self.verifier {
+            if self.options.verify {
+                verifier(&name, &ir, form).map_err(|message| PassError::VerificationFailed {
+                    pass: name,
+                    message,
+                })?;
+            }
+        }
+
+        Ok((ir, form))
+    }
+
+    /// Runs every pass in order. The dumps, the statistics and the diagnostics of the passes that
+    /// have run are kept even if a pass fails.
+    pub fn run(&mut self, ir: Ir) -> Result<Ir, PassError> {
+        self.run_with_hook(ir, |_, _, _| Ok(()))
+    }
+
+    /// Like `run`, but calls `hook` with the name of every pass, its output and the form of its
+    /// output once it has been verified. Stops at the first error of a pass or of `hook`.
+    pub fn run_with_hook<E: From<PassError>>(
+        &mut self,
+        ir: Ir,
+        mut hook: impl FnMut(&str, &Ir, IrForm) -> Result<(), E>,
+    ) -> Result<Ir, E> {
+        self.dumps.clear();
+        self.statistics.clear();
+        self.diagnostics.clear();
+        self.check_pass_filter(&self.options.dump_before)?;
+        self.check_pass_filter(&self.options.dump_after)?;
+        self.check_pass_filter(&self.options.optimize)?;
+
+        let mut passes = std::mem::take(&mut self.passes);
+        let result = passes
+            .iter_mut()
+            .try_fold((ir, IrForm::default()), |ir, pass| {
+                let (ir, form) = self.run_pass(pass.as_mut(), ir)?;
+                hook(pass.name(), &ir, form)?;
+                Ok((ir, form))
+            });
+        self.passes = passes;
+        result.map(|(ir, _)| ir)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use frontend::parse_expr;
+
+    use super::*;
+
+    fn source(code: &str) -> Ir {
+        Ir::Source(parse_expr(code).unwrap())
+    }
+
+    #[test]
+    fn run_default_pipeline() {
+        let mut manager = PassManager::with_default_pipeline(
+            PassManagerOptions::default(),
+            ArithmeticSemantics::Checked,
+        );
+        assert_eq!(
+            manager.pass_names(),
+            [
+                "range_analysis",
+                "uniquify",
+                "remove_complex_operands",
+                "explicate_control",
+                "select_instructions",
+                "assign_homes",
+                "patch_instructions"
+            ]
+        );
+
+        let result = manager.run(source("(let ([x read]) (- x 1))")).unwrap();
+        assert_eq!(
+            result.to_string().trim(),
+            r#"
+frame: 16
+main:
+    callq   read_int
+    movq    %rax, -8(%rbp)
+    movq    -8(%rbp), %rax
+    decq    %rax
+    jmp     conclusion
+conclusion:
+            "#
+            .trim()
+        );
+
+        let sizes: Vec<_> = manager
+            .statistics()
+            .iter()
+            .map(|statistics| (statistics.size_before, statistics.size_after))
+            .collect();
+        assert_eq!(
+            sizes,
+            [(5, 5), (5, 5), (5, 5), (5, 2), (2, 5), (5, 5), (5, 5)]
+        );
+        assert!(manager.dumps().is_empty());
+    }
+
+    #[test]
+    fn dump_ir() {
+        let mut options = PassManagerOptions::default();
+        let rest = options.parse_args([
+            "--dump-before=uniquify",
+            "program.rkt",
+            "--dump-after=explicate_control,assign_homes",
+            "--verify",
+        ]);
+        assert_eq!(rest, ["program.rkt"]);
+        assert!(options.verify);
+
+        let mut manager = PassManager::with_default_pipeline(options, ArithmeticSemantics::Checked);
+        manager.run(source("(let ([x 1]) (+ x read))")).unwrap();
+        let dumps: Vec<_> = manager.dumps().iter().map(IrDump::to_string).collect();
+        assert_eq!(
+            dumps.join(""),
+            r#";; IR dump before uniquify
+(let ([x 1]) (+ x read))
+;; IR dump after explicate_control
+local: [x0, tmp0]
+start:
+    x0 = 1;
+    tmp0 = read;
+    return (+ x0 tmp0);
+;; IR dump after assign_homes
+frame: 16
+main:
+    movq    $0x1, -8(%rbp)
+    callq   read_int
+    movq    %rax, -16(%rbp)
+    movq    -8(%rbp), %rax
+    addq    -16(%rbp), %rax
+    jmp     conclusion
+conclusion:
+"#
+        );
+
+        let mut options = PassManagerOptions::default();
+        options.parse_args(["--dump-after=all", "--dump-after=uniquify"]);
+        assert_eq!(options.dump_after, PassFilter::All);
+
+        let mut options = PassManagerOptions::default();
+        options.parse_args(["--dump-after=unknown"]);
+        assert_eq!(
+            PassManager::with_default_pipeline(options, ArithmeticSemantics::Checked)
+                .run(source("1")),
+            Err(PassError::UnknownPass("unknown".to_string()))
+        );
+    }
+
+    #[test]
+    fn optimization_passes() {
+        let mut options = PassManagerOptions::default();
+        options.parse_args(["--optimize=all"]);
+        let mut manager = PassManager::with_default_pipeline(options, ArithmeticSemantics::Checked);
+        assert_eq!(
+            manager.pass_names(),
+            [
+                "range_analysis",
+                "partial_eval",
+                "uniquify",
+                "rewrite",
+                "remove_complex_operands",
+                "explicate_control",
+                "local_value_numbering",
+                "construct_ssa",
+                "sparse_constant_propagation",
+                "destruct_ssa",
+                "copy_propagation",
+                "select_instructions",
+                "assign_homes",
+                "patch_instructions",
+                "block_layout",
+                "peephole"
+            ]
+        );
+        assert_eq!(
+            manager
+                .run(source("(+ 10 (- (+ 5 3)))"))
+                .unwrap()
+                .to_string(),
+            "main:\n    movq    $0x2, %rax\nconclusion:\n"
+        );
+
+        let mut options = PassManagerOptions::default();
+        options.parse_args([
+            "--optimize=copy_propagation",
+            "--dump-after=copy_propagation",
+        ]);
+        let mut manager = PassManager::with_default_pipeline(options, ArithmeticSemantics::Checked);
+        manager
+            .run(source("(let ([x read]) (let ([y x]) (+ y 1)))"))
+            .unwrap();
+        assert_eq!(
+            manager.dumps()[0].to_string(),
+            ";; IR dump after copy_propagation\nlocal: [x0]\nstart:\n    x0 = read;\n    return (+ x0 1);\n"
+        );
+
+        let mut options = PassManagerOptions::default();
+        options.parse_args(["--optimize=unknown"]);
+        assert_eq!(
+            PassManager::with_default_pipeline(options, ArithmeticSemantics::Checked)
+                .run(source("1")),
+            Err(PassError::UnknownPass("unknown".to_string()))
+        );
+    }
+
+    #[test]
+    fn custom_passes() {
+        let mut manager = PassManager::with_default_pipeline(
+            PassManagerOptions::default(),
+            ArithmeticSemantics::Checked,
+        );
+
+        // Replaces every program by `42`.
+        let constant = FnPass::new("constant", |ir: Ir| {
+            ir.into_source("constant")?;
+            Ok(Ir::Source(Expr::Integer(42)))
+        });
+        manager.insert_after("uniquify", constant).unwrap();
+        assert_eq!(
+            manager.pass_names()[1..4],
+            ["uniquify", "constant", "remove_complex_operands"]
+        );
+        assert_eq!(
+            manager.run(source("(+ 1 read)")).unwrap().to_string(),
+            "main:\n    movq    $0x2a, %rax\n    jmp     conclusion\nconclusion:\n"
+        );
+
+        // A pass that produces the wrong IR is reported by the next pass.
+        let to_x86 = FnPass::new("to_x86", |_| Ok(Ir::X86(VarProgram::new())));
+        manager.insert_before("explicate_control", to_x86).unwrap();
+        assert_eq!(
+            manager.run(source("1")),
+            Err(PassError::UnexpectedIr {
+                pass: "explicate_control".to_string(),
+                expected: IrKind::Source,
+                found: IrKind::X86
+            })
+        );
+
+        assert_eq!(
+            manager.insert_before("missing", FnPass::new("nothing", Ok)),
+            Err(PassError::UnknownPass("missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn run_with_hook() {
+        let mut manager = PassManager::with_default_pipeline(
+            PassManagerOptions::default(),
+            ArithmeticSemantics::Checked,
+        );
+        let mut forms = Vec::new();
+        let result = manager.run_with_hook(source("(+ 1 2)"), |pass, _, form| {
+            forms.push((pass.to_string(), form.monadic, form.x86_stage));
+            Ok::<_, PassError>(())
+        });
+        assert!(result.is_ok());
+        assert_eq!(
+            forms,
+            [
+                ("range_analysis", false, X86Stage::Selected),
+                ("uniquify", false, X86Stage::Selected),
+                ("remove_complex_operands", true, X86Stage::Selected),
+                ("explicate_control", true, X86Stage::Selected),
+                ("select_instructions", true, X86Stage::Selected),
+                ("assign_homes", true, X86Stage::HomesAssigned),
+                ("patch_instructions", true, X86Stage::Patched),
+            ]
+            .map(|(pass, monadic, stage)| (pass.to_string(), monadic, stage))
+        );
+
+        // An error of the hook stops the pipeline.
+        let error = PassError::UnknownPass("stop".to_string());
+        let result = manager.run_with_hook(source("(+ 1 2)"), |pass, _, _| match pass {
+            "uniquify" => Err(error.clone()),
+            _ => Ok(()),
+        });
+        assert_eq!(result, Err(error));
+        assert_eq!(manager.statistics().len(), 2);
+    }
+
+    #[test]
+    fn verify_passes() {
+        let options = PassManagerOptions {
+            verify: true,
+            ..PassManagerOptions::default()
+        };
+        let mut manager = PassManager::with_default_pipeline(options, ArithmeticSemantics::Checked);
+        // Rejects x86 programs that still use variables.
+        manager.set_verifier(|_, ir, _| match ir {
+            Ir::X86(program) if !program.local_variables.is_empty() => {
+                Err(format!("locals {:?}", program.local_variables))
+            }
+            _ => Ok(()),
+        });
+
+        assert_eq!(
+            manager.run(source("(let ([x 1]) x)")),
+            Err(PassError::VerificationFailed {
+                pass: "select_instructions".to_string(),
+                message: "locals [\"x0\"]".to_string()
+            })
+        );
+        // The statistics of the passes that have run are kept.
+        assert_eq!(manager.statistics().len(), 5);
+    }
+
+    #[test]
+    fn overflow_warnings() {
+        let mut manager = PassManager::with_default_pipeline(
+            PassManagerOptions::default(),
+            ArithmeticSemantics::Wrapping,
+        );
+        let code = "(let ([x 9223372036854775807]) (+ read (+ x 1)))";
+        assert!(manager.run(source(code)).is_ok());
+        assert_eq!(
+            manager
+                .diagnostics()
+                .iter()
+                .map(PassDiagnostic::to_string)
+                .collect::<Vec<_>>(),
+            ["warning in range_analysis: `(+ x 1)` always overflows"]
+        );
+
+        // The diagnostics are those of the last run.
+        assert!(manager.run(source("(+ read 1)")).is_ok());
+        assert!(manager.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn rewrite_steps() {
+        let mut options = PassManagerOptions::default();
+        options.parse_args(["--optimize=rewrite"]);
+        let mut manager = PassManager::with_default_pipeline(options, ArithmeticSemantics::Checked);
+        assert!(manager.run(source("(- (+ read 0) 0)")).is_ok());
+        assert_eq!(
+            manager
+                .diagnostics()
+                .iter()
+                .map(PassDiagnostic::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "note in rewrite: sub_zero: (- (+ read 0) 0) => (+ read 0)",
+                "note in rewrite: add_zero_right: (+ read 0) => read"
+            ]
+        );
+    }
+}
@@ -1,5 +1,41 @@
 use crate::ir::x86::{Reg, VarArg, VarBlock, VarInstr, VarProgram};
 
+fn is_memory(arg: &VarArg) -> bool {
+    matches!(arg, VarArg::Deref(..))
+}
+
+/// Checks whether `arg` is an immediate that cannot be encoded as a sign-extended 32-bit value,
+/// which is the only form of immediates accepted by most instructions.
+fn is_large_immediate(arg: &VarArg) -> bool {
+    matches!(arg, VarArg::Imm(value) if i32::try_from(*value).is_err())
+}
+
+fn needs_scratch(lhs: &VarArg, rhs: &VarArg) -> bool {
+    (is_memory(lhs) && is_memory(rhs)) || is_large_immediate(rhs)
+}
+
+/// Moves `rhs` to a scratch register, and adds the instruction built by `build_instr` that uses
+/// it instead of `rhs`.
+fn patch_arithmetic(
+    lhs: VarArg,
+    rhs: VarArg,
+    build_instr: fn(VarArg, VarArg) -> VarInstr,
+    result: &mut VarBlock,
+) {
+    // The result of a `return` is computed in %rax, so another scratch register is needed in this
+    // case.
+    let scratch = if lhs == VarArg::Reg(Reg::RAX) {
+        VarArg::Reg(Reg::R11)
+    } else {
+        VarArg::Reg(Reg::RAX)
+    };
+    result.add_instr(VarInstr::Movq {
+        from: rhs,
+        to: scratch.clone(),
+    });
+    result.add_instr(build_instr(lhs, scratch));
+}
+
 fn transform_block(block: VarBlock) -> VarBlock {
     let mut result = VarBlock::new(block.label);
 
@@ -7,89 +43,33 @@ fn transform_block(block: VarBlock) -> VarBlock {
         .instructions
         .into_iter()
         .for_each(|instr| match instr {
-            VarInstr::Addq {
-                lhs: VarArg::Deref(reg_lhs, offset_lhs),
-                rhs: VarArg::Deref(reg_rhs, offset_rhs),
-            } => {
+            // Only `movq` to a register can take a 64-bit immediate.
+            VarInstr::Movq { from, to }
+                if is_memory(&to) && (is_memory(&from) || is_large_immediate(&from)) =>
+            {
                 result.add_instr(VarInstr::Movq {
-                    from: VarArg::Deref(reg_rhs, offset_rhs),
-                    to: VarArg::Reg(Reg::RAX),
-                });
-                result.add_instr(VarInstr::Addq {
-                    lhs: VarArg::Deref(reg_lhs, offset_lhs),
-                    rhs: VarArg::Reg(Reg::RAX),
-                });
-            }
-
-            VarInstr::Subq {
-                lhs: VarArg::Deref(reg_lhs, offset_lhs),
-                rhs: VarArg::Deref(reg_rhs, offset_rhs),
-            } => {
-                result.add_instr(VarInstr::Movq {
-                    from: VarArg::Deref(reg_rhs, offset_rhs),
-                    to: VarArg::Reg(Reg::RAX),
-                });
-                result.add_instr(VarInstr::Subq {
-                    lhs: VarArg::Deref(reg_lhs, offset_lhs),
-                    rhs: VarArg::Reg(Reg::RAX),
-                });
-            }
-
-            VarInstr::Movq {
-                from: VarArg::Deref(reg_rhs, offset_rhs),
-                to: VarArg::Deref(reg_lhs, offset_lhs),
-            } => {
-                result.add_instr(VarInstr::Movq {
-                    from: VarArg::Deref(reg_rhs, offset_rhs),
+                    from,
                     to: VarArg::Reg(Reg::RAX),
                 });
                 result.add_instr(VarInstr::Movq {
                     from: VarArg::Reg(Reg::RAX),
-                    to: VarArg::Deref(reg_lhs, offset_lhs),
+                    to,
                 });
             }
 
-            VarInstr::Addq {
-                lhs: VarArg::Deref(reg_lhs, offset_lhs),
-                rhs: VarArg::Imm(value),
-            } if value > 0x10000 => {
-                result.add_instr(VarInstr::Movq {
-                    from: VarArg::Imm(value),
-                    to: VarArg::Reg(Reg::RAX),
-                });
-                result.add_instr(VarInstr::Addq {
-                    lhs: VarArg::Deref(reg_lhs, offset_lhs),
-                    rhs: VarArg::Reg(Reg::RAX),
-                });
-            }
-
-            VarInstr::Subq {
-                lhs: VarArg::Deref(reg_lhs, offset_lhs),
-                rhs: VarArg::Imm(value),
-            } if value > 0x10000 => {
-                result.add_instr(VarInstr::Movq {
-                    from: VarArg::Imm(value),
-                    to: VarArg::Reg(Reg::RAX),
-                });
-                result.add_instr(VarInstr::Subq {
-                    lhs: VarArg::Deref(reg_lhs, offset_lhs),
-                    rhs: VarArg::Reg(Reg::RAX),
-                });
-            }
+            VarInstr::Addq { lhs, rhs } if needs_scratch(&lhs, &rhs) => patch_arithmetic(
+                lhs,
+                rhs,
+                |lhs, rhs| VarInstr::Addq { lhs, rhs },
+                &mut result,
+            ),
 
-            VarInstr::Movq {
-                from: VarArg::Imm(value),
-                to: VarArg::Deref(reg_lhs, offset_lhs),
-            } if value > 0x10000 => {
-                result.add_instr(VarInstr::Movq {
-                    from: VarArg::Imm(value),
-                    to: VarArg::Reg(Reg::RAX),
-                });
-                result.add_instr(VarInstr::Addq {
-                    lhs: VarArg::Deref(reg_lhs, offset_lhs),
-                    rhs: VarArg::Reg(Reg::RAX),
-                });
-            }
+            VarInstr::Subq { lhs, rhs } if needs_scratch(&lhs, &rhs) => patch_arithmetic(
+                lhs,
+                rhs,
+                |lhs, rhs| VarInstr::Subq { lhs, rhs },
+                &mut result,
+            ),
 
             other => result.add_instr(other),
         });
@@ -97,14 +77,28 @@ fn transform_block(block: VarBlock) -> VarBlock {
     result
 }
 
+/// Returns the number of instructions that `instr` is patched into, once its variables are
+/// assigned homes on the stack.
+pub(crate) fn patched_len(instr: &VarInstr) -> usize {
+    let mut instr = instr.clone();
+    for operand in instr.operands_mut() {
+        if let VarArg::Variable(_) = operand {
+            *operand = VarArg::Deref(Reg::RBP, -8);
+        }
+    }
+    transform_block(VarBlock {
+        label: String::new(),
+        instructions: vec![instr],
+    })
+    .instructions
+    .len()
+}
+
 pub(crate) fn patch_instructions(program: VarProgram) -> VarProgram {
     VarProgram {
         local_variables: program.local_variables,
-        body: program
-            .body
-            .into_iter()
-            .map(|block| transform_block(block))
-            .collect(),
+        body: program.body.into_iter().map(transform_block).collect(),
+        frame_size: program.frame_size,
     }
 }
 
@@ -119,6 +113,7 @@ mod test {
                 label: "test".to_string(),
                 instructions,
             }],
+            frame_size: 0,
         }
     }
 
@@ -138,7 +133,24 @@ mod test {
                 },
                 VarInstr::Addq {
                     lhs: Deref(Reg::RBP, -40),
-                    rhs: Imm(65537)
+                    rhs: Imm(0x1_0000_0000)
+                },
+                VarInstr::Subq {
+                    lhs: Reg::RAX.into(),
+                    rhs: Imm(i64::MIN)
+                },
+                VarInstr::Movq {
+                    from: Imm(-0x8000_0001),
+                    to: Deref(Reg::RBP, -48)
+                },
+                // These immediates can be encoded.
+                VarInstr::Addq {
+                    lhs: Deref(Reg::RBP, -56),
+                    rhs: Imm(i32::MIN as i64)
+                },
+                VarInstr::Movq {
+                    from: Imm(i64::MAX),
+                    to: Reg::RAX.into()
                 },
             ]))
             .body[0]
@@ -161,13 +173,37 @@ mod test {
                     rhs: Reg::RAX.into(),
                 },
                 VarInstr::Movq {
-                    from: Imm(65537),
+                    from: Imm(0x1_0000_0000),
                     to: Reg::RAX.into(),
                 },
                 VarInstr::Addq {
                     lhs: Deref(Reg::RBP, -40),
                     rhs: Reg::RAX.into(),
                 },
+                VarInstr::Movq {
+                    from: Imm(i64::MIN),
+                    to: Reg::R11.into(),
+                },
+                VarInstr::Subq {
+                    lhs: Reg::RAX.into(),
+                    rhs: Reg::R11.into(),
+                },
+                VarInstr::Movq {
+                    from: Imm(-0x8000_0001),
+                    to: Reg::RAX.into(),
+                },
+                VarInstr::Movq {
+                    from: Reg::RAX.into(),
+                    to: Deref(Reg::RBP, -48)
+                },
+                VarInstr::Addq {
+                    lhs: Deref(Reg::RBP, -56),
+                    rhs: Imm(i32::MIN as i64)
+                },
+                VarInstr::Movq {
+                    from: Imm(i64::MAX),
+                    to: Reg::RAX.into()
+                },
             ]
         );
     }
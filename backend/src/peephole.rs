@@ -0,0 +1,647 @@
+use std::collections::HashSet;
+
+use crate::ir::x86::{Reg, VarArg, VarInstr, VarProgram};
+
+// How an instruction affects the value that is tracked by a liveness query.
+enum Effect {
+    // The value is read, so it is live.
+    Read,
+    // The value is overwritten before it is read, so it is dead.
+    Overwritten,
+    Unaffected,
+}
+
+// A change to the instructions of a block.
+enum Edit {
+    Remove(usize),
+    Replace(usize, VarInstr),
+}
+
+/// Checks whether writing `lhs` may change the value of `rhs`, where the memory operands are
+/// 8-byte cells, and cells relative to different registers may overlap.
+fn may_alias(lhs: &VarArg, rhs: &VarArg) -> bool {
+    match (lhs, rhs) {
+        (VarArg::Deref(lhs_reg, lhs_offset), VarArg::Deref(rhs_reg, rhs_offset)) => {
+            lhs_reg != rhs_reg || (lhs_offset - rhs_offset).abs() < 8
+        }
+        _ => lhs == rhs,
+    }
+}
+
+/// Checks whether evaluating the operand `operand` reads `location`, either as its value or as
+/// the register of its address.
+fn reads(operand: &VarArg, location: &VarArg) -> bool {
+    match operand {
+        VarArg::Deref(reg, _) if *location == VarArg::Reg(*reg) => true,
+        _ => may_alias(operand, location),
+    }
+}
+
+fn writes_flags(instr: &VarInstr) -> bool {
+    matches!(
+        instr,
+        VarInstr::Addq { .. }
+            | VarInstr::Subq { .. }
+            | VarInstr::Negq { .. }
+            | VarInstr::Incq { .. }
+            | VarInstr::Decq { .. }
+            | VarInstr::Xorq { .. }
+            | VarInstr::Callq { .. }
+    )
+}
+
+fn flags_effect(instr: &VarInstr) -> Effect {
+    match instr {
+        VarInstr::Jo { .. } => Effect::Read,
+        _ if writes_flags(instr) => Effect::Overwritten,
+        _ => Effect::Unaffected,
+    }
+}
+
+/// The effect of `instr` on `location`, which is a register other than %rsp and %rbp, or a cell
+/// of the stack frame.
+fn location_effect(instr: &VarInstr, location: &VarArg) -> Effect {
+    let is_read = |operand| reads(operand, location);
+    match instr {
+        VarInstr::Movq { from, to } => {
+            if is_read(from)
+                || matches!(to, VarArg::Deref(reg, _) if *location == VarArg::Reg(*reg))
+            {
+                Effect::Read
+            } else if to == location {
+                Effect::Overwritten
+            } else if may_alias(to, location) {
+                // Only a part of the cell is overwritten.
+                Effect::Read
+            } else {
+                Effect::Unaffected
+            }
+        }
+        VarInstr::Addq { lhs, rhs } | VarInstr::Subq { lhs, rhs } | VarInstr::Xorq { lhs, rhs } => {
+            if is_read(lhs) || is_read(rhs) {
+                Effect::Read
+            } else {
+                Effect::Unaffected
+            }
+        }
+        VarInstr::Negq { operand }
+        | VarInstr::Incq { operand }
+        | VarInstr::Decq { operand }
+        | VarInstr::Pushq { operand }
+            if is_read(operand) =>
+        {
+            Effect::Read
+        }
+        // `popq` reads the top of the stack, which may be in the frame.
+        VarInstr::Popq { .. } => Effect::Read,
+        // The callee may read its arguments from registers, but never reads the stack frame.
+        VarInstr::Callq { .. } if matches!(location, VarArg::Reg(_)) => Effect::Read,
+        _ => Effect::Unaffected,
+    }
+}
+
+struct PeepholeImpl<'p> {
+    program: &'p VarProgram,
+}
+
+impl PeepholeImpl<'_> {
+    /// Checks whether a value may be read after the instruction at `index` of a block, by
+    /// following every path from it until `effect` reports that the value is read or
+    /// overwritten. `live_at_exit` tells whether the value is read when the program ends.
+    fn is_live_after(
+        &self,
+        block: usize,
+        index: usize,
+        effect: impl Fn(&VarInstr) -> Effect,
+        live_at_exit: bool,
+    ) -> bool {
+        let find_block = |label: &str| {
+            self.program
+                .body
+                .iter()
+                .position(|block| block.label == label)
+        };
+
+        let mut visited = HashSet::new();
+        let mut worklist = vec![(block, index + 1)];
+        while let Some((block, start)) = worklist.pop() {
+            let mut falls_through = true;
+            for instr in &self.program.body[block].instructions[start..] {
+                match effect(instr) {
+                    Effect::Read => return true,
+                    Effect::Overwritten => {
+                        falls_through = false;
+                        break;
+                    }
+                    Effect::Unaffected => (),
+                }
+
+                match instr {
+                    VarInstr::Jmp { target } | VarInstr::Jo { target } => {
+                        // A jump to an unknown label is reported by `verify_x86`.
+                        match find_block(target) {
+                            Some(target) if visited.insert(target) => worklist.push((target, 0)),
+                            Some(_) => (),
+                            None => return true,
+                        }
+                        if let VarInstr::Jmp { .. } = instr {
+                            falls_through = false;
+                            break;
+                        }
+                    }
+                    VarInstr::Retq if live_at_exit => return true,
+                    VarInstr::Retq => {
+                        falls_through = false;
+                        break;
+                    }
+                    _ => (),
+                }
+            }
+
+            if falls_through {
+                if block + 1 < self.program.body.len() {
+                    if visited.insert(block + 1) {
+                        worklist.push((block + 1, 0));
+                    }
+                } else if live_at_exit {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    fn are_flags_live_after(&self, block: usize, index: usize) -> bool {
+        self.is_live_after(block, index, flags_effect, false)
+    }
+
+    /// Checks whether the value of `location` may be read after the instruction at `index`. Only
+    /// the cells of the stack frame are dead when the program ends, since the registers are
+    /// visible to the caller.
+    fn is_location_live_after(&self, block: usize, index: usize, location: &VarArg) -> bool {
+        match location {
+            VarArg::Reg(Reg::RSP | Reg::RBP) | VarArg::Imm(_) | VarArg::Variable(_) => true,
+            VarArg::Deref(reg, _) if *reg != Reg::RBP => true,
+            _ => self.is_live_after(
+                block,
+                index,
+                |instr| location_effect(instr, location),
+                matches!(location, VarArg::Reg(_)),
+            ),
+        }
+    }
+
+    /// Looks for a rewrite of the window of instructions that starts at `index`.
+    fn find_rewrite(&self, block: usize, index: usize) -> Option<Vec<Edit>> {
+        let instructions = &self.program.body[block].instructions;
+        match &instructions[index] {
+            // `movq a, a`
+            VarInstr::Movq { from, to } if from == to => Some(vec![Edit::Remove(index)]),
+
+            // `movq a, b; movq b, a`, where the second instruction writes the value that `a`
+            // already holds. This is not the case if `b` is the register of the address of `a`.
+            VarInstr::Movq { from, to }
+                if !matches!(from, VarArg::Deref(reg, _) if *to == VarArg::Reg(*reg))
+                    && instructions.get(index + 1)
+                        == Some(&VarInstr::Movq {
+                            from: to.clone(),
+                            to: from.clone(),
+                        }) =>
+            {
+                Some(vec![Edit::Remove(index + 1)])
+            }
+
+            // `movq $0, r` becomes `xorq r, r`, which is shorter, but changes the flags.
+            VarInstr::Movq {
+                from: VarArg::Imm(0),
+                to: to @ VarArg::Reg(_),
+            } if !self.are_flags_live_after(block, index) => Some(vec![Edit::Replace(
+                index,
+                VarInstr::Xorq {
+                    lhs: to.clone(),
+                    rhs: to.clone(),
+                },
+            )]),
+
+            VarInstr::Negq { operand } => self.fuse_negation(block, index, operand),
+
+            _ => None,
+        }
+    }
+
+    /// Rewrites `negq a; ...; addq a, b` into `subq a, b` if the instructions in between are
+    /// moves that do not use `a`, and neither `a` nor the flags are read afterwards. Both compute
+    /// `b + (-a)` modulo 2^64, but they may set the overflow flag differently.
+    fn fuse_negation(&self, block: usize, index: usize, negated: &VarArg) -> Option<Vec<Edit>> {
+        let instructions = &self.program.body[block].instructions;
+        for (add_index, instr) in instructions.iter().enumerate().skip(index + 1) {
+            match instr {
+                VarInstr::Movq { from, to } if !reads(from, negated) && !reads(to, negated) => (),
+                VarInstr::Addq { lhs, rhs }
+                    if rhs == negated
+                        && !reads(lhs, negated)
+                        && !self.are_flags_live_after(block, add_index)
+                        && !self.is_location_live_after(block, add_index, negated) =>
+                {
+                    return Some(vec![
+                        Edit::Replace(
+                            add_index,
+                            VarInstr::Subq {
+                                lhs: lhs.clone(),
+                                rhs: rhs.clone(),
+                            },
+                        ),
+                        Edit::Remove(index),
+                    ]);
+                }
+                _ => return None,
+            }
+        }
+        None
+    }
+}
+
+/// Slides a window over the instructions of every block, and applies local rewrites that make
+/// the program shorter or cheaper. Removes the `jmp` at the end of a block that goes to the next
+/// block, since the execution falls through to it.
+pub(crate) fn peephole(mut program: VarProgram) -> VarProgram {
+    for block in 0..program.body.len() {
+        let mut index = 0;
+        while index < program.body[block].instructions.len() {
+            let edits = PeepholeImpl { program: &program }.find_rewrite(block, index);
+            match edits {
+                Some(edits) => {
+                    // The edits are ordered by decreasing index, so that they do not move each
+                    // other.
+                    let instructions = &mut program.body[block].instructions;
+                    for edit in edits {
+                        match edit {
+                            Edit::Remove(index) => {
+                                instructions.remove(index);
+                            }
+                            Edit::Replace(index, instr) => instructions[index] = instr,
+                        }
+                    }
+                    // A rewrite may create a new window with the previous instruction.
+                    index = index.saturating_sub(1);
+                }
+                None => index += 1,
+            }
+        }
+    }
+
+    for block in 1..program.body.len() {
+        let next_label = program.body[block].label.clone();
+        let instructions = &mut program.body[block - 1].instructions;
+        if instructions.last() == Some(&VarInstr::Jmp { target: next_label }) {
+            instructions.pop();
+        }
+    }
+
+    program
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use frontend::{parse_expr, ArithmeticSemantics, Evaluation};
+
+    use crate::{
+        assign_homes::assign_homes,
+        explicate_control::explicate_control,
+        interp::x86::{emulate_x86_with_options, EmulatorError, EmulatorOptions},
+        ir::x86::VarBlock,
+        patch_instructions::patch_instructions,
+        remove_complex_operands::remove_complex_operands,
+        select_instructions::select_instructions,
+        uniquify::uniquify_expr,
+        verify::{verify_x86, X86Stage},
+    };
+
+    use super::*;
+
+    use VarArg::{Deref, Imm};
+
+    // Stops the programs that loop forever, e.g. after a wrong layout.
+    fn emulate(
+        program: &VarProgram,
+        input: &mut VecDeque<i64>,
+    ) -> Result<Evaluation, EmulatorError> {
+        let options = EmulatorOptions { fuel: Some(10_000) };
+        emulate_x86_with_options(program, input, options)
+    }
+
+    fn rax() -> VarArg {
+        Reg::RAX.into()
+    }
+
+    fn jmp(target: &str) -> VarInstr {
+        VarInstr::Jmp {
+            target: target.to_string(),
+        }
+    }
+
+    fn program(blocks: Vec<(&str, Vec<VarInstr>)>) -> VarProgram {
+        VarProgram {
+            local_variables: Vec::new(),
+            body: blocks
+                .into_iter()
+                .map(|(label, instructions)| VarBlock {
+                    label: label.to_string(),
+                    instructions,
+                })
+                .collect(),
+            frame_size: 0,
+        }
+    }
+
+    /// Optimizes `program`, and checks that the result behaves like it on `inputs`.
+    fn optimize(program: VarProgram, inputs: &[i64]) -> String {
+        let optimized = peephole(program.clone());
+        let expected = emulate(&program, &mut VecDeque::from(inputs.to_vec()));
+        assert!(expected.is_ok());
+        assert_eq!(
+            emulate(&optimized, &mut VecDeque::from(inputs.to_vec())),
+            expected
+        );
+        optimized.to_string()
+    }
+
+    #[test]
+    fn remove_moves() {
+        let program = program(vec![
+            (
+                "main",
+                vec![
+                    // An address in the stack of the emulator.
+                    VarInstr::Movq {
+                        from: Imm(0x7ffe_ff00),
+                        to: Deref(Reg::RBP, -8),
+                    },
+                    VarInstr::Movq {
+                        from: Deref(Reg::RBP, -8),
+                        to: Deref(Reg::RBP, -8),
+                    },
+                    VarInstr::Movq {
+                        from: Deref(Reg::RBP, -8),
+                        to: rax(),
+                    },
+                    VarInstr::Movq {
+                        from: rax(),
+                        to: Deref(Reg::RBP, -8),
+                    },
+                    VarInstr::Movq {
+                        from: rax(),
+                        to: rax(),
+                    },
+                    // The address of the store depends on the loaded value.
+                    VarInstr::Movq {
+                        from: Deref(Reg::RBP, -8),
+                        to: Reg::RBP.into(),
+                    },
+                    VarInstr::Movq {
+                        from: Reg::RBP.into(),
+                        to: Deref(Reg::RBP, -8),
+                    },
+                ],
+            ),
+            ("conclusion", vec![]),
+        ]);
+        assert_eq!(
+            optimize(program, &[]).trim(),
+            r#"
+main:
+    movq    $0x7ffeff00, -8(%rbp)
+    movq    -8(%rbp), %rax
+    movq    -8(%rbp), %rbp
+    movq    %rbp, -8(%rbp)
+conclusion:
+            "#
+            .trim()
+        );
+    }
+
+    #[test]
+    fn clear_registers() {
+        let program = program(vec![
+            (
+                "main",
+                vec![
+                    VarInstr::Movq {
+                        from: Imm(0),
+                        to: Deref(Reg::RBP, -8),
+                    },
+                    VarInstr::Movq {
+                        from: Imm(9223372036854775807),
+                        to: rax(),
+                    },
+                    VarInstr::Addq {
+                        lhs: rax(),
+                        rhs: Imm(1),
+                    },
+                    // The overflow flag is read after this move, so it must be kept.
+                    VarInstr::Movq {
+                        from: Imm(0),
+                        to: Reg::RCX.into(),
+                    },
+                    VarInstr::Jo {
+                        target: "overflow".to_string(),
+                    },
+                    jmp("conclusion"),
+                ],
+            ),
+            (
+                "overflow",
+                vec![
+                    VarInstr::Movq {
+                        from: Imm(0),
+                        to: rax(),
+                    },
+                    VarInstr::Retq,
+                ],
+            ),
+            ("conclusion", vec![]),
+        ]);
+        assert_eq!(
+            optimize(program, &[]).trim(),
+            r#"
+main:
+    movq    $0x0, -8(%rbp)
+    movq    $0x7fffffffffffffff, %rax
+    addq    $0x1, %rax
+    movq    $0x0, %rcx
+    jo      overflow
+    jmp     conclusion
+overflow:
+    xorq    %rax, %rax
+    retq
+conclusion:
+            "#
+            .trim()
+        );
+    }
+
+    #[test]
+    fn fuse_negations() {
+        let negate = |operand| VarInstr::Negq { operand };
+        let add = |lhs, rhs| VarInstr::Addq { lhs, rhs };
+        let program = program(vec![
+            (
+                "main",
+                vec![
+                    VarInstr::Callq {
+                        callee: "read_int".to_string(),
+                    },
+                    VarInstr::Movq {
+                        from: rax(),
+                        to: Deref(Reg::RBP, -8),
+                    },
+                    VarInstr::Movq {
+                        from: Imm(3),
+                        to: Deref(Reg::RBP, -16),
+                    },
+                    negate(Deref(Reg::RBP, -8)),
+                    VarInstr::Movq {
+                        from: Imm(10),
+                        to: rax(),
+                    },
+                    add(rax(), Deref(Reg::RBP, -8)),
+                    // The negated value is read again.
+                    negate(Deref(Reg::RBP, -16)),
+                    add(rax(), Deref(Reg::RBP, -16)),
+                    jmp("next"),
+                ],
+            ),
+            (
+                "next",
+                vec![
+                    add(rax(), Deref(Reg::RBP, -16)),
+                    VarInstr::Movq {
+                        from: rax(),
+                        to: Deref(Reg::RBP, -24),
+                    },
+                    // The negated value is only overwritten.
+                    negate(Deref(Reg::RBP, -24)),
+                    add(rax(), Deref(Reg::RBP, -24)),
+                    VarInstr::Movq {
+                        from: Imm(1),
+                        to: Deref(Reg::RBP, -24),
+                    },
+                    jmp("conclusion"),
+                ],
+            ),
+            ("conclusion", vec![]),
+        ]);
+        assert_eq!(
+            optimize(program, &[4]).trim(),
+            r#"
+main:
+    callq   read_int
+    movq    %rax, -8(%rbp)
+    movq    $0x3, -16(%rbp)
+    movq    $0xa, %rax
+    subq    -8(%rbp), %rax
+    negq    -16(%rbp)
+    addq    -16(%rbp), %rax
+next:
+    addq    -16(%rbp), %rax
+    movq    %rax, -24(%rbp)
+    subq    -24(%rbp), %rax
+    movq    $0x1, -24(%rbp)
+conclusion:
+            "#
+            .trim()
+        );
+
+        // The overflow of the addition is checked, so the flags must stay the same.
+        let program = self::program(vec![
+            (
+                "main",
+                vec![
+                    VarInstr::Movq {
+                        from: Imm(i64::MIN),
+                        to: Reg::RCX.into(),
+                    },
+                    negate(Reg::RCX.into()),
+                    VarInstr::Movq {
+                        from: Imm(-1),
+                        to: rax(),
+                    },
+                    add(rax(), Reg::RCX.into()),
+                    VarInstr::Jo {
+                        target: "overflow".to_string(),
+                    },
+                    VarInstr::Retq,
+                ],
+            ),
+            ("overflow", vec![VarInstr::Retq]),
+        ]);
+        assert_eq!(peephole(program.clone()), program);
+    }
+
+    #[test]
+    fn remove_jumps() {
+        let program = program(vec![
+            (
+                "main",
+                vec![
+                    VarInstr::Movq {
+                        from: Imm(1),
+                        to: rax(),
+                    },
+                    jmp("second"),
+                ],
+            ),
+            (
+                "second",
+                vec![
+                    VarInstr::Addq {
+                        lhs: rax(),
+                        rhs: Imm(2),
+                    },
+                    jmp("third"),
+                ],
+            ),
+            ("skipped", vec![jmp("conclusion")]),
+            ("third", vec![jmp("conclusion")]),
+            ("conclusion", vec![]),
+        ]);
+        assert_eq!(
+            optimize(program, &[]).trim(),
+            r#"
+main:
+    movq    $0x1, %rax
+second:
+    addq    $0x2, %rax
+    jmp     third
+skipped:
+    jmp     conclusion
+third:
+conclusion:
+            "#
+            .trim()
+        );
+    }
+
+    #[test]
+    fn optimize_compiled_programs() {
+        for (code, inputs) in [
+            ("(let ([x read]) (let ([y x]) (+ y (- x))))", vec![7]),
+            ("(- read (- (+ read 0)))", vec![2, 3]),
+            ("(let ([x 0]) (+ x (- 9223372036854775807)))", vec![]),
+        ] {
+            for semantics in [ArithmeticSemantics::Checked, ArithmeticSemantics::Trapping] {
+                let program = patch_instructions(assign_homes(select_instructions(
+                    explicate_control(remove_complex_operands(
+                        uniquify_expr(parse_expr(code).unwrap()).unwrap(),
+                    )),
+                    semantics,
+                )));
+                optimize(program.clone(), &inputs);
+                assert_eq!(verify_x86(&peephole(program), X86Stage::Patched), Ok(()));
+            }
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::{HashMap, HashSet};
+
+use frontend::{ArithmeticSemantics, Interval, IntervalResult, OverflowRisk};
+
+use crate::ir::cvar::{Atom, BinaryOpKind, Expr, Program, Stmt, UnaryOpKind};
+
+// The values of the variables at a point of the program. A variable that is missing may hold any
+// value.
+type Environment<'p> = HashMap<&'p str, Interval>;
+
+// The number of times the environment at the start of a block may change before its intervals are
+// widened, so that the analysis of a loop terminates.
+const WIDENING_DELAY: u32 = 3;
+
+fn atom_interval(atom: &Atom, environment: &Environment) -> Interval {
+    match atom {
+        Atom::Integer(value) => Interval::constant(*value),
+        Atom::Variable(name) => environment
+            .get(name.as_str())
+            .copied()
+            .unwrap_or(Interval::FULL),
+    }
+}
+
+fn expr_result(
+    expr: &Expr,
+    environment: &Environment,
+    semantics: ArithmeticSemantics,
+) -> IntervalResult {
+    let value = |atom| atom_interval(atom, environment);
+    match expr {
+        Expr::Atom(atom) => IntervalResult {
+            value: Some(value(atom)),
+            risk: OverflowRisk::Never,
+        },
+        Expr::Read => IntervalResult {
+            value: Some(Interval::FULL),
+            risk: OverflowRisk::Never,
+        },
+        Expr::UnaryOperation {
+            kind: UnaryOpKind::Minus,
+            operand,
+        } => value(operand).neg(semantics),
+        Expr::BinaryOperation {
+            kind,
+            left_operand,
+            right_operand,
+        } => match kind {
+            BinaryOpKind::Add => value(left_operand).add(value(right_operand), semantics),
+            BinaryOpKind::Sub => value(left_operand).sub(value(right_operand), semantics),
+        },
+    }
+}
+
+/// Joins the environment `new`, which reaches a block, with `old`, which reached it before. When
+/// `widen` is set, the bounds that grow are moved to the ends of the range.
+fn join<'p>(old: &Environment<'p>, new: &Environment<'p>, widen: bool) -> Environment<'p> {
+    old.iter()
+        .filter_map(|(&name, &old)| {
+            let mut result = old.join(*new.get(name)?);
+            if widen && result.min < old.min {
+                result.min = i64::MIN;
+            }
+            if widen && result.max > old.max {
+                result.max = i64::MAX;
+            }
+            Some((name, result))
+        })
+        .collect()
+}
+
+struct RangeAnalysisImpl<'p> {
+    program: &'p Program,
+    semantics: ArithmeticSemantics,
+    // The environment at the start of every block, or `None` if the block is not reached.
+    entries: Vec<Option<Environment<'p>>>,
+    // The number of times the environment at the start of every block has changed.
+    changes: Vec<u32>,
+}
+
+impl<'p> RangeAnalysisImpl<'p> {
+    fn new(program: &'p Program, semantics: ArithmeticSemantics) -> Self {
+        Self {
+            program,
+            semantics,
+            entries: vec![None; program.blocks.len()],
+            changes: vec![0; program.blocks.len()],
+        }
+    }
+
+    /// Evaluates the statements of a block from `environment`, until the evaluation stops.
+    /// Returns the risk of overflow of every evaluated statement, and the environment at the end
+    /// of the block, or `None` if the evaluation always stops before it.
+    fn analyze_block(
+        &self,
+        block: usize,
+        mut environment: Environment<'p>,
+    ) -> (Vec<OverflowRisk>, Option<Environment<'p>>) {
+        let mut risks = Vec::new();
+        for stmt in &self.program.blocks[block].body {
+            let result = match stmt {
+                Stmt::Assign { rhs: expr, .. } | Stmt::Return(expr) => {
+                    expr_result(expr, &environment, self.semantics)
+                }
+                Stmt::Goto(_) => IntervalResult {
+                    value: None,
+                    risk: OverflowRisk::Never,
+                },
+            };
+            risks.push(result.risk);
+
+            match (stmt, result.value) {
+                (Stmt::Assign { lhs, .. }, Some(value)) => {
+                    environment.insert(lhs, value);
+                }
+                (Stmt::Assign { .. }, None) => return (risks, None),
+                _ => (),
+            }
+        }
+        (risks, Some(environment))
+    }
+
+    fn run(&mut self) {
+        if self.program.blocks.is_empty() {
+            return;
+        }
+
+        self.entries[0] = Some(Environment::new());
+        let mut worklist = vec![0];
+        while let Some(block) = worklist.pop() {
+            let entry = self.entries[block].clone().unwrap();
+            let (_, Some(exit)) = self.analyze_block(block, entry) else {
+                continue;
+            };
+
+            for label in self.program.blocks[block].successors() {
+                // A `goto` to an unknown label is reported by `verify_cvar`.
+                let Some(successor) = self
+                    .program
+                    .blocks
+                    .iter()
+                    .position(|block| block.label == label)
+                else {
+                    continue;
+                };
+                let joined = match &self.entries[successor] {
+                    None => exit.clone(),
+                    Some(old) => {
+                        let joined = join(old, &exit, self.changes[successor] >= WIDENING_DELAY);
+                        if joined == *old {
+                            continue;
+                        }
+                        joined
+                    }
+                };
+                self.entries[successor] = Some(joined);
+                self.changes[successor] += 1;
+                worklist.push(successor);
+            }
+        }
+    }
+}
+
+/// Finds the operations of `program` that never overflow under `semantics`, where `read` can
+/// produce any `i64`. Returns the positions of their statements, as the index of the block and the
+/// index of the statement in it.
+pub(crate) fn overflow_free_operations(
+    program: &Program,
+    semantics: ArithmeticSemantics,
+) -> HashSet<(usize, usize)> {
+    let mut analysis = RangeAnalysisImpl::new(program, semantics);
+    analysis.run();
+
+    let mut result = HashSet::new();
+    for (block, entry) in analysis.entries.iter().enumerate() {
+        let Some(entry) = entry else {
+            continue;
+        };
+        let (risks, _) = analysis.analyze_block(block, entry.clone());
+        for (index, risk) in risks.into_iter().enumerate() {
+            let is_operation = matches!(
+                &program.blocks[block].body[index],
+                Stmt::Assign {
+                    rhs: Expr::UnaryOperation { .. } | Expr::BinaryOperation { .. },
+                    ..
+                } | Stmt::Return(Expr::UnaryOperation { .. } | Expr::BinaryOperation { .. })
+            );
+            if is_operation && risk == OverflowRisk::Never {
+                result.insert((block, index));
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn variable(name: &str) -> Atom {
+        Atom::Variable(name.to_string())
+    }
+
+    fn binary(kind: BinaryOpKind, left_operand: Atom, right_operand: Atom) -> Expr {
+        Expr::BinaryOperation {
+            kind,
+            left_operand,
+            right_operand,
+        }
+    }
+
+    fn sorted(operations: HashSet<(usize, usize)>) -> Vec<(usize, usize)> {
+        let mut result: Vec<_> = operations.into_iter().collect();
+        result.sort();
+        result
+    }
+
+    #[test]
+    fn find_overflow_free_operations() {
+        let mut program = Program::new();
+        for name in ["x", "y", "z"] {
+            program.create_local_variable(name.to_string());
+        }
+        program.create_assign("x".to_string(), Expr::Read);
+        // May overflow, and then `y` is in `-i64::MAX..=i64::MAX`.
+        program.create_assign(
+            "y".to_string(),
+            Expr::UnaryOperation {
+                kind: UnaryOpKind::Minus,
+                operand: variable("x"),
+            },
+        );
+        program.create_assign(
+            "z".to_string(),
+            binary(BinaryOpKind::Sub, variable("y"), Atom::Integer(1)),
+        );
+        program.create_goto("next".to_string());
+        program.create_block("next".to_string());
+        program.create_terminator(binary(BinaryOpKind::Add, variable("z"), variable("y")));
+
+        assert_eq!(
+            sorted(overflow_free_operations(
+                &program,
+                ArithmeticSemantics::Trapping
+            )),
+            [(0, 2)]
+        );
+        // The negation may produce `i64::MIN`.
+        assert_eq!(
+            sorted(overflow_free_operations(
+                &program,
+                ArithmeticSemantics::Wrapping
+            )),
+            []
+        );
+    }
+
+    #[test]
+    fn widen_loops() {
+        // Counts up from 0, until the counter overflows.
+        let mut program = Program::new();
+        for name in ["i", "j"] {
+            program.create_local_variable(name.to_string());
+        }
+        program.create_assign("i".to_string(), Atom::Integer(0).into());
+        program.create_goto("loop".to_string());
+        program.create_block("loop".to_string());
+        program.create_assign(
+            "j".to_string(),
+            binary(BinaryOpKind::Sub, variable("i"), Atom::Integer(1)),
+        );
+        program.create_assign(
+            "i".to_string(),
+            binary(BinaryOpKind::Add, variable("i"), Atom::Integer(1)),
+        );
+        program.create_goto("loop".to_string());
+
+        // `i` is never negative, so `i - 1` never overflows.
+        assert_eq!(
+            sorted(overflow_free_operations(
+                &program,
+                ArithmeticSemantics::Trapping
+            )),
+            [(1, 0)]
+        );
+    }
+}
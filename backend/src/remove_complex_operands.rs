@@ -19,7 +19,12 @@ impl RCOImpl {
         match expr {
             Integer(val) => (Integer(val), Vec::new()),
 
-            Read => (Read, Vec::new()),
+            // `read` has a side effect, so it must be bound to a temporary variable to keep the
+            // order in which the operands are evaluated.
+            Read => {
+                let name = self.name_gen.generate();
+                (Identifier(name.clone()), vec![(name, Read)])
+            }
 
             Identifier(name) => (Identifier(name), Vec::new()),
 
@@ -61,14 +66,22 @@ impl RCOImpl {
                 variable_name,
                 init_expr,
                 body,
-            } => (
-                Let {
-                    variable_name,
-                    init_expr: Box::new(self.rco_expr(*init_expr)),
-                    body: Box::new(self.rco_expr(*body)),
-                },
-                Vec::new(),
-            ),
+            } => {
+                let init_expr = Box::new(self.rco_expr(*init_expr));
+                let body = Box::new(self.rco_expr(*body));
+                let name = self.name_gen.generate();
+                (
+                    Identifier(name.clone()),
+                    vec![(
+                        name,
+                        Let {
+                            variable_name,
+                            init_expr,
+                            body,
+                        },
+                    )],
+                )
+            }
         }
     }
 
@@ -221,5 +234,18 @@ mod test {
                     )"#
             )
         );
+
+        // The operands of variadic operations are evaluated from left to right.
+        assert_eq!(
+            remove_complex_operands(parse_expr("(- read read read)").unwrap()).to_string(),
+            "(let ([tmp0 read]) (let ([tmp1 read]) (let ([tmp2 (- tmp0 tmp1)]) \
+             (let ([tmp3 read]) (- tmp2 tmp3)))))"
+        );
+
+        assert_eq!(
+            remove_complex_operands(parse_expr("(+ (let ([x read]) (- x)) 2)").unwrap())
+                .to_string(),
+            "(let ([tmp0 (let ([x read]) (- x))]) (+ tmp0 2))"
+        );
     }
 }
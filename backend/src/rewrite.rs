@@ -0,0 +1,398 @@
+use std::{collections::HashMap, fmt::Display};
+
+use frontend::{parse_expr, ArithmeticSemantics, Expr};
+
+/// A side condition that must hold for a rule to fire.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Condition {
+    // Evaluating the term bound to the metavariable reads no input and cannot fail.
+    Pure(String),
+    // The term bound to the metavariable is an integer literal.
+    Constant(String),
+    // Arithmetic wraps around. Rules that change the operations a program performs may change the
+    // overflow it reports otherwise.
+    Wrapping,
+}
+
+/// A rewrite rule, whose sides are terms of the source language in which identifiers starting
+/// with `?` are metavariables. A metavariable that occurs more than once in `lhs` only matches
+/// equal terms. A rule whose `rhs` or conditions use a metavariable that `lhs` does not bind never
+/// fires.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub lhs: Expr,
+    pub rhs: Expr,
+    pub conditions: Vec<Condition>,
+}
+
+impl Rule {
+    /// Creates a rule from the source code of its sides. Panics if one of them cannot be parsed.
+    pub fn new(name: &str, lhs: &str, rhs: &str) -> Self {
+        let parse = |code| {
+            parse_expr(code).unwrap_or_else(|error| {
+                panic!("invalid pattern `{}` in {}: {:?}", code, name, error)
+            })
+        };
+        Self {
+            name: name.to_string(),
+            lhs: parse(lhs),
+            rhs: parse(rhs),
+            conditions: Vec::new(),
+        }
+    }
+
+    pub fn when(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+}
+
+/// The application of a rule to a subterm.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct RewriteStep {
+    pub rule: String,
+    pub before: Expr,
+    pub after: Expr,
+}
+
+impl Display for RewriteStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} => {}", self.rule, self.before, self.after)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct RewriteResult {
+    pub expr: Expr,
+    pub steps: Vec<RewriteStep>,
+    // Whether rewriting stopped because of the step limit rather than at a fixpoint.
+    pub reached_limit: bool,
+}
+
+fn is_metavariable(name: &str) -> bool {
+    name.starts_with('?')
+}
+
+fn match_pattern<'e>(
+    pattern: &Expr,
+    term: &'e Expr,
+    bindings: &mut HashMap<String, &'e Expr>,
+) -> bool {
+    match (pattern, term) {
+        (Expr::Identifier(name), _) if is_metavariable(name) => match bindings.get(name) {
+            Some(bound) => *bound == term,
+            None => {
+                bindings.insert(name.clone(), term);
+                true
+            }
+        },
+        (
+            Expr::UnaryOperation { kind, operand },
+            Expr::UnaryOperation {
+                kind: term_kind,
+                operand: term_operand,
+            },
+        ) => kind == term_kind && match_pattern(operand, term_operand, bindings),
+        (
+            Expr::BinaryOperation {
+                kind,
+                left_operand,
+                right_operand,
+            },
+            Expr::BinaryOperation {
+                kind: term_kind,
+                left_operand: term_left,
+                right_operand: term_right,
+            },
+        ) => {
+            kind == term_kind
+                && match_pattern(left_operand, term_left, bindings)
+                && match_pattern(right_operand, term_right, bindings)
+        }
+        (
+            Expr::Let {
+                variable_name,
+                init_expr,
+                body,
+            },
+            Expr::Let {
+                variable_name: term_name,
+                init_expr: term_init,
+                body: term_body,
+            },
+        ) => {
+            variable_name == term_name
+                && match_pattern(init_expr, term_init, bindings)
+                && match_pattern(body, term_body, bindings)
+        }
+        (pattern, term) => pattern == term,
+    }
+}
+
+/// Replaces the metavariables of `template` by their bindings. Returns `None` if one of them is
+/// not bound.
+fn instantiate(template: &Expr, bindings: &HashMap<String, &Expr>) -> Option<Expr> {
+    Some(match template {
+        Expr::Identifier(name) if is_metavariable(name) => (*bindings.get(name)?).clone(),
+        Expr::Integer(_) | Expr::Read | Expr::Identifier(_) => template.clone(),
+        Expr::UnaryOperation { kind, operand } => Expr::UnaryOperation {
+            kind: kind.clone(),
+            operand: Box::new(instantiate(operand, bindings)?),
+        },
+        Expr::BinaryOperation {
+            kind,
+            left_operand,
+            right_operand,
+        } => Expr::BinaryOperation {
+            kind: kind.clone(),
+            left_operand: Box::new(instantiate(left_operand, bindings)?),
+            right_operand: Box::new(instantiate(right_operand, bindings)?),
+        },
+        Expr::Let {
+            variable_name,
+            init_expr,
+            body,
+        } => Expr::Let {
+            variable_name: variable_name.clone(),
+            init_expr: Box::new(instantiate(init_expr, bindings)?),
+            body: Box::new(instantiate(body, bindings)?),
+        },
+    })
+}
+
+/// Rewrites expressions with a list of rules until none applies, or until a number of steps.
+pub struct Rewriter {
+    rules: Vec<Rule>,
+    semantics: ArithmeticSemantics,
+    max_steps: usize,
+}
+
+impl Rewriter {
+    pub const DEFAULT_MAX_STEPS: usize = 10_000;
+
+    pub fn new(rules: Vec<Rule>, semantics: ArithmeticSemantics) -> Self {
+        Self {
+            rules,
+            semantics,
+            max_steps: Self::DEFAULT_MAX_STEPS,
+        }
+    }
+
+    pub fn set_max_steps(&mut self, max_steps: usize) {
+        self.max_steps = max_steps;
+    }
+
+    // Identifiers are assumed to be bound, as in the output of `uniquify`.
+    fn is_pure(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Integer(value) => i64::try_from(*value).is_ok(),
+            Expr::Read => false,
+            Expr::Identifier(_) => true,
+            Expr::UnaryOperation { operand, .. } => {
+                self.semantics == ArithmeticSemantics::Wrapping && self.is_pure(operand)
+            }
+            Expr::BinaryOperation {
+                left_operand,
+                right_operand,
+                ..
+            } => {
+                self.semantics == ArithmeticSemantics::Wrapping
+                    && self.is_pure(left_operand)
+                    && self.is_pure(right_operand)
+            }
+            Expr::Let {
+                init_expr, body, ..
+            } => self.is_pure(init_expr) && self.is_pure(body),
+        }
+    }
+
+    fn holds(&self, condition: &Condition, bindings: &HashMap<String, &Expr>) -> bool {
+        match condition {
+            Condition::Pure(name) => bindings.get(name).is_some_and(|term| self.is_pure(term)),
+            Condition::Constant(name) => {
+                matches!(bindings.get(name), Some(Expr::Integer(_)))
+            }
+            Condition::Wrapping => self.semantics == ArithmeticSemantics::Wrapping,
+        }
+    }
+
+    fn apply(&self, rule: &Rule, term: &Expr) -> Option<Expr> {
+        let mut bindings = HashMap::new();
+        if !match_pattern(&rule.lhs, term, &mut bindings) {
+            return None;
+        }
+        if !rule
+            .conditions
+            .iter()
+            .all(|condition| self.holds(condition, &bindings))
+        {
+            return None;
+        }
+        instantiate(&rule.rhs, &bindings)
+    }
+
+    /// Applies the first rule that matches the outermost, leftmost subterm of `expr` it can.
+    fn rewrite_once(&self, expr: &mut Expr) -> Option<RewriteStep> {
+        for rule in &self.rules {
+            if let Some(after) = self.apply(rule, expr) {
+                let before = std::mem::replace(expr, after.clone());
+                return Some(RewriteStep {
+                    rule: rule.name.clone(),
+                    before,
+                    after,
+                });
+            }
+        }
+
+        match expr {
+            Expr::Integer(_) | Expr::Read | Expr::Identifier(_) => None,
+            Expr::UnaryOperation { operand, .. } => self.rewrite_once(operand),
+            Expr::BinaryOperation {
+                left_operand,
+                right_operand,
+                ..
+            } => self
+                .rewrite_once(left_operand)
+                .or_else(|| self.rewrite_once(right_operand)),
+            Expr::Let {
+                init_expr, body, ..
+            } => self
+                .rewrite_once(init_expr)
+                .or_else(|| self.rewrite_once(body)),
+        }
+    }
+
+    pub fn rewrite(&self, mut expr: Expr) -> RewriteResult {
+        let mut steps = Vec::new();
+        let reached_limit = loop {
+            if steps.len() == self.max_steps {
+                break true;
+            }
+            match self.rewrite_once(&mut expr) {
+                Some(step) => steps.push(step),
+                None => break false,
+            }
+        };
+
+        RewriteResult {
+            expr,
+            steps,
+            reached_limit,
+        }
+    }
+}
+
+/// Returns the algebraic identities that hold for the source language. A rule that removes a
+/// term requires it to be pure, and a rule that changes the operations requires wrapping
+/// arithmetic.
+pub fn algebraic_rules() -> Vec<Rule> {
+    vec![
+        Rule::new("add_zero_left", "(+ 0 ?x)", "?x"),
+        Rule::new("add_zero_right", "(+ ?x 0)", "?x"),
+        Rule::new("sub_zero", "(- ?x 0)", "?x"),
+        Rule::new("negate_zero", "(- 0)", "0"),
+        Rule::new("sub_self", "(- ?x ?x)", "0").when(Condition::Pure("?x".to_string())),
+        Rule::new("double_negation", "(- (- ?x))", "?x").when(Condition::Wrapping),
+        Rule::new("sub_from_zero", "(- 0 ?x)", "(- ?x)").when(Condition::Wrapping),
+        Rule::new("add_negation", "(+ ?x (- ?y))", "(- ?x ?y)").when(Condition::Wrapping),
+        Rule::new("sub_negated_constant", "(- ?x (- ?c))", "(+ ?x ?c)")
+            .when(Condition::Constant("?c".to_string()))
+            .when(Condition::Wrapping),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rewrite(code: &str, semantics: ArithmeticSemantics) -> (String, Vec<String>) {
+        let result = Rewriter::new(algebraic_rules(), semantics).rewrite(parse_expr(code).unwrap());
+        assert!(!result.reached_limit);
+        (
+            result.expr.to_string(),
+            result.steps.iter().map(RewriteStep::to_string).collect(),
+        )
+    }
+
+    #[test]
+    fn algebraic_simplification() {
+        assert_eq!(
+            rewrite("(+ (- (- read)) (- 5 5))", ArithmeticSemantics::Wrapping),
+            (
+                "read".to_string(),
+                vec![
+                    "double_negation: (- (- read)) => read".to_string(),
+                    "sub_self: (- 5 5) => 0".to_string(),
+                    "add_zero_right: (+ read 0) => read".to_string(),
+                ]
+            )
+        );
+        // `(- (- x))` overflows if `x` is the smallest integer.
+        assert_eq!(
+            rewrite("(+ (- (- read)) (- 5 5))", ArithmeticSemantics::Checked).0,
+            "(- (- read))"
+        );
+
+        let code = "(let ([x read]) (- (+ x 1) (+ x 1)))";
+        assert_eq!(
+            rewrite(code, ArithmeticSemantics::Wrapping).0,
+            "(let ([x read]) 0)"
+        );
+        // `(+ x 1)` may overflow.
+        assert_eq!(rewrite(code, ArithmeticSemantics::Checked).0, code);
+
+        // Every input is still read.
+        assert_eq!(
+            rewrite("(- read read)", ArithmeticSemantics::Wrapping).0,
+            "(- read read)"
+        );
+        assert_eq!(
+            rewrite("(- 0 (+ read (- 7)))", ArithmeticSemantics::Wrapping).0,
+            "(- (- read 7))"
+        );
+    }
+
+    #[test]
+    fn rewrite_limit() {
+        let rules = vec![
+            Rule::new("commute", "(+ ?x ?y)", "(+ ?y ?x)"),
+            Rule::new("never", "(- ?x ?x)", "0").when(Condition::Constant("?x".to_string())),
+        ];
+        let mut rewriter = Rewriter::new(rules, ArithmeticSemantics::Checked);
+        rewriter.set_max_steps(3);
+
+        let result = rewriter.rewrite(parse_expr("(- (+ 1 x) (+ 1 x))").unwrap());
+        assert!(result.reached_limit);
+        assert_eq!(result.expr.to_string(), "(- (+ x 1) (+ 1 x))");
+        assert_eq!(
+            result
+                .steps
+                .iter()
+                .map(|step| step.rule.as_str())
+                .collect::<Vec<_>>(),
+            ["commute", "commute", "commute"]
+        );
+    }
+
+    #[test]
+    fn unbound_metavariables() {
+        let rules = vec![
+            Rule::new("unbound_rhs", "(+ ?x 0)", "?y"),
+            Rule::new("unbound_condition", "(- ?x 0)", "?x")
+                .when(Condition::Constant("?y".to_string())),
+            Rule::new("sub_zero", "(- ?x 0)", "?x"),
+        ];
+        let result = Rewriter::new(rules, ArithmeticSemantics::Checked)
+            .rewrite(parse_expr("(+ (- read 0) 0)").unwrap());
+        assert_eq!(result.expr.to_string(), "(+ read 0)");
+        assert_eq!(
+            result
+                .steps
+                .iter()
+                .map(|step| step.rule.as_str())
+                .collect::<Vec<_>>(),
+            ["sub_zero"]
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use frontend::OverflowKind;
+
+/// The overflow handlers that compiled programs jump to in `ArithmeticSemantics::Trapping` mode.
+/// Every handler terminates the program with its own exit code, so that the kind of the overflow
+/// can be told from the outside.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
+pub enum OverflowTrap {
+    Neg,
+    Add,
+    Sub,
+}
+
+impl OverflowTrap {
+    pub fn exit_code(self) -> i64 {
+        match self {
+            OverflowTrap::Neg => 65,
+            OverflowTrap::Add => 66,
+            OverflowTrap::Sub => 67,
+        }
+    }
+
+    pub fn from_exit_code(code: i64) -> Option<Self> {
+        [OverflowTrap::Neg, OverflowTrap::Add, OverflowTrap::Sub]
+            .into_iter()
+            .find(|trap| trap.exit_code() == code)
+    }
+
+    pub fn handler_label(self) -> &'static str {
+        match self {
+            OverflowTrap::Neg => "overflow_neg",
+            OverflowTrap::Add => "overflow_add",
+            OverflowTrap::Sub => "overflow_sub",
+        }
+    }
+}
+
+impl From<&OverflowKind> for OverflowTrap {
+    fn from(value: &OverflowKind) -> Self {
+        match value {
+            OverflowKind::NegOverflow(_) => OverflowTrap::Neg,
+            OverflowKind::AddOverflow(..) => OverflowTrap::Add,
+            OverflowKind::SubOverflow(..) => OverflowTrap::Sub,
+        }
+    }
+}
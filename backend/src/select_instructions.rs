@@ -1,16 +1,262 @@
-use crate::ir::{
-    cvar::{Atom, BinaryOpKind, Expr, Program, Stmt, UnaryOpKind},
-    x86::{Block, Reg, VarArg, VarInstr, VarProgram},
+use std::collections::HashSet;
+
+use frontend::ArithmeticSemantics;
+
+use crate::{
+    ir::{
+        cvar::{Atom, BinaryOpKind, Expr, Program, Stmt, UnaryOpKind},
+        x86::{Block, Reg, VarArg, VarInstr, VarProgram},
+    },
+    patch_instructions::patched_len,
+    range_analysis::overflow_free_operations,
+    runtime::OverflowTrap,
 };
 
+// A tree pattern, which matches the right-hand side of an assignment or one of its operands.
+#[derive(Debug, Clone)]
+enum Pattern {
+    // Any atom.
+    Atom,
+    // Any atom other than the destination of the assignment, which can be read after the
+    // destination is written.
+    NotDestination,
+    // A specific integer.
+    Integer(i64),
+    Read,
+    Unary(UnaryOpKind, Box<Pattern>),
+    Binary(BinaryOpKind, Box<Pattern>, Box<Pattern>),
+}
+
+impl Pattern {
+    fn unary(kind: UnaryOpKind, operand: Pattern) -> Self {
+        Pattern::Unary(kind, Box::new(operand))
+    }
+
+    fn binary(kind: BinaryOpKind, left: Pattern, right: Pattern) -> Self {
+        Pattern::Binary(kind, Box::new(left), Box::new(right))
+    }
+
+    /// Matches an operand, and adds it to `operands` unless the pattern is a specific integer.
+    fn match_atom(&self, atom: &Atom, destination: &VarArg, operands: &mut Vec<VarArg>) -> bool {
+        let arg = SelectInstrImpl::handle_atom(atom.clone());
+        let matches = match self {
+            Pattern::Atom => true,
+            Pattern::NotDestination => arg != *destination,
+            Pattern::Integer(value) => return *atom == Atom::Integer(*value),
+            _ => false,
+        };
+        if matches {
+            operands.push(arg);
+        }
+        matches
+    }
+
+    /// Matches the right-hand side of an assignment to `destination`, and collects the operands
+    /// bound by the leaves of the pattern from left to right. The operands of a commutative
+    /// operation are also matched in the other order.
+    fn match_expr(&self, expr: &Expr, destination: &VarArg) -> Option<Vec<VarArg>> {
+        let mut operands = Vec::new();
+        let matches = match (self, expr) {
+            (Pattern::Read, Expr::Read) => true,
+            (_, Expr::Atom(atom)) => self.match_atom(atom, destination, &mut operands),
+            (Pattern::Unary(kind, pattern), Expr::UnaryOperation { kind: op, operand }) => {
+                kind == op && pattern.match_atom(operand, destination, &mut operands)
+            }
+            (
+                Pattern::Binary(kind, left, right),
+                Expr::BinaryOperation {
+                    kind: op,
+                    left_operand,
+                    right_operand,
+                },
+            ) if kind == op => {
+                let mut match_operands = |first, second| {
+                    operands.clear();
+                    left.match_atom(first, destination, &mut operands)
+                        && right.match_atom(second, destination, &mut operands)
+                };
+                match_operands(left_operand, right_operand)
+                    || (kind.is_commutative() && match_operands(right_operand, left_operand))
+            }
+            _ => false,
+        };
+        matches.then_some(operands)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum Overflow {
+    // The operation never overflows.
+    Impossible,
+    // The last arithmetic instruction sets the overflow flag like the operation, so that the
+    // overflow can be caught by the handler of `OverflowTrap`.
+    Detected(OverflowTrap),
+}
+
+// A rule of the instruction selector, which translates the assignments matched by its pattern.
+struct Rule {
+    pattern: Pattern,
+    overflow: Overflow,
+    // Generates the instructions from the destination and the operands bound by the pattern.
+    emit: fn(VarArg, &[VarArg]) -> Vec<VarInstr>,
+}
+
+fn movq(from: VarArg, to: VarArg) -> VarInstr {
+    VarInstr::Movq { from, to }
+}
+
+/// The rules of the instruction selector. When several rules match, the one whose instructions
+/// are the cheapest once patched is chosen, and the first one among equally cheap rules.
+fn default_rules() -> Vec<Rule> {
+    use BinaryOpKind::{Add, Sub};
+    use Pattern::{Atom, Integer, NotDestination};
+
+    vec![
+        Rule {
+            pattern: Atom,
+            overflow: Overflow::Impossible,
+            emit: |dst, ops| vec![movq(ops[0].clone(), dst)],
+        },
+        Rule {
+            pattern: Pattern::Read,
+            overflow: Overflow::Impossible,
+            emit: |dst, _| {
+                vec![
+                    VarInstr::Callq {
+                        callee: SelectInstrImpl::read_int_func_name(),
+                    },
+                    movq(SelectInstrImpl::rax_reg(), dst),
+                ]
+            },
+        },
+        Rule {
+            pattern: Pattern::unary(UnaryOpKind::Minus, Atom),
+            overflow: Overflow::Detected(OverflowTrap::Neg),
+            emit: |dst, ops| {
+                vec![
+                    movq(ops[0].clone(), dst.clone()),
+                    VarInstr::Negq { operand: dst },
+                ]
+            },
+        },
+        Rule {
+            pattern: Pattern::binary(Add, Atom, Integer(1)),
+            overflow: Overflow::Detected(OverflowTrap::Add),
+            emit: |dst, ops| {
+                vec![
+                    movq(ops[0].clone(), dst.clone()),
+                    VarInstr::Incq { operand: dst },
+                ]
+            },
+        },
+        Rule {
+            pattern: Pattern::binary(Add, Atom, Integer(-1)),
+            overflow: Overflow::Detected(OverflowTrap::Add),
+            emit: |dst, ops| {
+                vec![
+                    movq(ops[0].clone(), dst.clone()),
+                    VarInstr::Decq { operand: dst },
+                ]
+            },
+        },
+        Rule {
+            pattern: Pattern::binary(Sub, Atom, Integer(1)),
+            overflow: Overflow::Detected(OverflowTrap::Sub),
+            emit: |dst, ops| {
+                vec![
+                    movq(ops[0].clone(), dst.clone()),
+                    VarInstr::Decq { operand: dst },
+                ]
+            },
+        },
+        Rule {
+            pattern: Pattern::binary(Sub, Atom, Integer(-1)),
+            overflow: Overflow::Detected(OverflowTrap::Sub),
+            emit: |dst, ops| {
+                vec![
+                    movq(ops[0].clone(), dst.clone()),
+                    VarInstr::Incq { operand: dst },
+                ]
+            },
+        },
+        // The destination is written before the right operand is read.
+        Rule {
+            pattern: Pattern::binary(Add, Atom, NotDestination),
+            overflow: Overflow::Detected(OverflowTrap::Add),
+            emit: |dst, ops| {
+                vec![
+                    movq(ops[0].clone(), dst.clone()),
+                    VarInstr::Addq {
+                        lhs: dst,
+                        rhs: ops[1].clone(),
+                    },
+                ]
+            },
+        },
+        Rule {
+            pattern: Pattern::binary(Sub, Atom, NotDestination),
+            overflow: Overflow::Detected(OverflowTrap::Sub),
+            emit: |dst, ops| {
+                vec![
+                    movq(ops[0].clone(), dst.clone()),
+                    VarInstr::Subq {
+                        lhs: dst,
+                        rhs: ops[1].clone(),
+                    },
+                ]
+            },
+        },
+        // The three-address forms compute the result in %rax, so that each variable is accessed
+        // once.
+        Rule {
+            pattern: Pattern::binary(Add, Atom, Atom),
+            overflow: Overflow::Detected(OverflowTrap::Add),
+            emit: |dst, ops| {
+                vec![
+                    movq(ops[0].clone(), SelectInstrImpl::rax_reg()),
+                    VarInstr::Addq {
+                        lhs: SelectInstrImpl::rax_reg(),
+                        rhs: ops[1].clone(),
+                    },
+                    movq(SelectInstrImpl::rax_reg(), dst),
+                ]
+            },
+        },
+        Rule {
+            pattern: Pattern::binary(Sub, Atom, Atom),
+            overflow: Overflow::Detected(OverflowTrap::Sub),
+            emit: |dst, ops| {
+                vec![
+                    movq(ops[0].clone(), SelectInstrImpl::rax_reg()),
+                    VarInstr::Subq {
+                        lhs: SelectInstrImpl::rax_reg(),
+                        rhs: ops[1].clone(),
+                    },
+                    movq(SelectInstrImpl::rax_reg(), dst),
+                ]
+            },
+        },
+    ]
+}
+
 struct SelectInstrImpl {
     result_program: VarProgram,
+    semantics: ArithmeticSemantics,
+    rules: Vec<Rule>,
+    // The overflow handlers that the program jumps to, in the order of their first use.
+    used_traps: Vec<OverflowTrap>,
+    // The label of the first block of the C_var program, which becomes `main`.
+    entry_label: String,
 }
 
 impl SelectInstrImpl {
-    fn new() -> Self {
+    fn new(semantics: ArithmeticSemantics) -> Self {
         Self {
             result_program: VarProgram::new(),
+            semantics,
+            rules: default_rules(),
+            used_traps: Vec::new(),
+            entry_label: Program::entry_label(),
         }
     }
 
@@ -18,18 +264,12 @@ impl SelectInstrImpl {
         "read_int".to_string()
     }
 
-    fn rax_reg() -> VarArg {
-        Reg::RAX.into()
+    fn exit_func_name() -> String {
+        "exit".to_string()
     }
 
-    fn generate_result_target(actual_result: VarArg, expected_result: VarArg) -> Option<VarInstr> {
-        match actual_result {
-            VarArg::Variable(_) if actual_result == expected_result => None,
-            _ => Some(VarInstr::Movq {
-                from: expected_result,
-                to: actual_result,
-            }),
-        }
+    fn rax_reg() -> VarArg {
+        Reg::RAX.into()
     }
 
     fn handle_atom(atom: Atom) -> VarArg {
@@ -39,98 +279,131 @@ impl SelectInstrImpl {
         }
     }
 
-    fn handle_expr(expr: Expr, result: VarArg, target_block: &mut Block<VarArg>) {
-        match expr {
-            Expr::Atom(atom) => target_block.add_instr(VarInstr::Movq {
-                from: Self::handle_atom(atom),
-                to: result,
-            }),
+    /// Returns the instructions of the cheapest rule that can translate the assignment of `expr`
+    /// to `result`, and how the rule detects overflows. Moves of an operand to itself, e.g. when
+    /// the destination is also an operand, are left out.
+    fn select_rule(&self, expr: &Expr, result: &VarArg) -> (Overflow, Vec<VarInstr>) {
+        let mut best: Option<(usize, Overflow, Vec<VarInstr>)> = None;
+        for rule in &self.rules {
+            let Some(operands) = rule.pattern.match_expr(expr, result) else {
+                continue;
+            };
 
-            Expr::Read => {
-                target_block.add_instr(VarInstr::Callq {
-                    callee: Self::read_int_func_name(),
-                });
-                target_block.add_instr(VarInstr::Movq {
-                    from: Self::rax_reg(),
-                    to: result,
-                });
+            let mut instructions = (rule.emit)(result.clone(), &operands);
+            instructions
+                .retain(|instr| !matches!(instr, VarInstr::Movq { from, to } if from == to));
+            let cost = instructions.iter().map(patched_len).sum();
+            if best
+                .as_ref()
+                .is_none_or(|(best_cost, ..)| cost < *best_cost)
+            {
+                best = Some((cost, rule.overflow, instructions));
             }
+        }
 
-            Expr::UnaryOperation { kind, operand } => {
-                if let Some(instr) =
-                    Self::generate_result_target(result.clone(), Self::handle_atom(operand))
-                {
-                    target_block.add_instr(instr);
-                }
-
-                match kind {
-                    UnaryOpKind::Minus => {
-                        target_block.add_instr(VarInstr::Negq { operand: result })
-                    }
-                }
-            }
+        let (_, overflow, instructions) =
+            best.unwrap_or_else(|| panic!("no instruction selection rule matches `{}`", expr));
+        (overflow, instructions)
+    }
+
+    /// Adds the instructions of the cheapest rule for the assignment of `expr` to `result`,
+    /// followed by a jump to the overflow handler when `check_overflow` is set.
+    fn handle_expr(
+        &mut self,
+        expr: Expr,
+        result: VarArg,
+        check_overflow: bool,
+        target_block: &mut Block<VarArg>,
+    ) {
+        let (overflow, instructions) = self.select_rule(&expr, &result);
+        target_block.instructions.extend(instructions);
 
-            Expr::BinaryOperation {
-                kind,
-                left_operand,
-                right_operand,
-            } => {
-                if let Some(instr) =
-                    Self::generate_result_target(result.clone(), Self::handle_atom(left_operand))
-                {
-                    target_block.add_instr(instr);
-                }
-
-                match kind {
-                    BinaryOpKind::Add => target_block.add_instr(VarInstr::Addq {
-                        lhs: result,
-                        rhs: Self::handle_atom(right_operand),
-                    }),
-
-                    BinaryOpKind::Sub => target_block.add_instr(VarInstr::Subq {
-                        lhs: result,
-                        rhs: Self::handle_atom(right_operand),
-                    }),
-                }
+        if let (Overflow::Detected(trap), true) = (overflow, check_overflow) {
+            if !self.used_traps.contains(&trap) {
+                self.used_traps.push(trap);
             }
+            target_block.add_instr(VarInstr::Jo {
+                target: trap.handler_label().to_string(),
+            });
         }
     }
 
-    fn handle_stmt(stmt: Stmt, target_block: &mut Block<VarArg>) {
+    fn handle_stmt(&mut self, stmt: Stmt, check_overflow: bool, target_block: &mut Block<VarArg>) {
         match stmt {
             Stmt::Assign { lhs, rhs } => {
-                Self::handle_expr(rhs, VarArg::Variable(lhs), target_block);
+                self.handle_expr(rhs, VarArg::Variable(lhs), check_overflow, target_block);
             }
 
             Stmt::Return(operand) => {
-                Self::handle_expr(operand, Self::rax_reg(), target_block);
+                self.handle_expr(operand, Self::rax_reg(), check_overflow, target_block);
                 target_block.add_instr(VarInstr::Jmp {
                     target: "conclusion".to_string(),
                 });
             }
+
+            Stmt::Goto(label) => target_block.add_instr(VarInstr::Jmp {
+                target: self.block_label(label),
+            }),
+        }
+    }
+
+    fn block_label(&self, label: String) -> String {
+        if label == self.entry_label {
+            "main".to_string()
+        } else {
+            label
         }
     }
 
+    /// Generates a block that exits the program with the exit code of `trap`.
+    fn trap_handler(trap: OverflowTrap) -> Block<VarArg> {
+        let mut block = Block::new(trap.handler_label().to_string());
+        block.add_instr(VarInstr::Movq {
+            from: VarArg::Imm(trap.exit_code()),
+            to: Reg::RDI.into(),
+        });
+        block.add_instr(VarInstr::Callq {
+            callee: Self::exit_func_name(),
+        });
+        block
+    }
+
     fn handle_program(mut self, program: Program) -> Self {
-        // Create new blocks.
-        let mut main_block: Block<VarArg> = Block::new("main".to_string());
-        let conclusion_block: Block<VarArg> = Block::new("conclusion".to_string());
+        // In `Trapping` mode, only the operations that may overflow are checked.
+        let unchecked = match self.semantics {
+            ArithmeticSemantics::Trapping => overflow_free_operations(&program, self.semantics),
+            ArithmeticSemantics::Wrapping | ArithmeticSemantics::Checked => HashSet::new(),
+        };
 
-        program
-            .body
-            .into_iter()
-            .for_each(|stmt| Self::handle_stmt(stmt, &mut main_block));
+        if let Some(entry) = program.entry() {
+            self.entry_label = entry.label.clone();
+        }
+        for (block_index, block) in program.blocks.into_iter().enumerate() {
+            let mut target_block: Block<VarArg> = Block::new(self.block_label(block.label));
+            for (index, stmt) in block.body.into_iter().enumerate() {
+                let check_overflow = self.semantics == ArithmeticSemantics::Trapping
+                    && !unchecked.contains(&(block_index, index));
+                self.handle_stmt(stmt, check_overflow, &mut target_block);
+            }
+            self.result_program.body.push(target_block);
+        }
 
-        self.result_program.body.push(main_block);
-        self.result_program.body.push(conclusion_block);
+        // The handlers never return, so they can be placed anywhere before the conclusion, which
+        // must stay the last block.
+        for trap in &self.used_traps {
+            self.result_program.body.push(Self::trap_handler(*trap));
+        }
+        self.result_program
+            .body
+            .push(Block::new("conclusion".to_string()));
         self.result_program.local_variables = program.locals;
 
         self
     }
 }
 
-pub(crate) fn select_instructions(program: Program) -> VarProgram {
-    SelectInstrImpl::new()
+pub(crate) fn select_instructions(program: Program, semantics: ArithmeticSemantics) -> VarProgram {
+    SelectInstrImpl::new(semantics)
         .handle_program(program)
         .result_program
 }
@@ -150,9 +423,10 @@ mod test {
     #[test]
     fn select_instructions_test() {
         assert_eq!(
-            select_instructions(prepare_program(
-                "let ([y (let ([x1 (- 20)]) (let ([x2 22]) (+ x1 x2)))]) y"
-            ))
+            select_instructions(
+                prepare_program("let ([y (let ([x1 (- 20)]) (let ([x2 22]) (+ x1 x2)))]) y"),
+                ArithmeticSemantics::Checked
+            )
             .to_string()
             .trim(),
             r#"
@@ -161,8 +435,9 @@ main:
     movq    $0x14, x1
     negq    x1
     movq    $0x16, x2
-    movq    x1, y
-    addq    x2, y
+    movq    x1, %rax
+    addq    x2, %rax
+    movq    %rax, y
     movq    y, %rax
     jmp     conclusion
 conclusion:
@@ -171,9 +446,10 @@ conclusion:
         );
 
         assert_eq!(
-            select_instructions(prepare_program(
-                "let ([x1 read]) (let ([x2 (- x1 15)]) (+ x1 x2))"
-            ))
+            select_instructions(
+                prepare_program("let ([x1 read]) (let ([x2 (- x1 15)]) (+ x1 x2))"),
+                ArithmeticSemantics::Checked
+            )
             .to_string()
             .trim(),
             r#"
@@ -186,6 +462,135 @@ main:
     movq    x1, %rax
     addq    x2, %rax
     jmp     conclusion
+conclusion:
+    "#
+            .trim()
+        );
+    }
+
+    #[test]
+    fn select_instructions_blocks() {
+        let mut program = Program::new();
+        program.create_local_variable("x".to_string());
+        program.create_assign("x".to_string(), Expr::Read);
+        program.create_goto("block0".to_string());
+        program.create_block("block0".to_string());
+        program.create_terminator(Atom::Variable("x".to_string()).into());
+        // The first block becomes `main`, whatever its label.
+        program.blocks[0].label = "begin".to_string();
+
+        assert_eq!(
+            select_instructions(program, ArithmeticSemantics::Checked)
+                .to_string()
+                .trim(),
+            r#"
+locals: [x]
+main:
+    callq   read_int
+    movq    %rax, x
+    jmp     block0
+block0:
+    movq    x, %rax
+    jmp     conclusion
+conclusion:
+    "#
+            .trim()
+        );
+    }
+
+    #[test]
+    fn select_instructions_trapping() {
+        // `y` is never `i64::MIN`, so `(- y 1)` cannot overflow.
+        assert_eq!(
+            select_instructions(
+                prepare_program("(let ([x read]) (let ([y (- x)]) (let ([z (- y 1)]) (+ y z))))"),
+                ArithmeticSemantics::Trapping
+            )
+            .to_string()
+            .trim(),
+            r#"
+locals: [x, y, z]
+main:
+    callq   read_int
+    movq    %rax, x
+    movq    x, y
+    negq    y
+    jo      overflow_neg
+    movq    y, z
+    decq    z
+    movq    y, %rax
+    addq    z, %rax
+    jo      overflow_add
+    jmp     conclusion
+overflow_neg:
+    movq    $0x41, %rdi
+    callq   exit
+overflow_add:
+    movq    $0x42, %rdi
+    callq   exit
+conclusion:
+    "#
+            .trim()
+        );
+    }
+
+    #[test]
+    fn select_cheapest_rules() {
+        let variable = |name: &str| Atom::Variable(name.to_string());
+        let binary = |kind, left_operand, right_operand| Expr::BinaryOperation {
+            kind,
+            left_operand,
+            right_operand,
+        };
+
+        let mut program = Program::new();
+        for name in ["x", "y", "z"] {
+            program.create_local_variable(name.to_string());
+        }
+        program.create_assign("x".to_string(), Expr::Read);
+        program.create_assign(
+            "x".to_string(),
+            binary(BinaryOpKind::Add, Atom::Integer(1), variable("x")),
+        );
+        program.create_assign(
+            "y".to_string(),
+            binary(BinaryOpKind::Sub, variable("x"), Atom::Integer(-1)),
+        );
+        program.create_assign(
+            "y".to_string(),
+            binary(BinaryOpKind::Sub, Atom::Integer(1), variable("y")),
+        );
+        program.create_assign(
+            "y".to_string(),
+            binary(BinaryOpKind::Add, variable("y"), variable("x")),
+        );
+        program.create_assign(
+            "z".to_string(),
+            binary(BinaryOpKind::Add, variable("x"), variable("y")),
+        );
+        program.create_terminator(variable("z").into());
+
+        assert_eq!(
+            select_instructions(program, ArithmeticSemantics::Checked)
+                .to_string()
+                .trim(),
+            r#"
+locals: [x, y, z]
+main:
+    callq   read_int
+    movq    %rax, x
+    incq    x
+    movq    x, y
+    incq    y
+    movq    $0x1, %rax
+    subq    y, %rax
+    movq    %rax, y
+    addq    x, y
+    movq    x, %rax
+    addq    y, %rax
+    movq    %rax, z
+    movq    z, %rax
+    jmp     conclusion
 conclusion:
     "#
             .trim()
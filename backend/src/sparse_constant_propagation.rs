@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+
+use frontend::ArithmeticSemantics;
+
+use crate::ir::{
+    cvar::{Atom, BinaryOpKind, Expr, Stmt, UnaryOpKind},
+    ssa::Program,
+};
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum Value {
+    // No assignment of the variable has been evaluated yet.
+    Undefined,
+    Constant(i64),
+    // The variable may hold different values, or a value that is only known at runtime.
+    Overdefined,
+}
+
+impl Value {
+    fn meet(self, other: Value) -> Value {
+        match (self, other) {
+            (Value::Undefined, value) | (value, Value::Undefined) => value,
+            (Value::Constant(lhs), Value::Constant(rhs)) if lhs == rhs => self,
+            _ => Value::Overdefined,
+        }
+    }
+}
+
+// A place where a variable is defined: the phi node or the statement at an index of a block.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum Definition {
+    Phi(usize, usize),
+    Stmt(usize, usize),
+}
+
+struct ConstantPropagationImpl<'p> {
+    program: &'p Program,
+    semantics: ArithmeticSemantics,
+    values: HashMap<&'p str, Value>,
+    definitions: HashMap<&'p str, Definition>,
+    // The definitions that use each variable.
+    uses: HashMap<&'p str, Vec<Definition>>,
+}
+
+impl<'p> ConstantPropagationImpl<'p> {
+    fn new(program: &'p Program, semantics: ArithmeticSemantics) -> Self {
+        let mut result = Self {
+            program,
+            semantics,
+            values: HashMap::new(),
+            definitions: HashMap::new(),
+            uses: HashMap::new(),
+        };
+
+        for (block_index, block) in program.blocks.iter().enumerate() {
+            for (index, phi) in block.phis.iter().enumerate() {
+                let definition = Definition::Phi(block_index, index);
+                result.definitions.insert(&phi.lhs, definition);
+                for (_, atom) in &phi.arguments {
+                    result.add_use(atom, definition);
+                }
+            }
+            for (index, stmt) in block.body.iter().enumerate() {
+                if let Stmt::Assign { lhs, rhs } = stmt {
+                    let definition = Definition::Stmt(block_index, index);
+                    result.definitions.insert(lhs, definition);
+                    for atom in rhs.atoms() {
+                        result.add_use(atom, definition);
+                    }
+                }
+            }
+        }
+        for &variable in result.definitions.keys() {
+            result.values.insert(variable, Value::Undefined);
+        }
+
+        result
+    }
+
+    fn add_use(&mut self, atom: &'p Atom, definition: Definition) {
+        if let Some(variable) = atom.as_variable() {
+            self.uses.entry(variable).or_default().push(definition);
+        }
+    }
+
+    // A variable without a definition, which is used before it is assigned, is unknown.
+    fn atom_value(&self, atom: &Atom) -> Value {
+        match atom {
+            Atom::Integer(value) => Value::Constant(*value),
+            Atom::Variable(name) => self
+                .values
+                .get(name.as_str())
+                .copied()
+                .unwrap_or(Value::Overdefined),
+        }
+    }
+
+    // An operation that overflows is overdefined, so that it still happens at runtime.
+    fn expr_value(&self, expr: &Expr) -> Value {
+        let operands: Vec<Value> = expr
+            .atoms()
+            .into_iter()
+            .map(|atom| self.atom_value(atom))
+            .collect();
+        if operands.contains(&Value::Overdefined) {
+            return Value::Overdefined;
+        }
+        if operands.contains(&Value::Undefined) {
+            return Value::Undefined;
+        }
+        let constants: Vec<i64> = operands
+            .into_iter()
+            .map(|value| match value {
+                Value::Constant(value) => value,
+                _ => unreachable!(),
+            })
+            .collect();
+
+        let result = match expr {
+            Expr::Atom(_) => Ok(constants[0]),
+            Expr::Read => return Value::Overdefined,
+            Expr::UnaryOperation {
+                kind: UnaryOpKind::Minus,
+                ..
+            } => self.semantics.neg(constants[0]),
+            Expr::BinaryOperation { kind, .. } => match kind {
+                BinaryOpKind::Add => self.semantics.add(constants[0], constants[1]),
+                BinaryOpKind::Sub => self.semantics.sub(constants[0], constants[1]),
+            },
+        };
+        result.map_or(Value::Overdefined, Value::Constant)
+    }
+
+    fn definition_value(&self, definition: Definition) -> Value {
+        match definition {
+            Definition::Phi(block, index) => self.program.blocks[block].phis[index]
+                .arguments
+                .iter()
+                .fold(Value::Undefined, |value, (_, atom)| {
+                    value.meet(self.atom_value(atom))
+                }),
+            Definition::Stmt(block, index) => match &self.program.blocks[block].body[index] {
+                Stmt::Assign { rhs, .. } => self.expr_value(rhs),
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    fn defined_variable(&self, definition: Definition) -> &'p str {
+        match definition {
+            Definition::Phi(block, index) => &self.program.blocks[block].phis[index].lhs,
+            Definition::Stmt(block, index) => match &self.program.blocks[block].body[index] {
+                Stmt::Assign { lhs, .. } => lhs,
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    /// Evaluates every definition, then the users of the variables whose value changes, until no
+    /// value changes. Values only move down the lattice, so this terminates.
+    fn run(mut self) -> HashMap<&'p str, Value> {
+        let mut worklist: Vec<Definition> = self.definitions.values().copied().collect();
+        while let Some(definition) = worklist.pop() {
+            let variable = self.defined_variable(definition);
+            let value = self.definition_value(definition);
+            if value != self.values[variable] {
+                self.values.insert(variable, value);
+                worklist.extend(self.uses.get(variable).into_iter().flatten().copied());
+            }
+        }
+        self.values
+    }
+}
+
+/// Finds the variables of an SSA program that always hold the same value under `semantics`,
+/// replaces their uses by the value, and removes the phi nodes that define them. Since C_var has
+/// no conditional jumps yet, every block that remains after `construct_ssa` is executable.
+pub(crate) fn sparse_constant_propagation(
+    mut program: Program,
+    semantics: ArithmeticSemantics,
+) -> Program {
+    let constants: HashMap<String, i64> = ConstantPropagationImpl::new(&program, semantics)
+        .run()
+        .into_iter()
+        .filter_map(|(variable, value)| match value {
+            Value::Constant(value) => Some((variable.to_string(), value)),
+            _ => None,
+        })
+        .collect();
+
+    let replace = |atom: &mut Atom| {
+        if let Atom::Variable(name) = atom {
+            if let Some(value) = constants.get(name) {
+                *atom = Atom::Integer(*value);
+            }
+        }
+    };
+
+    for block in &mut program.blocks {
+        block.phis.retain(|phi| !constants.contains_key(&phi.lhs));
+        for phi in &mut block.phis {
+            phi.arguments.iter_mut().for_each(|(_, atom)| replace(atom));
+        }
+
+        for stmt in &mut block.body {
+            match stmt {
+                Stmt::Assign { lhs, rhs } => match constants.get(lhs) {
+                    Some(value) => *rhs = Atom::Integer(*value).into(),
+                    None => rhs.atoms_mut().into_iter().for_each(replace),
+                },
+                Stmt::Return(expr) => expr.atoms_mut().into_iter().for_each(replace),
+                Stmt::Goto(_) => (),
+            }
+        }
+    }
+
+    let defined: Vec<String> = program
+        .blocks
+        .iter()
+        .flat_map(|block| {
+            block
+                .phis
+                .iter()
+                .map(|phi| phi.lhs.clone())
+                .chain(block.body.iter().filter_map(|stmt| match stmt {
+                    Stmt::Assign { lhs, .. } => Some(lhs.clone()),
+                    _ => None,
+                }))
+        })
+        .collect();
+    program
+        .locals
+        .retain(|name| defined.contains(name) || !constants.contains_key(name));
+
+    program
+}
+
+#[cfg(test)]
+mod test {
+    use frontend::parse_expr;
+
+    use crate::{
+        construct_ssa::construct_ssa,
+        explicate_control::explicate_control,
+        ir::{cvar::Program as CProgram, ssa::Phi},
+        remove_complex_operands::remove_complex_operands,
+        uniquify::uniquify_expr,
+    };
+
+    use super::*;
+
+    fn propagate(code: &str, semantics: ArithmeticSemantics) -> String {
+        let program = explicate_control(remove_complex_operands(
+            uniquify_expr(parse_expr(code).unwrap()).unwrap(),
+        ));
+        sparse_constant_propagation(construct_ssa(program), semantics).to_string()
+    }
+
+    #[test]
+    fn propagate_constants() {
+        assert_eq!(
+            propagate(
+                "(let ([x 5]) (let ([y (- x)]) (+ read (+ y x))))",
+                ArithmeticSemantics::Checked
+            )
+            .trim(),
+            r#"
+local: [x0, x1, tmp0, tmp1]
+start:
+    x0 = 5;
+    x1 = -5;
+    tmp0 = read;
+    tmp1 = 0;
+    return (+ tmp0 0);
+            "#
+            .trim()
+        );
+
+        // The overflow is kept, unless arithmetic wraps around.
+        let code = "(let ([x (- 9223372036854775807 (- 1))]) (+ x 1))";
+        assert_eq!(
+            propagate(code, ArithmeticSemantics::Trapping).trim(),
+            r#"
+local: [tmp0, x0]
+start:
+    tmp0 = -1;
+    x0 = (- 9223372036854775807 -1);
+    return (+ x0 1);
+            "#
+            .trim()
+        );
+        assert_eq!(
+            propagate(code, ArithmeticSemantics::Wrapping).trim(),
+            r#"
+local: [tmp0, x0]
+start:
+    tmp0 = -1;
+    x0 = -9223372036854775808;
+    return (+ -9223372036854775808 1);
+            "#
+            .trim()
+        );
+    }
+
+    #[test]
+    fn propagate_through_phis() {
+        let variable = |name: &str| Atom::Variable(name.to_string());
+
+        // The value of `x` is the same around the loop, but `y` changes.
+        let mut program = CProgram::new();
+        for name in ["x", "y"] {
+            program.create_local_variable(name.to_string());
+        }
+        program.create_assign("x".to_string(), Atom::Integer(3).into());
+        program.create_assign("y".to_string(), Expr::Read);
+        program.create_goto("loop".to_string());
+        program.create_block("loop".to_string());
+        program.create_assign("x".to_string(), variable("x").into());
+        program.create_assign(
+            "y".to_string(),
+            Expr::BinaryOperation {
+                kind: BinaryOpKind::Sub,
+                left_operand: variable("x"),
+                right_operand: Atom::Integer(1),
+            },
+        );
+        program.create_goto("loop".to_string());
+
+        let program =
+            sparse_constant_propagation(construct_ssa(program), ArithmeticSemantics::Checked);
+        assert!(program.blocks[1].phis.is_empty());
+        assert_eq!(
+            program.to_string().trim(),
+            r#"
+local: [x, y, x.2, y.1]
+start:
+    x = 3;
+    y = read;
+    goto loop;
+loop:
+    x.2 = 3;
+    y.1 = 2;
+    goto loop;
+            "#
+            .trim()
+        );
+
+        // A phi node of different constants is overdefined.
+        let mut program = construct_ssa(CProgram::new());
+        program.blocks[0].phis.push(Phi {
+            lhs: "z".to_string(),
+            arguments: vec![
+                ("a".to_string(), Atom::Integer(1)),
+                ("b".to_string(), Atom::Integer(2)),
+            ],
+        });
+        program.blocks[0]
+            .body
+            .push(Stmt::Return(variable("z").into()));
+        let propagated = sparse_constant_propagation(program.clone(), ArithmeticSemantics::Checked);
+        assert_eq!(propagated, program);
+    }
+}
@@ -2,12 +2,7 @@ use std::collections::HashMap;
 
 use frontend::Expr;
 
-use crate::NameGenerator;
-
-#[derive(Debug, Eq, PartialEq, Clone)]
-pub enum PassError {
-    UnknownIdentifier(String),
-}
+use crate::{NameGenerator, PassError};
 
 struct UniquifyImpl {
     name_gen: NameGenerator,
@@ -143,5 +138,13 @@ mod test {
                 .to_string(),
             "(let ([x1 (let ([x0 1]) (+ x0 2))]) (- x1))"
         );
+
+        // parallel bindings
+        assert_eq!(
+            uniquify_expr(parse_expr("let ([x 1]) (let ([x 2] [y x]) (+ x y))").unwrap())
+                .unwrap()
+                .to_string(),
+            "(let ([x0 1]) (let ([x1 2]) (let ([x2 x0]) (let ([x3 x1]) (+ x3 x2)))))"
+        );
     }
 }
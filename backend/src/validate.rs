@@ -0,0 +1,593 @@
+use std::{collections::VecDeque, fmt::Display};
+
+use frontend::{
+    interp_expr_with_options, ArithmeticSemantics, Evaluation, Expr, InterpOptions,
+    InterpreterError,
+};
+
+use crate::{
+    destruct_ssa::destruct_ssa,
+    interp::{
+        cvar::{interp_cvar_with_options, CVarInterpError, CVarInterpOptions},
+        x86::{emulate_x86_with_options, EmulatorError, EmulatorOptions},
+    },
+    ir::{cvar::Program as CProgram, x86::VarProgram},
+    pass_manager::{Ir, IrForm, PassFilter, PassManager, PassManagerOptions},
+    runtime::OverflowTrap,
+    verify::{pipeline_verifier, verify_cvar},
+    PassError,
+};
+
+/// The observable behavior of a program at some stage of the compiler.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Outcome {
+    Value(Evaluation),
+    // An error that every stage must report in the same way, e.g. running out of input.
+    Error(InterpreterError),
+    // The compiled program exits through an overflow handler. Only produced by x86 programs in
+    // `ArithmeticSemantics::Trapping` mode.
+    Trap(OverflowTrap),
+    // An error that only exists in one IR, e.g. reading an uninitialized register. It always
+    // indicates a bug in a pass.
+    Invalid(String),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Divergence {
+    // The first pass whose output does not behave like the source program.
+    pub pass: String,
+    pub expected: Outcome,
+    pub actual: Outcome,
+    // The difference between the IR before and after the pass.
+    pub ir_diff: String,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Value(evaluation) => write!(
+                f,
+                "{} (after reading {} inputs)",
+                evaluation.value, evaluation.inputs_consumed
+            ),
+            Outcome::Error(error) => write!(f, "error: {:?}", error),
+            Outcome::Trap(trap) => write!(
+                f,
+                "overflow trap: {:?} (exit code {})",
+                trap,
+                trap.exit_code()
+            ),
+            Outcome::Invalid(error) => write!(f, "invalid program: {}", error),
+        }
+    }
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "the output of `{}` diverges", self.pass)?;
+        writeln!(f, "expected: {}", self.expected)?;
+        writeln!(f, "actual:   {}", self.actual)?;
+        write!(f, "{}", self.ir_diff)
+    }
+}
+
+impl From<Result<Evaluation, InterpreterError>> for Outcome {
+    fn from(value: Result<Evaluation, InterpreterError>) -> Self {
+        match value {
+            Ok(evaluation) => Outcome::Value(evaluation),
+            Err(error) => Outcome::Error(error),
+        }
+    }
+}
+
+impl From<Result<Evaluation, CVarInterpError>> for Outcome {
+    fn from(value: Result<Evaluation, CVarInterpError>) -> Self {
+        match value {
+            Ok(evaluation) => Outcome::Value(evaluation),
+            Err(CVarInterpError::Frontend(error)) => Outcome::Error(error),
+            Err(error) => Outcome::Invalid(format!("{:?}", error)),
+        }
+    }
+}
+
+impl From<Result<Evaluation, EmulatorError>> for Outcome {
+    fn from(value: Result<Evaluation, EmulatorError>) -> Self {
+        match value {
+            Ok(evaluation) => Outcome::Value(evaluation),
+            Err(EmulatorError::Frontend(error)) => Outcome::Error(error),
+            Err(EmulatorError::Exit { code }) => match OverflowTrap::from_exit_code(code) {
+                Some(trap) => Outcome::Trap(trap),
+                None => Outcome::Invalid(format!("exit with code {}", code)),
+            },
+            Err(error) => Outcome::Invalid(format!("{:?}", error)),
+        }
+    }
+}
+
+/// Computes a line diff between `before` and `after`, where unchanged lines are prefixed with
+/// two spaces, removed lines with `- ` and added lines with `+ `. Only `context` unchanged lines
+/// around each change are kept.
+fn line_diff(before: &str, after: &str, context: usize) -> String {
+    let before: Vec<_> = before.lines().collect();
+    let after: Vec<_> = after.lines().collect();
+
+    // lcs[i][j] is the length of the longest common subsequence of before[i..] and after[j..].
+    let mut lcs = vec![vec![0; after.len() + 1]; before.len() + 1];
+    for i in (0..before.len()).rev() {
+        for j in (0..after.len()).rev() {
+            lcs[i][j] = if before[i] == after[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < before.len() || j < after.len() {
+        if i < before.len() && j < after.len() && before[i] == after[j] {
+            lines.push(('=', before[i]));
+            i += 1;
+            j += 1;
+        } else if i < before.len() && (j == after.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(('-', before[i]));
+            i += 1;
+        } else {
+            lines.push(('+', after[j]));
+            j += 1;
+        }
+    }
+
+    let is_near_change = |index: usize| {
+        lines[index.saturating_sub(context)..(index + context + 1).min(lines.len())]
+            .iter()
+            .any(|(kind, _)| *kind != '=')
+    };
+
+    let mut result = String::new();
+    let mut skipped = false;
+    for (index, (kind, line)) in lines.iter().enumerate() {
+        if !is_near_change(index) {
+            skipped = true;
+            continue;
+        }
+        if skipped {
+            result.push_str("  ...\n");
+            skipped = false;
+        }
+        let prefix = match kind {
+            '=' => ' ',
+            other => *other,
+        };
+        result.push_str(&format!("{} {}\n", prefix, line));
+    }
+    if skipped {
+        result.push_str("  ...\n");
+    }
+
+    result
+}
+
+// The number of statements or instructions that a compiled program may execute before it is
+// considered to loop forever. The source programs have no loops, so they take far fewer steps.
+const FUEL: u64 = 1_000_000;
+
+struct Validator<'a> {
+    inputs: &'a [i64],
+    semantics: ArithmeticSemantics,
+    // The behavior of the source program.
+    expected: Outcome,
+}
+
+// Stops the pipeline at the first pass that fails or whose output diverges.
+enum Failure {
+    Pass(PassError),
+    Divergence(Divergence),
+}
+
+impl From<PassError> for Failure {
+    fn from(value: PassError) -> Self {
+        Failure::Pass(value)
+    }
+}
+
+impl<'a> Validator<'a> {
+    fn input(&self) -> VecDeque<i64> {
+        self.inputs.iter().copied().collect()
+    }
+
+    fn interp_cvar(&self, program: &CProgram) -> Outcome {
+        let options = CVarInterpOptions {
+            fuel: Some(FUEL),
+            semantics: self.semantics,
+        };
+        interp_cvar_with_options(program, &mut self.input(), options).into()
+    }
+
+    fn emulate_x86(&self, program: &VarProgram) -> Outcome {
+        let options = EmulatorOptions { fuel: Some(FUEL) };
+        emulate_x86_with_options(program, &mut self.input(), options).into()
+    }
+
+    fn check(
+        &self,
+        pass: &str,
+        before: &dyn Display,
+        after: &dyn Display,
+        actual: Outcome,
+    ) -> Result<(), Divergence> {
+        self.check_against(&self.expected, pass, before, after, actual)
+    }
+
+    fn check_against(
+        &self,
+        expected: &Outcome,
+        pass: &str,
+        before: &dyn Display,
+        after: &dyn Display,
+        actual: Outcome,
+    ) -> Result<(), Divergence> {
+        if actual == *expected {
+            return Ok(());
+        }
+
+        Err(Divergence {
+            pass: pass.to_string(),
+            expected: expected.clone(),
+            actual,
+            ir_diff: line_diff(&before.to_string(), &after.to_string(), 3),
+        })
+    }
+
+    fn check_x86(
+        &self,
+        pass: &str,
+        before: &dyn Display,
+        after: &VarProgram,
+    ) -> Result<(), Divergence> {
+        let expected = match (&self.expected, self.semantics) {
+            // Without overflow checks, the x86 arithmetic instructions silently wrap around, so
+            // programs that overflow are not expected to behave like the source program.
+            (
+                Outcome::Error(InterpreterError::ArithmeticOverflow(_)),
+                ArithmeticSemantics::Checked,
+            ) => return Ok(()),
+            (
+                Outcome::Error(InterpreterError::ArithmeticOverflow(kind)),
+                ArithmeticSemantics::Trapping,
+            ) => Outcome::Trap(kind.into()),
+            (expected, _) => expected.clone(),
+        };
+
+        let actual = self.emulate_x86(after);
+        self.check_against(&expected, pass, before, after, actual)
+    }
+
+    /// Runs the output of `pass` with the interpreter or the emulator of its IR. A malformed
+    /// output is reported as a divergence, since the interpreters may not handle it.
+    fn check_pass(
+        &self,
+        pass: &str,
+        before: &Ir,
+        after: &Ir,
+        form: IrForm,
+    ) -> Result<(), Divergence> {
+        if let Err(error) = pipeline_verifier(pass, after, form) {
+            return self.check(pass, before, after, Outcome::Invalid(error));
+        }
+
+        match after {
+            Ir::Source(expr) => {
+                let options = InterpOptions {
+                    semantics: self.semantics,
+                    ..InterpOptions::default()
+                };
+                let actual = interp_expr_with_options(expr, &mut self.input(), options).into();
+                self.check(pass, before, after, actual)
+            }
+            Ir::CVar(program) => self.check(pass, before, after, self.interp_cvar(program)),
+            // There is no interpreter for the SSA form, so the program is run after it has been
+            // converted back.
+            Ir::Ssa(program) => {
+                let program = destruct_ssa(program.clone());
+                let actual = match verify_cvar(&program) {
+                    Ok(()) => self.interp_cvar(&program),
+                    Err(error) => Outcome::Invalid(error.to_string()),
+                };
+                self.check(pass, before, after, actual)
+            }
+            Ir::X86(program) => self.check_x86(pass, before, program),
+        }
+    }
+
+    /// Runs the default pipeline with the optimization passes selected by `optimize`, and checks
+    /// the output of every pass.
+    fn run(&self, expr: &Expr, optimize: PassFilter) -> Result<(), Divergence> {
+        let options = PassManagerOptions {
+            optimize,
+            ..PassManagerOptions::default()
+        };
+        let mut manager = PassManager::with_default_pipeline(options, self.semantics);
+
+        let mut before = Ir::Source(expr.clone());
+        let mut passes_run = 0;
+        let result = manager.run_with_hook(before.clone(), |pass, ir, form| {
+            self.check_pass(pass, &before, ir, form)
+                .map_err(Failure::Divergence)?;
+            before = ir.clone();
+            passes_run += 1;
+            Ok(())
+        });
+
+        let actual = match result {
+            Ok(_) => return Ok(()),
+            Err(Failure::Divergence(divergence)) => return Err(divergence),
+            Err(Failure::Pass(PassError::UnknownPass(name))) => {
+                panic!("`{}` is not an optimization pass", name)
+            }
+            Err(Failure::Pass(PassError::UnknownIdentifier(name))) => {
+                Outcome::Error(InterpreterError::UnknownIdentifier(name))
+            }
+            Err(Failure::Pass(error)) => Outcome::Invalid(format!("{:?}", error)),
+        };
+        self.check(manager.pass_names()[passes_run], &before, &"", actual)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct ValidateOptions {
+    pub semantics: ArithmeticSemantics,
+    // The optional optimization passes that are validated too.
+    pub optimize: PassFilter,
+}
+
+/// Runs `expr` with `inputs` after every pass of the compiler, using the interpreter or the
+/// emulator of the IR produced by the pass, and reports the first pass after which the program
+/// behaves differently from `frontend::interp_expr` on the source program. Returns the behavior
+/// of the source program if all passes agree.
+pub fn validate_passes(expr: &Expr, inputs: &[i64]) -> Result<Outcome, Divergence> {
+    validate_passes_with_options(expr, inputs, ValidateOptions::default())
+}
+
+pub fn validate_passes_with_semantics(
+    expr: &Expr,
+    inputs: &[i64],
+    semantics: ArithmeticSemantics,
+) -> Result<Outcome, Divergence> {
+    let options = ValidateOptions {
+        semantics,
+        ..ValidateOptions::default()
+    };
+    validate_passes_with_options(expr, inputs, options)
+}
+
+/// Like `validate_passes_with_semantics`, but also runs every optional optimization pass.
+pub fn validate_optimized_passes(
+    expr: &Expr,
+    inputs: &[i64],
+    semantics: ArithmeticSemantics,
+) -> Result<Outcome, Divergence> {
+    let options = ValidateOptions {
+        semantics,
+        optimize: PassFilter::All,
+    };
+    validate_passes_with_options(expr, inputs, options)
+}
+
+/// Like `validate_passes`, with the given semantics and optimization passes. Panics if
+/// `options.optimize` names a pass that is not in the pipeline.
+pub fn validate_passes_with_options(
+    expr: &Expr,
+    inputs: &[i64],
+    options: ValidateOptions,
+) -> Result<Outcome, Divergence> {
+    let interp_options = InterpOptions {
+        semantics: options.semantics,
+        ..InterpOptions::default()
+    };
+    let validator = Validator {
+        inputs,
+        semantics: options.semantics,
+        expected: interp_expr_with_options(
+            expr,
+            &mut inputs.iter().copied().collect::<VecDeque<_>>(),
+            interp_options,
+        )
+        .into(),
+    };
+    validator.run(expr, options.optimize)?;
+    Ok(validator.expected)
+}
+
+#[cfg(test)]
+mod test {
+    use frontend::{
+        parse_expr, shrink_expr, test_support::env_or, ExprGenerator, GeneratorConfig, OverflowKind,
+    };
+
+    use super::*;
+
+    #[test]
+    fn validate_passes_test() {
+        for (code, inputs, value, inputs_consumed) in [
+            ("42", vec![], 42, 0),
+            ("(- 10 1 2 3)", vec![], 4, 0),
+            ("(let ([x 100000]) (+ x (- 70000)))", vec![], 30000, 0),
+            ("(let ([x read] [y read]) (- x y))", vec![10, 3, 5], 7, 2),
+            (
+                "(let ([x 1]) (let ([x 2] [y x]) (let* ([x (+ x y)] [y x]) (+ x y))))",
+                vec![],
+                6,
+                0,
+            ),
+            (
+                "(+ (let ([x read]) (- x)) (- read read))",
+                vec![1, 2, 3],
+                -2,
+                3,
+            ),
+        ] {
+            let expr = parse_expr(code).unwrap();
+            let expected = Ok(Outcome::Value(Evaluation {
+                value,
+                inputs_consumed,
+            }));
+            assert_eq!(validate_passes(&expr, &inputs), expected);
+            assert_eq!(
+                validate_optimized_passes(&expr, &inputs, ArithmeticSemantics::Checked),
+                expected
+            );
+        }
+
+        assert_eq!(
+            validate_passes(&parse_expr("(+ read read)").unwrap(), &[1]),
+            Ok(Outcome::Error(InterpreterError::EndOfInput { consumed: 1 }))
+        );
+
+        assert_eq!(
+            validate_passes(&parse_expr("(+ 9223372036854775807 1)").unwrap(), &[]),
+            Ok(Outcome::Error(InterpreterError::ArithmeticOverflow(
+                OverflowKind::AddOverflow(i64::MAX, 1)
+            )))
+        );
+
+        assert_eq!(
+            validate_passes(&parse_expr("(let ([x 1]) y)").unwrap(), &[]),
+            Ok(Outcome::Error(InterpreterError::UnknownIdentifier(
+                "y".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn report_divergence() {
+        let validator = Validator {
+            inputs: &[],
+            semantics: ArithmeticSemantics::Checked,
+            expected: Outcome::Value(Evaluation {
+                value: 1,
+                inputs_consumed: 0,
+            }),
+        };
+
+        let before = "main:\n    movq $1, x\n    movq x, y\n    movq y, %rax\n    jmp conclusion\nconclusion:\n";
+        let after = "main:\n    movq $1, x\n    movq x, y\n    movq x, %rax\n    jmp conclusion\nconclusion:\n";
+        let divergence = validator
+            .check(
+                "some_pass",
+                &before,
+                &after,
+                Outcome::Invalid("UninitializedVariable(\"x\")".to_string()),
+            )
+            .unwrap_err();
+
+        assert_eq!(divergence.pass, "some_pass");
+        assert_eq!(
+            divergence.to_string(),
+            r#"the output of `some_pass` diverges
+expected: 1 (after reading 0 inputs)
+actual:   invalid program: UninitializedVariable("x")
+  main:
+      movq $1, x
+      movq x, y
+-     movq y, %rax
++     movq x, %rax
+      jmp conclusion
+  conclusion:
+"#
+        );
+    }
+
+    #[test]
+    fn validate_pass_filter() {
+        let expr = parse_expr("(let ([x read]) (+ (- x 0) 1))").unwrap();
+        let options = ValidateOptions {
+            semantics: ArithmeticSemantics::Wrapping,
+            optimize: PassFilter::Passes(vec!["rewrite".to_string(), "peephole".to_string()]),
+        };
+        assert_eq!(
+            validate_passes_with_options(&expr, &[4], options),
+            Ok(Outcome::Value(Evaluation {
+                value: 5,
+                inputs_consumed: 1
+            }))
+        );
+    }
+
+    #[test]
+    fn diff_context() {
+        let before: String = (0..20).map(|line| format!("{}\n", line)).collect();
+        let after = before.replace("10\n", "ten\n");
+
+        assert_eq!(
+            line_diff(&before, &after, 2),
+            "  ...\n  8\n  9\n- 10\n+ ten\n  11\n  12\n  ...\n"
+        );
+        assert_eq!(line_diff(&before, &before, 2), "  ...\n");
+    }
+
+    #[test]
+    fn validate_semantics() {
+        let expr = parse_expr("(let ([x read]) (- (+ x 1) 2))").unwrap();
+        let validate = |semantics| validate_passes_with_semantics(&expr, &[i64::MAX], semantics);
+
+        assert_eq!(
+            validate(ArithmeticSemantics::Wrapping),
+            Ok(Outcome::Value(Evaluation {
+                value: i64::MAX - 1,
+                inputs_consumed: 1
+            }))
+        );
+        for semantics in [ArithmeticSemantics::Checked, ArithmeticSemantics::Trapping] {
+            assert_eq!(
+                validate(semantics),
+                Ok(Outcome::Error(InterpreterError::ArithmeticOverflow(
+                    OverflowKind::AddOverflow(i64::MAX, 1)
+                )))
+            );
+        }
+    }
+
+    // Compiles random programs, with and without the optimization passes, and compares the result
+    // of every pass with the interpreter. A failing program is minimized before it is reported.
+    fn fuzz_passes(seed: u64, iterations: u64) {
+        let mut generator = ExprGenerator::new(seed, GeneratorConfig::default());
+        for iteration in 0..iterations {
+            let expr = generator.generate();
+            let inputs = generator.generate_inputs();
+            for semantics in [
+                ArithmeticSemantics::Wrapping,
+                ArithmeticSemantics::Checked,
+                ArithmeticSemantics::Trapping,
+            ] {
+                let validate = |expr: &Expr| {
+                    validate_passes_with_semantics(expr, &inputs, semantics)?;
+                    validate_optimized_passes(expr, &inputs, semantics)
+                };
+                if validate(&expr).is_err() {
+                    let minimized = shrink_expr(&expr, |expr| validate(expr).is_err());
+                    panic!(
+                        "iteration {iteration} with seed {seed}, inputs {inputs:?} and {semantics:?} \
+                         semantics failed: {}\nminimized program: {minimized}\n{}",
+                        expr,
+                        validate(&minimized).unwrap_err(),
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fuzz_passes_test() {
+        fuzz_passes(env_or("FUZZ_SEED", 0), 300);
+    }
+
+    // Run with `cargo test -- --ignored`. The number of programs and the seed can be set with the
+    // `FUZZ_ITERATIONS` and `FUZZ_SEED` environment variables.
+    #[test]
+    #[ignore]
+    fn fuzz_passes_long_running() {
+        fuzz_passes(env_or("FUZZ_SEED", 1), env_or("FUZZ_ITERATIONS", 100_000));
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+
+use crate::ir::cvar::{Atom, BinaryOpKind, Block, Expr, Program, Stmt, UnaryOpKind};
+
+// The operation that computes a value from the value numbers of its operands. The operands of
+// `Add` are sorted, since it is commutative.
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+enum ValueKey {
+    Neg(usize),
+    Add(usize, usize),
+    Sub(usize, usize),
+}
+
+#[derive(Default)]
+struct ValueNumberingImpl {
+    next_number: usize,
+    constants: HashMap<i64, usize>,
+    // The value number of the current value of each variable.
+    variables: HashMap<String, usize>,
+    // The position of the assignment that gave each variable its current value, where the
+    // variables defined in other blocks count as assigned in the order of their first use.
+    assigned_at: HashMap<String, usize>,
+    next_assignment: usize,
+    expressions: HashMap<ValueKey, usize>,
+    // An atom that holds each value, which can replace any later computation of the value.
+    holders: HashMap<usize, Atom>,
+}
+
+impl ValueNumberingImpl {
+    fn fresh_number(&mut self) -> usize {
+        self.next_number += 1;
+        self.next_number - 1
+    }
+
+    fn atom_number(&mut self, atom: &Atom) -> usize {
+        let number = match atom {
+            Atom::Integer(value) => self.constants.get(value).copied(),
+            Atom::Variable(name) => self.variables.get(name).copied(),
+        };
+        if let Some(number) = number {
+            return number;
+        }
+
+        // The first use of a constant, or of a variable defined in another block.
+        let number = self.fresh_number();
+        match atom {
+            Atom::Integer(value) => self.constants.insert(*value, number),
+            Atom::Variable(name) => {
+                self.record_assignment(name);
+                self.variables.insert(name.clone(), number)
+            }
+        };
+        self.holders.insert(number, atom.clone());
+        number
+    }
+
+    fn expr_key(&mut self, expr: &Expr) -> Option<ValueKey> {
+        match expr {
+            Expr::Atom(_) | Expr::Read => None,
+            Expr::UnaryOperation {
+                kind: UnaryOpKind::Minus,
+                operand,
+            } => Some(ValueKey::Neg(self.atom_number(operand))),
+            Expr::BinaryOperation {
+                kind,
+                left_operand,
+                right_operand,
+            } => {
+                let lhs = self.atom_number(left_operand);
+                let rhs = self.atom_number(right_operand);
+                Some(match kind {
+                    BinaryOpKind::Add => ValueKey::Add(lhs.min(rhs), lhs.max(rhs)),
+                    BinaryOpKind::Sub => ValueKey::Sub(lhs, rhs),
+                })
+            }
+        }
+    }
+
+    /// Replaces `expr` by an atom that already holds its value, if there is one. Returns the value
+    /// number of `expr`.
+    fn number_expr(&mut self, expr: &mut Expr) -> usize {
+        if let Expr::Atom(atom) = expr {
+            return self.atom_number(atom);
+        }
+
+        // Every `read` produces a new value.
+        let Some(key) = self.expr_key(expr) else {
+            return self.fresh_number();
+        };
+
+        match self.expressions.get(&key) {
+            Some(number) => {
+                if let Some(holder) = self.holders.get(number) {
+                    *expr = Expr::Atom(holder.clone());
+                }
+                *number
+            }
+            None => {
+                let number = self.fresh_number();
+                self.expressions.insert(key, number);
+                number
+            }
+        }
+    }
+
+    fn record_assignment(&mut self, name: &str) {
+        self.assigned_at
+            .insert(name.to_string(), self.next_assignment);
+        self.next_assignment += 1;
+    }
+
+    fn assign(&mut self, lhs: &str, number: usize) {
+        // `lhs` no longer holds its previous value, which may still be held by other variables. The
+        // one that has held it the longest becomes its holder, so that the output does not depend
+        // on the iteration order of `variables`.
+        if let Some(previous) = self.variables.remove(lhs) {
+            if self.holders.get(&previous) == Some(&Atom::Variable(lhs.to_string())) {
+                self.holders.remove(&previous);
+                if let Some((name, _)) = self
+                    .variables
+                    .iter()
+                    .filter(|(_, number)| **number == previous)
+                    .min_by_key(|(name, _)| self.assigned_at[*name])
+                {
+                    self.holders.insert(previous, Atom::Variable(name.clone()));
+                }
+            }
+        }
+
+        self.variables.insert(lhs.to_string(), number);
+        self.record_assignment(lhs);
+        self.holders
+            .entry(number)
+            .or_insert_with(|| Atom::Variable(lhs.to_string()));
+    }
+
+    fn number_block(mut self, block: &mut Block) {
+        for stmt in &mut block.body {
+            match stmt {
+                Stmt::Assign { lhs, rhs } => {
+                    let number = self.number_expr(rhs);
+                    self.assign(lhs, number);
+                }
+                Stmt::Return(expr) => {
+                    self.number_expr(expr);
+                }
+                Stmt::Goto(_) => (),
+            }
+        }
+    }
+}
+
+/// Replaces the operations that compute a value which is already held by an atom in the same
+/// block by that atom. The copies that this introduces are removed by `copy_propagation`.
+pub(crate) fn local_value_numbering(mut program: Program) -> Program {
+    for block in &mut program.blocks {
+        ValueNumberingImpl::default().number_block(block);
+    }
+    program
+}
+
+#[cfg(test)]
+mod test {
+    use frontend::parse_expr;
+
+    use crate::{
+        explicate_control::explicate_control, remove_complex_operands::remove_complex_operands,
+        uniquify::uniquify_expr,
+    };
+
+    use super::*;
+
+    fn number(code: &str) -> String {
+        let program = explicate_control(remove_complex_operands(
+            uniquify_expr(parse_expr(code).unwrap()).unwrap(),
+        ));
+        local_value_numbering(program).to_string()
+    }
+
+    #[test]
+    fn eliminate_common_subexpressions() {
+        assert_eq!(
+            number("(let ([x read] [y read]) (- (+ x y) (+ y x)))").trim(),
+            r#"
+local: [x0, x1, tmp0, tmp1]
+start:
+    x0 = read;
+    x1 = read;
+    tmp0 = (+ x0 x1);
+    tmp1 = tmp0;
+    return (- tmp0 tmp1);
+            "#
+            .trim()
+        );
+
+        // The operands are compared by value, and subtraction is not commutative.
+        assert_eq!(
+            number("(let ([x read]) (let ([y x]) (+ (- x 1) (+ (- y 1) (- 1 x)))))").trim(),
+            r#"
+local: [x0, x1, tmp0, tmp1, tmp2, tmp3]
+start:
+    x0 = read;
+    x1 = x0;
+    tmp0 = (- x0 1);
+    tmp1 = tmp0;
+    tmp2 = (- 1 x0);
+    tmp3 = (+ tmp1 tmp2);
+    return (+ tmp0 tmp3);
+            "#
+            .trim()
+        );
+
+        // Every `read` is kept.
+        assert_eq!(
+            number("(- (+ read 1) (+ read 1))").trim(),
+            r#"
+local: [tmp0, tmp1, tmp2, tmp3]
+start:
+    tmp0 = read;
+    tmp1 = (+ tmp0 1);
+    tmp2 = read;
+    tmp3 = (+ tmp2 1);
+    return (- tmp1 tmp3);
+            "#
+            .trim()
+        );
+    }
+
+    #[test]
+    fn reassigned_variables() {
+        let variable = |name: &str| Atom::Variable(name.to_string());
+        let negate = |name| Expr::UnaryOperation {
+            kind: UnaryOpKind::Minus,
+            operand: variable(name),
+        };
+
+        let mut program = Program::new();
+        for name in ["x", "y", "z"] {
+            program.create_local_variable(name.to_string());
+        }
+        program.create_assign("y".to_string(), negate("x"));
+        program.create_assign("z".to_string(), variable("y").into());
+        program.create_assign("y".to_string(), Expr::Read);
+        program.create_assign("y".to_string(), negate("x"));
+        program.create_assign("x".to_string(), Expr::Read);
+        program.create_terminator(negate("x"));
+
+        assert_eq!(
+            local_value_numbering(program).to_string().trim(),
+            r#"
+local: [x, y, z]
+start:
+    y = (- x);
+    z = y;
+    y = read;
+    y = z;
+    x = read;
+    return (- x);
+            "#
+            .trim()
+        );
+
+        // The variable that has held the value the longest replaces the computation.
+        let mut program = Program::new();
+        for name in ["x", "y", "z", "w"] {
+            program.create_local_variable(name.to_string());
+        }
+        program.create_assign("y".to_string(), negate("x"));
+        program.create_assign("w".to_string(), variable("y").into());
+        program.create_assign("z".to_string(), variable("y").into());
+        program.create_assign("y".to_string(), Expr::Read);
+        program.create_terminator(negate("x"));
+
+        assert_eq!(
+            local_value_numbering(program).to_string().trim(),
+            r#"
+local: [x, y, z, w]
+start:
+    y = (- x);
+    w = y;
+    z = y;
+    y = read;
+    return w;
+            "#
+            .trim()
+        );
+    }
+}
@@ -0,0 +1,870 @@
+use std::{collections::HashMap, fmt::Display};
+
+use frontend::Expr;
+
+use crate::{
+    ir::{
+        cvar::{Block as CBlock, Expr as CExpr, Program as CProgram, Stmt},
+        ssa::Program as SsaProgram,
+        x86::{Reg, VarArg, VarInstr, VarProgram},
+    },
+    pass_manager::{Ir, IrForm},
+};
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum VerifyErrorKind {
+    // An operand of an operation is not an integer or a variable.
+    ComplexOperand(String),
+    // An integer literal does not fit in an `i64`.
+    IntegerOutOfRange(u64),
+    UndeclaredVariable(String),
+    DuplicateLabel(String),
+    UnknownLabel(String),
+    // The block does not end with a `return` or a `goto`.
+    MissingTerminator,
+    // A `return` or a `goto` is followed by other statements.
+    StatementAfterTerminator,
+    // A variable of an SSA program is assigned by more than one phi node or statement.
+    MultipleAssignments(String),
+    // The labels of the arguments of the phi node that assigns the variable are not the
+    // predecessors of its block.
+    PhiPredecessorMismatch(String),
+    // A variable is used after every variable should have been assigned a home.
+    UnexpectedVariable(String),
+    TooManyMemoryOperands,
+    // The immediate cannot be encoded as a sign-extended 32-bit value.
+    ImmediateOutOfRange(i64),
+    // `callq` is executed when the stack pointer is not a multiple of 16 bytes below its value at
+    // the start of the program. `offset` is the number of bytes below it.
+    MisalignedStack { offset: i64 },
+    // The block is reached with different stack pointers.
+    InconsistentStack { offsets: (i64, i64) },
+    // %rsp is changed by something else than `pushq`, `popq`, or adding or subtracting an
+    // immediate.
+    UntrackedStackPointer,
+}
+
+/// The statement or the instruction at `index` in the block `block`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Location {
+    pub block: String,
+    pub index: usize,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct VerifyError {
+    pub kind: VerifyErrorKind,
+    // `None` for the source program, which has no blocks.
+    pub location: Option<Location>,
+}
+
+impl Display for VerifyErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use VerifyErrorKind::*;
+
+        match self {
+            ComplexOperand(operand) => write!(f, "the operand `{}` is not atomic", operand),
+            IntegerOutOfRange(value) => write!(f, "the integer {} does not fit in 64 bits", value),
+            UndeclaredVariable(name) => write!(f, "the variable `{}` is not declared", name),
+            DuplicateLabel(label) => write!(f, "the label `{}` is defined more than once", label),
+            UnknownLabel(label) => write!(f, "the label `{}` is not defined", label),
+            MissingTerminator => write!(f, "the block does not end with a return or a goto"),
+            StatementAfterTerminator => write!(f, "the statement follows a terminator"),
+            MultipleAssignments(name) => {
+                write!(f, "the variable `{}` is assigned more than once", name)
+            }
+            PhiPredecessorMismatch(name) => write!(
+                f,
+                "the arguments of the phi node of `{}` do not match the predecessors",
+                name
+            ),
+            UnexpectedVariable(name) => {
+                write!(f, "the variable `{}` has not been assigned a home", name)
+            }
+            TooManyMemoryOperands => write!(f, "the instruction has more than one memory operand"),
+            ImmediateOutOfRange(value) => {
+                write!(f, "the immediate {} does not fit in 32 bits", value)
+            }
+            MisalignedStack { offset } => write!(
+                f,
+                "the stack is not 16-byte aligned at the call ({} bytes are pushed)",
+                offset
+            ),
+            InconsistentStack { offsets } => write!(
+                f,
+                "the block is reached with {} and {} bytes pushed",
+                offsets.0, offsets.1
+            ),
+            UntrackedStackPointer => write!(f, "the change of %rsp cannot be tracked"),
+        }
+    }
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(location) = &self.location {
+            write!(f, "{}[{}]: ", location.block, location.index)?;
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+
+fn error_at(kind: VerifyErrorKind, block: &str, index: usize) -> VerifyError {
+    VerifyError {
+        kind,
+        location: Some(Location {
+            block: block.to_string(),
+            index,
+        }),
+    }
+}
+
+fn verify_atom(expr: &Expr) -> Result<(), VerifyError> {
+    match expr {
+        Expr::Integer(value) if i64::try_from(*value).is_err() => Err(VerifyError {
+            kind: VerifyErrorKind::IntegerOutOfRange(*value),
+            location: None,
+        }),
+        Expr::Integer(_) | Expr::Identifier(_) => Ok(()),
+        other => Err(VerifyError {
+            kind: VerifyErrorKind::ComplexOperand(other.to_string()),
+            location: None,
+        }),
+    }
+}
+
+/// Checks that `expr` is in the form produced by `remove_complex_operands`, i.e. that the operands
+/// of every operation are atomic.
+pub fn verify_monadic(expr: &Expr) -> Result<(), VerifyError> {
+    match expr {
+        Expr::Read | Expr::Identifier(_) => Ok(()),
+        Expr::Integer(_) => verify_atom(expr),
+        Expr::UnaryOperation { operand, .. } => verify_atom(operand),
+        Expr::BinaryOperation {
+            left_operand,
+            right_operand,
+            ..
+        } => {
+            verify_atom(left_operand)?;
+            verify_atom(right_operand)
+        }
+        Expr::Let {
+            init_expr, body, ..
+        } => {
+            verify_monadic(init_expr)?;
+            verify_monadic(body)
+        }
+    }
+}
+
+fn check_labels<'p>(
+    labels: impl Iterator<Item = &'p str>,
+) -> Result<Vec<&'p str>, VerifyErrorKind> {
+    let mut result: Vec<&str> = Vec::new();
+    for label in labels {
+        if result.contains(&label) {
+            return Err(VerifyErrorKind::DuplicateLabel(label.to_string()));
+        }
+        result.push(label);
+    }
+    Ok(result)
+}
+
+/// Checks that every variable of a C_var program is declared, that every block ends with exactly
+/// one terminator, and that every `goto` jumps to an existing block.
+pub fn verify_cvar(program: &CProgram) -> Result<(), VerifyError> {
+    let labels =
+        check_labels(program.blocks.iter().map(|block| block.label.as_str())).map_err(|kind| {
+            VerifyError {
+                kind,
+                location: None,
+            }
+        })?;
+
+    for block in &program.blocks {
+        let check_variable = |name: &str, index| {
+            if program.locals.iter().any(|local| local == name) {
+                Ok(())
+            } else {
+                Err(error_at(
+                    VerifyErrorKind::UndeclaredVariable(name.to_string()),
+                    &block.label,
+                    index,
+                ))
+            }
+        };
+        let check_expr = |expr: &CExpr, index| {
+            expr.variables()
+                .try_for_each(|name| check_variable(name, index))
+        };
+
+        for (index, stmt) in block.body.iter().enumerate() {
+            if index + 1 < block.body.len() && !matches!(stmt, Stmt::Assign { .. }) {
+                return Err(error_at(
+                    VerifyErrorKind::StatementAfterTerminator,
+                    &block.label,
+                    index + 1,
+                ));
+            }
+
+            match stmt {
+                Stmt::Assign { lhs, rhs } => {
+                    check_expr(rhs, index)?;
+                    check_variable(lhs, index)?;
+                }
+                Stmt::Return(expr) => check_expr(expr, index)?,
+                Stmt::Goto(label) if !labels.contains(&label.as_str()) => {
+                    return Err(error_at(
+                        VerifyErrorKind::UnknownLabel(label.clone()),
+                        &block.label,
+                        index,
+                    ))
+                }
+                Stmt::Goto(_) => (),
+            }
+        }
+
+        if !matches!(block.body.last(), Some(Stmt::Return(_) | Stmt::Goto(_))) {
+            return Err(error_at(
+                VerifyErrorKind::MissingTerminator,
+                &block.label,
+                block.body.len(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that an SSA program is a well-formed C_var program apart from its phi nodes, that every
+/// variable is assigned once, and that every phi node has one argument for each predecessor.
+pub fn verify_ssa(program: &SsaProgram) -> Result<(), VerifyError> {
+    let global_error = |kind| VerifyError {
+        kind,
+        location: None,
+    };
+
+    verify_cvar(&CProgram {
+        locals: program.locals.clone(),
+        blocks: program
+            .blocks
+            .iter()
+            .map(|block| CBlock {
+                label: block.label.clone(),
+                body: block.body.clone(),
+            })
+            .collect(),
+    })?;
+
+    let predecessors = program.predecessors();
+    let mut assigned: Vec<&str> = Vec::new();
+    for block in &program.blocks {
+        for phi in &block.phis {
+            let mut labels: Vec<&str> = phi
+                .arguments
+                .iter()
+                .map(|(label, _)| label.as_str())
+                .collect();
+            let mut expected = predecessors[block.label.as_str()].clone();
+            labels.sort_unstable();
+            expected.sort_unstable();
+            if labels != expected {
+                return Err(global_error(VerifyErrorKind::PhiPredecessorMismatch(
+                    phi.lhs.clone(),
+                )));
+            }
+
+            let variables = phi
+                .arguments
+                .iter()
+                .filter_map(|(_, atom)| atom.as_variable());
+            for name in variables.chain([phi.lhs.as_str()]) {
+                if !program.locals.iter().any(|local| local == name) {
+                    return Err(global_error(VerifyErrorKind::UndeclaredVariable(
+                        name.to_string(),
+                    )));
+                }
+            }
+        }
+
+        let lhs =
+            block
+                .phis
+                .iter()
+                .map(|phi| phi.lhs.as_str())
+                .chain(block.body.iter().filter_map(|stmt| match stmt {
+                    Stmt::Assign { lhs, .. } => Some(lhs.as_str()),
+                    _ => None,
+                }));
+        for name in lhs {
+            if assigned.contains(&name) {
+                return Err(global_error(VerifyErrorKind::MultipleAssignments(
+                    name.to_string(),
+                )));
+            }
+            assigned.push(name);
+        }
+    }
+
+    Ok(())
+}
+
+/// The form of an x86 program at some point of the compiler.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum X86Stage {
+    // After `select_instructions`. Every variable must be declared.
+    Selected,
+    // After `assign_homes`. No variable is left.
+    HomesAssigned,
+    // After `patch_instructions`. Every instruction can be encoded.
+    Patched,
+}
+
+fn verify_operands(
+    program: &VarProgram,
+    instr: &VarInstr,
+    stage: X86Stage,
+) -> Result<(), VerifyErrorKind> {
+    let operands = instr.operands();
+
+    for operand in &operands {
+        match operand {
+            VarArg::Variable(name) if stage != X86Stage::Selected => {
+                return Err(VerifyErrorKind::UnexpectedVariable(name.clone()))
+            }
+            VarArg::Variable(name) if !program.local_variables.contains(name) => {
+                return Err(VerifyErrorKind::UndeclaredVariable(name.clone()))
+            }
+            _ => (),
+        }
+    }
+
+    if stage == X86Stage::Patched {
+        let memory_operands = operands
+            .iter()
+            .filter(|operand| matches!(operand, VarArg::Deref(..)))
+            .count();
+        if memory_operands > 1 {
+            return Err(VerifyErrorKind::TooManyMemoryOperands);
+        }
+
+        // Only `movq` to a register can take a 64-bit immediate.
+        if let VarInstr::Movq {
+            from: VarArg::Imm(_),
+            to: VarArg::Reg(_),
+        } = instr
+        {
+            return Ok(());
+        }
+        for operand in &operands {
+            if let VarArg::Imm(value) = operand {
+                if i32::try_from(*value).is_err() {
+                    return Err(VerifyErrorKind::ImmediateOutOfRange(*value));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the change of %rsp caused by `instr`, or `None` if it cannot be tracked.
+fn stack_pointer_change(instr: &VarInstr) -> Option<i64> {
+    let rsp = VarArg::Reg(Reg::RSP);
+    match instr {
+        VarInstr::Pushq { .. } => Some(-8),
+        VarInstr::Popq { operand } if *operand != rsp => Some(8),
+        VarInstr::Addq {
+            lhs,
+            rhs: VarArg::Imm(value),
+        } if *lhs == rsp => Some(*value),
+        VarInstr::Subq {
+            lhs,
+            rhs: VarArg::Imm(value),
+        } if *lhs == rsp => value.checked_neg(),
+        // Any other instruction that writes %rsp.
+        VarInstr::Addq { lhs, .. }
+        | VarInstr::Subq { lhs, .. }
+        | VarInstr::Xorq { lhs, .. }
+        | VarInstr::Negq { operand: lhs }
+        | VarInstr::Incq { operand: lhs }
+        | VarInstr::Decq { operand: lhs }
+        | VarInstr::Movq { to: lhs, .. }
+        | VarInstr::Popq { operand: lhs }
+            if *lhs == rsp =>
+        {
+            None
+        }
+        _ => Some(0),
+    }
+}
+
+struct StackTracker<'p> {
+    program: &'p VarProgram,
+    // The number of bytes pushed when each block is reached.
+    offsets: HashMap<usize, i64>,
+    worklist: Vec<usize>,
+}
+
+impl StackTracker<'_> {
+    fn reach(&mut self, index: usize, offset: i64) -> Result<(), VerifyError> {
+        match self.offsets.insert(index, offset) {
+            Some(existing) if existing != offset => Err(error_at(
+                VerifyErrorKind::InconsistentStack {
+                    offsets: (existing, offset),
+                },
+                &self.program.body[index].label,
+                0,
+            )),
+            Some(_) => Ok(()),
+            None => {
+                self.worklist.push(index);
+                Ok(())
+            }
+        }
+    }
+
+    fn run_block(&mut self, index: usize) -> Result<(), VerifyError> {
+        let block = &self.program.body[index];
+        let mut offset = self.offsets[&index];
+
+        for (instr_index, instr) in block.instructions.iter().enumerate() {
+            match instr {
+                VarInstr::Callq { .. } if offset % 16 != 0 => {
+                    return Err(error_at(
+                        VerifyErrorKind::MisalignedStack { offset },
+                        &block.label,
+                        instr_index,
+                    ))
+                }
+                VarInstr::Jmp { target } | VarInstr::Jo { target } => {
+                    // Unknown labels are reported by `verify_x86`.
+                    if let Some(target) = self.program.body.iter().position(|b| b.label == *target)
+                    {
+                        self.reach(target, offset)?;
+                    }
+                    if let VarInstr::Jmp { .. } = instr {
+                        return Ok(());
+                    }
+                }
+                VarInstr::Retq => return Ok(()),
+                // The offset counts the bytes pushed, so it grows when %rsp decreases.
+                _ => match stack_pointer_change(instr) {
+                    Some(change) => offset -= change,
+                    None => {
+                        return Err(error_at(
+                            VerifyErrorKind::UntrackedStackPointer,
+                            &block.label,
+                            instr_index,
+                        ))
+                    }
+                },
+            }
+        }
+
+        // Falls through to the next block.
+        if index + 1 < self.program.body.len() {
+            self.reach(index + 1, offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the stack is 16-byte aligned at every `callq`, assuming it is aligned when the
+/// program starts, like in the emulator.
+fn verify_stack_alignment(program: &VarProgram) -> Result<(), VerifyError> {
+    if program.body.is_empty() {
+        return Ok(());
+    }
+
+    let mut tracker = StackTracker {
+        program,
+        offsets: HashMap::new(),
+        worklist: Vec::new(),
+    };
+    let entry = program
+        .body
+        .iter()
+        .position(|block| block.label == "main")
+        .unwrap_or(0);
+    tracker.reach(entry, 0)?;
+    while let Some(index) = tracker.worklist.pop() {
+        tracker.run_block(index)?;
+    }
+
+    Ok(())
+}
+
+/// Checks the operands of every instruction according to `stage`, that every jump goes to an
+/// existing block, and that the stack is 16-byte aligned at every `callq`.
+pub fn verify_x86(program: &VarProgram, stage: X86Stage) -> Result<(), VerifyError> {
+    let labels =
+        check_labels(program.body.iter().map(|block| block.label.as_str())).map_err(|kind| {
+            VerifyError {
+                kind,
+                location: None,
+            }
+        })?;
+
+    for block in &program.body {
+        for (index, instr) in block.instructions.iter().enumerate() {
+            verify_operands(program, instr, stage)
+                .map_err(|kind| error_at(kind, &block.label, index))?;
+
+            if let VarInstr::Jmp { target } | VarInstr::Jo { target } = instr {
+                if !labels.contains(&target.as_str()) {
+                    return Err(error_at(
+                        VerifyErrorKind::UnknownLabel(target.clone()),
+                        &block.label,
+                        index,
+                    ));
+                }
+            }
+        }
+    }
+
+    verify_stack_alignment(program)
+}
+
+/// The verifier of the default pipeline, for `PassManager::set_verifier`. Checks `ir` according to
+/// `form`, which the pass manager tracks from the passes that have run.
+pub fn pipeline_verifier(_pass: &str, ir: &Ir, form: IrForm) -> Result<(), String> {
+    match ir {
+        Ir::Source(expr) if form.monadic => verify_monadic(expr),
+        Ir::Source(_) => Ok(()),
+        Ir::CVar(program) => verify_cvar(program),
+        Ir::Ssa(program) => verify_ssa(program),
+        Ir::X86(program) => verify_x86(program, form.x86_stage),
+    }
+    .map_err(|error| error.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use frontend::{parse_expr, ArithmeticSemantics};
+
+    use crate::{
+        ir::{
+            cvar::Atom,
+            ssa::{Block as SsaBlock, Phi},
+            x86::VarBlock,
+        },
+        FnPass, PassError, PassFilter, PassManager, PassManagerOptions,
+    };
+
+    use super::*;
+
+    fn error_message<T>(result: Result<T, VerifyError>) -> String {
+        result.err().unwrap().to_string()
+    }
+
+    #[test]
+    fn verify_monadic_test() {
+        let expr = |code| parse_expr(code).unwrap();
+
+        assert_eq!(
+            verify_monadic(&expr("(let ([x (- 1)]) (let ([y read]) (+ x y)))")),
+            Ok(())
+        );
+        assert_eq!(
+            error_message(verify_monadic(&expr("(let ([x 1]) (+ x (- 2)))"))),
+            "the operand `(- 2)` is not atomic"
+        );
+        assert_eq!(
+            error_message(verify_monadic(&expr("(- read)"))),
+            "the operand `read` is not atomic"
+        );
+        assert_eq!(
+            error_message(verify_monadic(&expr("(+ 1 9223372036854775808)"))),
+            "the integer 9223372036854775808 does not fit in 64 bits"
+        );
+    }
+
+    #[test]
+    fn verify_cvar_test() {
+        let mut program = CProgram::new();
+        program.create_local_variable("x".to_string());
+        program.create_assign("x".to_string(), CExpr::Read);
+        program.create_goto("next".to_string());
+        program.create_block("next".to_string());
+        program.create_terminator(Atom::Variable("x".to_string()).into());
+        assert_eq!(verify_cvar(&program), Ok(()));
+
+        let mut undeclared = program.clone();
+        undeclared.locals.clear();
+        assert_eq!(
+            error_message(verify_cvar(&undeclared)),
+            "start[0]: the variable `x` is not declared"
+        );
+
+        let mut unknown_label = program.clone();
+        unknown_label.blocks[1].label = "other".to_string();
+        assert_eq!(
+            error_message(verify_cvar(&unknown_label)),
+            "start[1]: the label `next` is not defined"
+        );
+
+        let mut missing_terminator = program.clone();
+        missing_terminator.blocks[1].body.clear();
+        assert_eq!(
+            error_message(verify_cvar(&missing_terminator)),
+            "next[0]: the block does not end with a return or a goto"
+        );
+
+        let mut two_terminators = program.clone();
+        two_terminators.blocks[1]
+            .body
+            .push(Stmt::Return(CExpr::Read));
+        assert_eq!(
+            error_message(verify_cvar(&two_terminators)),
+            "next[1]: the statement follows a terminator"
+        );
+
+        let mut duplicate_label = program;
+        duplicate_label.blocks[1].label = "start".to_string();
+        assert_eq!(
+            error_message(verify_cvar(&duplicate_label)),
+            "the label `start` is defined more than once"
+        );
+    }
+
+    #[test]
+    fn verify_ssa_test() {
+        let mut program = SsaProgram {
+            locals: vec!["x".to_string(), "y".to_string()],
+            blocks: vec![
+                SsaBlock::new("start".to_string()),
+                SsaBlock::new("loop".to_string()),
+            ],
+        };
+        program.blocks[0].body = vec![
+            Stmt::Assign {
+                lhs: "x".to_string(),
+                rhs: CExpr::Read,
+            },
+            Stmt::Goto("loop".to_string()),
+        ];
+        program.blocks[1].phis.push(Phi {
+            lhs: "y".to_string(),
+            arguments: vec![
+                ("start".to_string(), Atom::Variable("x".to_string())),
+                ("loop".to_string(), Atom::Variable("y".to_string())),
+            ],
+        });
+        program.blocks[1].body = vec![Stmt::Goto("loop".to_string())];
+        assert_eq!(verify_ssa(&program), Ok(()));
+
+        let mut missing_argument = program.clone();
+        missing_argument.blocks[1].phis[0].arguments.pop();
+        assert_eq!(
+            error_message(verify_ssa(&missing_argument)),
+            "the arguments of the phi node of `y` do not match the predecessors"
+        );
+
+        let mut undeclared = program.clone();
+        undeclared.blocks[1].phis[0].lhs = "z".to_string();
+        assert_eq!(
+            error_message(verify_ssa(&undeclared)),
+            "the variable `z` is not declared"
+        );
+
+        let mut assigned_twice = program;
+        assigned_twice.blocks[1].body.insert(
+            0,
+            Stmt::Assign {
+                lhs: "x".to_string(),
+                rhs: CExpr::Read,
+            },
+        );
+        assert_eq!(
+            error_message(verify_ssa(&assigned_twice)),
+            "the variable `x` is assigned more than once"
+        );
+    }
+
+    fn program(instructions: Vec<VarInstr>) -> VarProgram {
+        VarProgram {
+            local_variables: vec!["x".to_string()],
+            body: vec![
+                VarBlock {
+                    label: "main".to_string(),
+                    instructions,
+                },
+                VarBlock::new("conclusion".to_string()),
+            ],
+            frame_size: 0,
+        }
+    }
+
+    #[test]
+    fn verify_x86_operands() {
+        use VarArg::{Deref, Imm, Variable};
+
+        let verify = |instr, stage| verify_x86(&program(vec![instr]), stage);
+        let variable = VarInstr::Negq {
+            operand: Variable("x".to_string()),
+        };
+        assert_eq!(verify(variable.clone(), X86Stage::Selected), Ok(()));
+        assert_eq!(
+            error_message(verify(variable, X86Stage::HomesAssigned)),
+            "main[0]: the variable `x` has not been assigned a home"
+        );
+        assert_eq!(
+            error_message(verify(
+                VarInstr::Negq {
+                    operand: Variable("y".to_string()),
+                },
+                X86Stage::Selected
+            )),
+            "main[0]: the variable `y` is not declared"
+        );
+
+        let memory_to_memory = VarInstr::Addq {
+            lhs: Deref(Reg::RBP, -8),
+            rhs: Deref(Reg::RBP, -16),
+        };
+        assert_eq!(
+            verify(memory_to_memory.clone(), X86Stage::HomesAssigned),
+            Ok(())
+        );
+        assert_eq!(
+            error_message(verify(memory_to_memory, X86Stage::Patched)),
+            "main[0]: the instruction has more than one memory operand"
+        );
+
+        assert_eq!(
+            verify(
+                VarInstr::Movq {
+                    from: Imm(i64::MIN),
+                    to: Reg::RAX.into(),
+                },
+                X86Stage::Patched
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            error_message(verify(
+                VarInstr::Movq {
+                    from: Imm(0x8000_0000),
+                    to: Deref(Reg::RBP, -8),
+                },
+                X86Stage::Patched
+            )),
+            "main[0]: the immediate 2147483648 does not fit in 32 bits"
+        );
+
+        assert_eq!(
+            error_message(verify(
+                VarInstr::Jo {
+                    target: "overflow".to_string(),
+                },
+                X86Stage::Patched
+            )),
+            "main[0]: the label `overflow` is not defined"
+        );
+    }
+
+    #[test]
+    fn verify_x86_stack() {
+        use VarArg::Imm;
+
+        let call = || VarInstr::Callq {
+            callee: "read_int".to_string(),
+        };
+        let verify = |instructions| verify_x86(&program(instructions), X86Stage::Patched);
+
+        assert_eq!(
+            verify(vec![
+                VarInstr::Pushq {
+                    operand: Reg::RBP.into()
+                },
+                VarInstr::Subq {
+                    lhs: Reg::RSP.into(),
+                    rhs: Imm(8)
+                },
+                call(),
+                VarInstr::Addq {
+                    lhs: Reg::RSP.into(),
+                    rhs: Imm(8)
+                },
+                VarInstr::Popq {
+                    operand: Reg::RBP.into()
+                },
+                call(),
+            ]),
+            Ok(())
+        );
+        assert_eq!(
+            error_message(verify(vec![
+                VarInstr::Pushq {
+                    operand: Reg::RBP.into()
+                },
+                call()
+            ])),
+            "main[1]: the stack is not 16-byte aligned at the call (8 bytes are pushed)"
+        );
+        assert_eq!(
+            error_message(verify(vec![VarInstr::Movq {
+                from: Reg::RBP.into(),
+                to: Reg::RSP.into()
+            }])),
+            "main[0]: the change of %rsp cannot be tracked"
+        );
+
+        // `conclusion` is reached both by the jump and by falling through.
+        assert_eq!(
+            error_message(verify(vec![
+                VarInstr::Jo {
+                    target: "conclusion".to_string()
+                },
+                VarInstr::Pushq { operand: Imm(1) },
+            ])),
+            "conclusion[0]: the block is reached with 0 and 8 bytes pushed"
+        );
+    }
+
+    #[test]
+    fn verify_pipeline() {
+        for optimize in [PassFilter::None, PassFilter::All] {
+            let options = PassManagerOptions {
+                verify: true,
+                optimize,
+                ..PassManagerOptions::default()
+            };
+            let mut manager =
+                PassManager::with_default_pipeline(options, ArithmeticSemantics::Trapping);
+            let expr = parse_expr("(let ([x read]) (+ (- x 5000000000) (- read)))").unwrap();
+            assert!(manager.run(Ir::Source(expr)).is_ok());
+
+            // The verifier starts over when the manager runs again.
+            let expr = parse_expr("(+ 1 (+ 2 3))").unwrap();
+            for _ in 0..2 {
+                assert!(manager.run(Ir::Source(expr.clone())).is_ok());
+            }
+        }
+
+        // A custom pass is checked with the form of its input.
+        let mut manager = PassManager::with_default_pipeline(
+            PassManagerOptions {
+                verify: true,
+                ..PassManagerOptions::default()
+            },
+            ArithmeticSemantics::Checked,
+        );
+        manager
+            .insert_after(
+                "remove_complex_operands",
+                FnPass::new("nest", |_| {
+                    Ok(Ir::Source(parse_expr("(+ 1 (+ 2 3))").unwrap()))
+                }),
+            )
+            .unwrap();
+        assert_eq!(
+            manager.run(Ir::Source(parse_expr("1").unwrap())),
+            Err(PassError::VerificationFailed {
+                pass: "nest".to_string(),
+                message: "the operand `(+ 2 3)` is not atomic".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use crate::OverflowKind;
+
+pub fn checked_neg(operand: i64) -> Result<i64, OverflowKind> {
+    operand
+        .checked_neg()
+        .ok_or(OverflowKind::NegOverflow(operand))
+}
+
+pub fn checked_add(lhs: i64, rhs: i64) -> Result<i64, OverflowKind> {
+    lhs.checked_add(rhs)
+        .ok_or(OverflowKind::AddOverflow(lhs, rhs))
+}
+
+pub fn checked_sub(lhs: i64, rhs: i64) -> Result<i64, OverflowKind> {
+    lhs.checked_sub(rhs)
+        .ok_or(OverflowKind::SubOverflow(lhs, rhs))
+}
+
+/// How integer arithmetic behaves when the result does not fit in an `i64`. The same setting is
+/// used by the interpreters and the compiler, so that they agree on programs that overflow.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum ArithmeticSemantics {
+    // The result wraps around, like the x86 arithmetic instructions.
+    Wrapping,
+    // The interpreters report `ArithmeticOverflow`. The compiled code does not check for
+    // overflow, so the result of a program that overflows is unspecified.
+    #[default]
+    Checked,
+    // The interpreters report `ArithmeticOverflow`, and the compiled code exits through a runtime
+    // overflow handler.
+    Trapping,
+}
+
+impl ArithmeticSemantics {
+    pub fn neg(self, operand: i64) -> Result<i64, OverflowKind> {
+        match self {
+            ArithmeticSemantics::Wrapping => Ok(operand.wrapping_neg()),
+            ArithmeticSemantics::Checked | ArithmeticSemantics::Trapping => checked_neg(operand),
+        }
+    }
+
+    pub fn add(self, lhs: i64, rhs: i64) -> Result<i64, OverflowKind> {
+        match self {
+            ArithmeticSemantics::Wrapping => Ok(lhs.wrapping_add(rhs)),
+            ArithmeticSemantics::Checked | ArithmeticSemantics::Trapping => checked_add(lhs, rhs),
+        }
+    }
+
+    pub fn sub(self, lhs: i64, rhs: i64) -> Result<i64, OverflowKind> {
+        match self {
+            ArithmeticSemantics::Wrapping => Ok(lhs.wrapping_sub(rhs)),
+            ArithmeticSemantics::Checked | ArithmeticSemantics::Trapping => checked_sub(lhs, rhs),
+        }
+    }
+}
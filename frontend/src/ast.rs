@@ -34,6 +34,47 @@ pub enum Expr {
     },
 }
 
+impl Expr {
+    /// Checks whether `name` occurs as an identifier anywhere in the expression, regardless of
+    /// which declaration it refers to.
+    pub fn mentions(&self, name: &str) -> bool {
+        use Expr::*;
+
+        match self {
+            Integer(_) | Read => false,
+            Identifier(identifier) => identifier == name,
+            UnaryOperation { operand, .. } => operand.mentions(name),
+            BinaryOperation {
+                left_operand,
+                right_operand,
+                ..
+            } => left_operand.mentions(name) || right_operand.mentions(name),
+            Let {
+                init_expr, body, ..
+            } => init_expr.mentions(name) || body.mentions(name),
+        }
+    }
+
+    /// Returns the number of nested expressions on the longest path from this expression to a
+    /// leaf, e.g. 1 for `42` and 2 for `(- 42)`.
+    pub fn depth(&self) -> usize {
+        use Expr::*;
+
+        match self {
+            Integer(_) | Read | Identifier(_) => 1,
+            UnaryOperation { operand, .. } => operand.depth() + 1,
+            BinaryOperation {
+                left_operand,
+                right_operand,
+                ..
+            } => left_operand.depth().max(right_operand.depth()) + 1,
+            Let {
+                init_expr, body, ..
+            } => init_expr.depth().max(body.depth()) + 1,
+        }
+    }
+}
+
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use Expr::*;
@@ -0,0 +1,177 @@
+//! A mutation fuzzer which checks that the lexer and the parser never panic, whatever bytes they
+//! are given. It starts from the programs in `fuzz/corpus` and mutates them at random.
+
+use std::{
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+};
+
+use crate::{parse_expr_with_mode, rng::Rng, test_support::env_or, Lexer, ParseMode, TokenKind};
+
+// Fragments which are likely to lead the lexer and the parser into unusual states.
+const DICTIONARY: &[&str] = &[
+    "(",
+    ")",
+    "[",
+    "]",
+    "let",
+    "let*",
+    "read",
+    "program",
+    "+",
+    "-",
+    "+1",
+    "-0",
+    "#|",
+    "|#",
+    ";",
+    "\n",
+    " ",
+    "#t",
+    ".",
+    "9223372036854775807",
+    "9223372036854775808",
+    "é",
+    "変",
+    "\u{feff}",
+    "\u{0}",
+    "((((((((",
+    "))))))))",
+    "[x 1]",
+    "([x read])",
+];
+
+fn load_corpus() -> Vec<Vec<u8>> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fuzz")
+        .join("corpus");
+    let mut paths: Vec<_> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    // Keep the order of the inputs stable, so that a seed always reproduces the same run.
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| fs::read(path).unwrap())
+        .collect()
+}
+
+fn random_range(rng: &mut Rng, len: usize) -> (usize, usize) {
+    let start = rng.below(len as u64 + 1) as usize;
+    let end = start + rng.below((len - start) as u64 + 1) as usize;
+    (start, end)
+}
+
+fn mutate(rng: &mut Rng, input: &mut Vec<u8>, corpus: &[Vec<u8>]) {
+    let position = rng.below(input.len() as u64 + 1) as usize;
+
+    match rng.below(7) {
+        // Flip a bit, which may also produce invalid UTF-8.
+        0 if !input.is_empty() => {
+            let index = position.min(input.len() - 1);
+            input[index] ^= 1 << rng.below(8);
+        }
+        // Insert a random byte.
+        1 => input.insert(position, rng.next_u64() as u8),
+        // Insert a fragment from the dictionary.
+        2 => {
+            let fragment = DICTIONARY[rng.below(DICTIONARY.len() as u64) as usize];
+            input.splice(position..position, fragment.bytes());
+        }
+        // Remove a range.
+        3 => {
+            let (start, end) = random_range(rng, input.len());
+            input.drain(start..end);
+        }
+        // Duplicate a range.
+        4 => {
+            let (start, end) = random_range(rng, input.len());
+            let range = input[start..end].to_vec();
+            input.splice(position..position, range);
+        }
+        // Insert a range of another input.
+        5 => {
+            let other = &corpus[rng.below(corpus.len() as u64) as usize];
+            let (start, end) = random_range(rng, other.len());
+            input.splice(position..position, other[start..end].iter().copied());
+        }
+        // Truncate the input.
+        _ => input.truncate(position),
+    }
+}
+
+fn check_lexer(code: &str, rng: &mut Rng) {
+    // With trivia, the spelling of the tokens covers the whole input.
+    let mut end = 0;
+    for token in Lexer::new(code).with_trivia() {
+        assert_eq!(token.start_location(), end);
+        assert_eq!(&code[token.span()], token.spelling());
+        end = token.end_location();
+    }
+    assert_eq!(end, code.len());
+
+    // Lexing may start at any offset, but only a character boundary is accepted.
+    let offset = rng.below(code.len() as u64 + 1) as usize;
+    match Lexer::new_at(code, offset) {
+        Some(mut lexer) => loop {
+            let token = lexer.next_token();
+            assert!(token.start_location() >= offset && token.end_location() <= code.len());
+            if token.token_kind() == TokenKind::Eof {
+                break;
+            }
+        },
+        None => assert!(!code.is_char_boundary(offset)),
+    }
+}
+
+fn check_parser(code: &str) {
+    for mode in [ParseMode::Lenient, ParseMode::Strict] {
+        if let Err(error) = parse_expr_with_mode(code, mode) {
+            assert!(
+                error.location <= code.len() && code.is_char_boundary(error.location),
+                "{:?} is out of bounds",
+                error
+            );
+        }
+    }
+}
+
+fn fuzz(seed: u64, iterations: u64) {
+    let corpus = load_corpus();
+    let mut rng = Rng::new(seed);
+
+    for iteration in 0..iterations {
+        let mut input = corpus[rng.below(corpus.len() as u64) as usize].clone();
+        for _ in 0..=rng.below(8) {
+            mutate(&mut rng, &mut input, &corpus);
+        }
+        let code = String::from_utf8_lossy(&input);
+
+        let mut check_rng = rng.clone();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            check_lexer(&code, &mut check_rng);
+            check_parser(&code);
+        }));
+        if result.is_err() {
+            panic!(
+                "iteration {} with seed {} failed on the input {:?}",
+                iteration, seed, code
+            );
+        }
+    }
+}
+
+#[test]
+fn fuzz_parser() {
+    fuzz(env_or("FUZZ_SEED", 0), 2000);
+}
+
+// Run with `cargo test -- --ignored`. The number of inputs and the seed can be set with the
+// `FUZZ_ITERATIONS` and `FUZZ_SEED` environment variables.
+#[test]
+#[ignore]
+fn fuzz_parser_long_running() {
+    fuzz(env_or("FUZZ_SEED", 1), env_or("FUZZ_ITERATIONS", 1_000_000));
+}
@@ -0,0 +1,396 @@
+use crate::{rng::Rng, BinaryOpKind, Expr, UnaryOpKind};
+
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    // The maximum depth of the generated expressions. Expressions at this depth are always
+    // integers, variables or `read`.
+    pub max_depth: u32,
+    // The relative weights of the kinds of expressions.
+    pub integer_weight: u32,
+    pub variable_weight: u32,
+    pub read_weight: u32,
+    pub negation_weight: u32,
+    pub addition_weight: u32,
+    pub subtraction_weight: u32,
+    pub let_weight: u32,
+    // The maximum number of `read`s in a program.
+    pub max_reads: usize,
+    // The probability, in percent, that a `let` declares a variable with the same name as a
+    // variable in scope.
+    pub shadowing_percent: u32,
+    // The probability, in percent, that an integer is close to `i64::MAX`, which is likely to make
+    // the program overflow.
+    pub large_integer_percent: u32,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 6,
+            integer_weight: 4,
+            variable_weight: 4,
+            read_weight: 1,
+            negation_weight: 2,
+            addition_weight: 3,
+            subtraction_weight: 3,
+            let_weight: 3,
+            max_reads: 4,
+            shadowing_percent: 30,
+            large_integer_percent: 5,
+        }
+    }
+}
+
+/// Generates random programs in which every variable is declared before it is used.
+pub struct ExprGenerator {
+    rng: Rng,
+    config: GeneratorConfig,
+    // The variables in scope, from the outermost to the innermost one.
+    scope: Vec<String>,
+    reads: usize,
+    name_index: u32,
+}
+
+impl ExprGenerator {
+    pub fn new(seed: u64, config: GeneratorConfig) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            config,
+            scope: Vec::new(),
+            reads: 0,
+            name_index: 0,
+        }
+    }
+
+    pub fn generate(&mut self) -> Expr {
+        self.scope.clear();
+        self.reads = 0;
+        self.name_index = 0;
+        self.generate_expr(0)
+    }
+
+    /// Generates the inputs for a program, i.e. at least as many integers as the maximum number
+    /// of `read`s.
+    pub fn generate_inputs(&mut self) -> Vec<i64> {
+        (0..self.config.max_reads)
+            .map(|_| self.generate_integer() as i64 * if self.rng.chance(50) { -1 } else { 1 })
+            .collect()
+    }
+
+    fn generate_integer(&mut self) -> u64 {
+        if self.rng.chance(self.config.large_integer_percent) {
+            i64::MAX as u64 - self.rng.below(16)
+        } else {
+            self.rng.below(64)
+        }
+    }
+
+    fn generate_variable_name(&mut self) -> String {
+        if !self.scope.is_empty() && self.rng.chance(self.config.shadowing_percent) {
+            let index = self.rng.below(self.scope.len() as u64) as usize;
+            self.scope[index].clone()
+        } else {
+            let name = format!("v{}", self.name_index);
+            self.name_index += 1;
+            name
+        }
+    }
+
+    fn generate_expr(&mut self, depth: u32) -> Expr {
+        let is_leaf = depth >= self.config.max_depth;
+        let weights = [
+            self.config.integer_weight,
+            if self.scope.is_empty() {
+                0
+            } else {
+                self.config.variable_weight
+            },
+            if self.reads < self.config.max_reads {
+                self.config.read_weight
+            } else {
+                0
+            },
+            if is_leaf {
+                0
+            } else {
+                self.config.negation_weight
+            },
+            if is_leaf {
+                0
+            } else {
+                self.config.addition_weight
+            },
+            if is_leaf {
+                0
+            } else {
+                self.config.subtraction_weight
+            },
+            if is_leaf { 0 } else { self.config.let_weight },
+        ];
+
+        match self.rng.weighted_index(&weights) {
+            Some(1) => {
+                let index = self.rng.below(self.scope.len() as u64) as usize;
+                Expr::Identifier(self.scope[index].clone())
+            }
+
+            Some(2) => {
+                self.reads += 1;
+                Expr::Read
+            }
+
+            Some(3) => Expr::UnaryOperation {
+                kind: UnaryOpKind::Minus,
+                operand: Box::new(self.generate_expr(depth + 1)),
+            },
+
+            Some(kind @ (4 | 5)) => Expr::BinaryOperation {
+                kind: if kind == 4 {
+                    BinaryOpKind::Add
+                } else {
+                    BinaryOpKind::Sub
+                },
+                left_operand: Box::new(self.generate_expr(depth + 1)),
+                right_operand: Box::new(self.generate_expr(depth + 1)),
+            },
+
+            Some(6) => {
+                // The initializer is generated before the variable is in scope.
+                let init_expr = Box::new(self.generate_expr(depth + 1));
+                let variable_name = self.generate_variable_name();
+                self.scope.push(variable_name.clone());
+                let body = Box::new(self.generate_expr(depth + 1));
+                self.scope.pop();
+                Expr::Let {
+                    variable_name,
+                    init_expr,
+                    body,
+                }
+            }
+
+            // Also used if all weights are zero.
+            _ => Expr::Integer(self.generate_integer()),
+        }
+    }
+}
+
+fn free_variables_impl<'e>(expr: &'e Expr, scope: &mut Vec<&'e str>, result: &mut Vec<&'e str>) {
+    match expr {
+        Expr::Integer(_) | Expr::Read => (),
+        Expr::Identifier(name) => {
+            if !scope.contains(&name.as_str()) && !result.contains(&name.as_str()) {
+                result.push(name);
+            }
+        }
+        Expr::UnaryOperation { operand, .. } => free_variables_impl(operand, scope, result),
+        Expr::BinaryOperation {
+            left_operand,
+            right_operand,
+            ..
+        } => {
+            free_variables_impl(left_operand, scope, result);
+            free_variables_impl(right_operand, scope, result);
+        }
+        Expr::Let {
+            variable_name,
+            init_expr,
+            body,
+        } => {
+            free_variables_impl(init_expr, scope, result);
+            scope.push(variable_name);
+            free_variables_impl(body, scope, result);
+            scope.pop();
+        }
+    }
+}
+
+fn free_variables(expr: &Expr) -> Vec<&str> {
+    let mut result = Vec::new();
+    free_variables_impl(expr, &mut Vec::new(), &mut result);
+    result
+}
+
+/// Produces the expressions that are one step smaller than `expr`.
+fn shrink_candidates(expr: &Expr) -> Vec<Expr> {
+    let mut result = Vec::new();
+
+    match expr {
+        Expr::Integer(0) => (),
+        Expr::Integer(val) => {
+            result.push(Expr::Integer(0));
+            result.push(Expr::Integer(val / 2));
+        }
+
+        Expr::Identifier(_) | Expr::Read => result.push(Expr::Integer(0)),
+
+        Expr::UnaryOperation { kind, operand } => {
+            result.push(Expr::Integer(0));
+            result.push(*operand.clone());
+            result.extend(shrink_candidates(operand).into_iter().map(|operand| {
+                Expr::UnaryOperation {
+                    kind: kind.clone(),
+                    operand: Box::new(operand),
+                }
+            }));
+        }
+
+        Expr::BinaryOperation {
+            kind,
+            left_operand,
+            right_operand,
+        } => {
+            result.push(Expr::Integer(0));
+            result.push(*left_operand.clone());
+            result.push(*right_operand.clone());
+            result.extend(
+                shrink_candidates(left_operand)
+                    .into_iter()
+                    .map(|left_operand| Expr::BinaryOperation {
+                        kind: kind.clone(),
+                        left_operand: Box::new(left_operand),
+                        right_operand: right_operand.clone(),
+                    }),
+            );
+            result.extend(
+                shrink_candidates(right_operand)
+                    .into_iter()
+                    .map(|right_operand| Expr::BinaryOperation {
+                        kind: kind.clone(),
+                        left_operand: left_operand.clone(),
+                        right_operand: Box::new(right_operand),
+                    }),
+            );
+        }
+
+        Expr::Let {
+            variable_name,
+            init_expr,
+            body,
+        } => {
+            result.push(Expr::Integer(0));
+            result.push(*init_expr.clone());
+            result.push(*body.clone());
+            result.extend(
+                shrink_candidates(init_expr)
+                    .into_iter()
+                    .map(|init_expr| Expr::Let {
+                        variable_name: variable_name.clone(),
+                        init_expr: Box::new(init_expr),
+                        body: body.clone(),
+                    }),
+            );
+            result.extend(shrink_candidates(body).into_iter().map(|body| Expr::Let {
+                variable_name: variable_name.clone(),
+                init_expr: init_expr.clone(),
+                body: Box::new(body),
+            }));
+        }
+    }
+
+    result
+}
+
+/// Minimizes a program for which `fails` returns true, by repeatedly replacing it with a smaller
+/// program that still fails. The smaller programs never use variables that are not declared in
+/// the original program.
+pub fn shrink_expr(expr: &Expr, mut fails: impl FnMut(&Expr) -> bool) -> Expr {
+    let mut result = expr.clone();
+
+    'shrink: loop {
+        let original_free_variables: Vec<String> = free_variables(&result)
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+
+        for candidate in shrink_candidates(&result) {
+            let is_well_scoped = free_variables(&candidate)
+                .iter()
+                .all(|name| original_free_variables.iter().any(|free| free == name));
+            if is_well_scoped && fails(&candidate) {
+                result = candidate;
+                continue 'shrink;
+            }
+        }
+
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use crate::{interp_expr, parse_expr, InterpreterError};
+
+    use super::*;
+
+    #[test]
+    fn generate_well_scoped() {
+        let mut generator = ExprGenerator::new(42, GeneratorConfig::default());
+        for _ in 0..500 {
+            let expr = generator.generate();
+            assert!(free_variables(&expr).is_empty(), "{}", expr);
+
+            // Every generated program can be printed and parsed again.
+            assert_eq!(parse_expr(&expr.to_string()), Ok(expr.clone()));
+
+            // The inputs are enough for every `read`.
+            let inputs = generator.generate_inputs();
+            assert!(!matches!(
+                interp_expr(&expr, &mut VecDeque::from(inputs)),
+                Err(InterpreterError::EndOfInput { .. } | InterpreterError::UnknownIdentifier(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn generate_deterministic() {
+        let config = GeneratorConfig {
+            max_depth: 3,
+            ..GeneratorConfig::default()
+        };
+        let programs = |seed| {
+            let mut generator = ExprGenerator::new(seed, config.clone());
+            (0..20).map(|_| generator.generate()).collect::<Vec<_>>()
+        };
+        assert_eq!(programs(7), programs(7));
+        assert_ne!(programs(7), programs(8));
+    }
+
+    #[test]
+    fn shrink() {
+        // The program "fails" if it contains a subtraction whose right operand is `read`.
+        fn contains_sub_read(expr: &Expr) -> bool {
+            match expr {
+                Expr::Integer(_) | Expr::Read | Expr::Identifier(_) => false,
+                Expr::UnaryOperation { operand, .. } => contains_sub_read(operand),
+                Expr::BinaryOperation {
+                    kind,
+                    left_operand,
+                    right_operand,
+                } => {
+                    (*kind == BinaryOpKind::Sub && **right_operand == Expr::Read)
+                        || contains_sub_read(left_operand)
+                        || contains_sub_read(right_operand)
+                }
+                Expr::Let {
+                    init_expr, body, ..
+                } => contains_sub_read(init_expr) || contains_sub_read(body),
+            }
+        }
+
+        let expr =
+            parse_expr("(let ([x (+ 10 20)]) (+ (- x) (let ([y x]) (- (+ y 1) read))))").unwrap();
+        assert_eq!(
+            shrink_expr(&expr, contains_sub_read).to_string(),
+            "(- 0 read)"
+        );
+
+        // Variables are never left undeclared.
+        let expr = parse_expr("(let ([x 5]) (- x read))").unwrap();
+        assert_eq!(
+            shrink_expr(&expr, |expr| expr.mentions("x")).to_string(),
+            "(let ([x 0]) x)"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use std::{collections::VecDeque, io::BufRead};
+
+use crate::InterpreterError;
+
+/// The source of the integers produced by `read`.
+pub trait InputSource {
+    /// Produces the next integer, or `None` if the input is exhausted.
+    fn read_int(&mut self) -> Result<Option<i64>, InterpreterError>;
+}
+
+impl InputSource for VecDeque<i64> {
+    fn read_int(&mut self) -> Result<Option<i64>, InterpreterError> {
+        Ok(self.pop_front())
+    }
+}
+
+/// Reads one integer from each line of a reader, e.g. `BufReadInput::new(io::stdin().lock())`.
+pub struct BufReadInput<R> {
+    reader: R,
+}
+
+impl<R: BufRead> BufReadInput<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+}
+
+impl<R: BufRead> InputSource for BufReadInput<R> {
+    fn read_int(&mut self) -> Result<Option<i64>, InterpreterError> {
+        let mut input = String::new();
+        match self.reader.read_line(&mut input) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(input.trim().parse()?)),
+            Err(e) => Err(InterpreterError::InputError(e.kind())),
+        }
+    }
+}
+
+/// Produces the integers returned by a closure.
+pub struct FnInput<F>(pub F);
+
+impl<F: FnMut() -> Option<i64>> InputSource for FnInput<F> {
+    fn read_int(&mut self) -> Result<Option<i64>, InterpreterError> {
+        Ok((self.0)())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn input_sources() {
+        let mut input = VecDeque::from([1, -2]);
+        assert_eq!(input.read_int(), Ok(Some(1)));
+        assert_eq!(input.read_int(), Ok(Some(-2)));
+        assert_eq!(input.read_int(), Ok(None));
+
+        let mut input = BufReadInput::new(Cursor::new("42\n  -7 \nabc\n"));
+        assert_eq!(input.read_int(), Ok(Some(42)));
+        assert_eq!(input.read_int(), Ok(Some(-7)));
+        assert!(matches!(
+            input.read_int(),
+            Err(InterpreterError::ParseIntegerError(_))
+        ));
+        assert_eq!(input.read_int(), Ok(None));
+
+        let mut next = 0;
+        let mut input = FnInput(|| {
+            next += 1;
+            (next <= 2).then_some(next * 10)
+        });
+        assert_eq!(input.read_int(), Ok(Some(10)));
+        assert_eq!(input.read_int(), Ok(Some(20)));
+        assert_eq!(input.read_int(), Ok(None));
+    }
+}
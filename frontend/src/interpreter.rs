@@ -4,7 +4,7 @@ use std::{
     num::{ParseIntError, TryFromIntError},
 };
 
-use crate::{BinaryOpKind, Expr, UnaryOpKind};
+use crate::{ArithmeticSemantics, BinaryOpKind, Expr, InputSource, UnaryOpKind};
 
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub enum OverflowKind {
@@ -19,6 +19,21 @@ pub enum InterpreterError {
     ParseIntegerError(ParseIntError),
     ArithmeticOverflow(OverflowKind),
     UnknownIdentifier(String),
+    // `read` is evaluated after the input is exhausted.
+    EndOfInput { consumed: usize },
+    InputError(io::ErrorKind),
+    // More expressions are evaluated than allowed by `InterpOptions::fuel`.
+    StepLimitExceeded,
+    // More scopes are nested than allowed by `InterpOptions::max_scope_depth`.
+    ScopeDepthExceeded,
+}
+
+/// The result of evaluating a program.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Evaluation {
+    pub value: i64,
+    // The number of integers read from the input.
+    pub inputs_consumed: usize,
 }
 
 impl From<TryFromIntError> for InterpreterError {
@@ -27,25 +42,106 @@ impl From<TryFromIntError> for InterpreterError {
     }
 }
 
+impl From<OverflowKind> for InterpreterError {
+    fn from(value: OverflowKind) -> Self {
+        InterpreterError::ArithmeticOverflow(value)
+    }
+}
+
 impl From<ParseIntError> for InterpreterError {
     fn from(value: ParseIntError) -> Self {
         InterpreterError::ParseIntegerError(value)
     }
 }
 
-struct Interpreter {
+/// Called after an expression is evaluated, with the environment the expression is evaluated in
+/// and its result.
+pub type TraceFn<'t> = dyn FnMut(&Expr, &Environment<'_>, &Result<i64, InterpreterError>) + 't;
+
+#[derive(Default)]
+pub struct InterpOptions<'t> {
+    // The maximum number of expressions to evaluate, or `None` for no limit.
+    pub fuel: Option<u64>,
+    // The maximum number of nested `let` scopes, or `None` for no limit.
+    pub max_scope_depth: Option<usize>,
+    pub trace: Option<&'t mut TraceFn<'t>>,
+    pub semantics: ArithmeticSemantics,
+}
+
+/// The variables visible to an expression while it is evaluated.
+pub struct Environment<'a> {
+    // The scopes from the outermost to the innermost one.
+    scopes: &'a [HashMap<String, i64>],
+}
+
+impl Environment<'_> {
+    pub fn lookup(&self, name: &str) -> Option<i64> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|table| table.get(name))
+            .copied()
+    }
+
+    pub fn scope_depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    /// Returns the visible variables and their values, from the innermost scope to the outermost
+    /// one. Shadowed variables are skipped.
+    pub fn bindings(&self) -> Vec<(&str, i64)> {
+        let mut result: Vec<(&str, i64)> = Vec::new();
+        for (name, &value) in self.scopes.iter().rev().flatten() {
+            if result.iter().all(|(visible, _)| visible != name) {
+                result.push((name, value));
+            }
+        }
+        result
+    }
+}
+
+struct Interpreter<'i> {
     symbol_table: Vec<HashMap<String, i64>>,
+    input: &'i mut dyn InputSource,
+    inputs_consumed: usize,
+    fuel: Option<u64>,
+    max_scope_depth: Option<usize>,
+    trace: Option<&'i mut TraceFn<'i>>,
+    semantics: ArithmeticSemantics,
 }
 
-impl Interpreter {
-    fn new() -> Self {
+impl<'i> Interpreter<'i> {
+    fn new(input: &'i mut dyn InputSource, options: InterpOptions<'i>) -> Self {
         Self {
             symbol_table: Vec::new(),
+            input,
+            inputs_consumed: 0,
+            fuel: options.fuel,
+            max_scope_depth: options.max_scope_depth,
+            trace: options.trace,
+            semantics: options.semantics,
         }
     }
 
-    fn enter_scope(&mut self) {
+    fn read_input(&mut self) -> Result<i64, InterpreterError> {
+        match self.input.read_int()? {
+            Some(value) => {
+                self.inputs_consumed += 1;
+                Ok(value)
+            }
+            None => Err(InterpreterError::EndOfInput {
+                consumed: self.inputs_consumed,
+            }),
+        }
+    }
+
+    fn enter_scope(&mut self) -> Result<(), InterpreterError> {
+        if self.max_scope_depth == Some(self.symbol_table.len()) {
+            return Err(InterpreterError::ScopeDepthExceeded);
+        }
+
         self.symbol_table.push(HashMap::new());
+        Ok(())
     }
 
     fn exit_scope(&mut self) {
@@ -60,29 +156,42 @@ impl Interpreter {
             .is_none()
     }
 
-    fn lookup(&self, name: &str) -> Option<i64> {
-        self.symbol_table
-            .iter()
-            .rev()
-            .find_map(|table| table.get(name))
-            .and_then(|&value| Some(value))
+    fn environment(&self) -> Environment<'_> {
+        Environment {
+            scopes: &self.symbol_table,
+        }
     }
 
     fn evaluate_expr(&mut self, expr: &Expr) -> Result<i64, InterpreterError> {
+        if let Some(fuel) = &mut self.fuel {
+            if *fuel == 0 {
+                return Err(InterpreterError::StepLimitExceeded);
+            }
+            *fuel -= 1;
+        }
+
+        let result = self.evaluate_expr_impl(expr);
+        if let Some(trace) = &mut self.trace {
+            trace(
+                expr,
+                &Environment {
+                    scopes: &self.symbol_table,
+                },
+                &result,
+            );
+        }
+        result
+    }
+
+    fn evaluate_expr_impl(&mut self, expr: &Expr) -> Result<i64, InterpreterError> {
         use Expr::*;
 
         match *expr {
             Integer(val) => Ok(val.try_into()?),
 
-            Read => {
-                let mut input = String::new();
-                io::stdin()
-                    .read_line(&mut input)
-                    .expect("Expected to read an integer.");
-                Ok(input.trim().parse()?)
-            }
+            Read => self.read_input(),
 
-            Identifier(ref name) => match self.lookup(name) {
+            Identifier(ref name) => match self.environment().lookup(name) {
                 Some(value) => Ok(value),
                 None => Err(InterpreterError::UnknownIdentifier(name.clone())),
             },
@@ -92,14 +201,7 @@ impl Interpreter {
                 ref operand,
             } => {
                 let operand = self.evaluate_expr(operand)?;
-                let (result, overflow) = operand.overflowing_neg();
-                if overflow {
-                    Err(InterpreterError::ArithmeticOverflow(
-                        OverflowKind::NegOverflow(operand),
-                    ))
-                } else {
-                    Ok(result)
-                }
+                Ok(self.semantics.neg(operand)?)
             }
 
             BinaryOperation {
@@ -109,14 +211,7 @@ impl Interpreter {
             } => {
                 let lhs = self.evaluate_expr(left_operand)?;
                 let rhs = self.evaluate_expr(right_operand)?;
-                let (result, overflow) = lhs.overflowing_add(rhs);
-                if overflow {
-                    Err(InterpreterError::ArithmeticOverflow(
-                        OverflowKind::AddOverflow(lhs, rhs),
-                    ))
-                } else {
-                    Ok(result)
-                }
+                Ok(self.semantics.add(lhs, rhs)?)
             }
 
             BinaryOperation {
@@ -126,14 +221,7 @@ impl Interpreter {
             } => {
                 let lhs = self.evaluate_expr(left_operand)?;
                 let rhs = self.evaluate_expr(right_operand)?;
-                let (result, overflow) = lhs.overflowing_sub(rhs);
-                if overflow {
-                    Err(InterpreterError::ArithmeticOverflow(
-                        OverflowKind::SubOverflow(lhs, rhs),
-                    ))
-                } else {
-                    Ok(result)
-                }
+                Ok(self.semantics.sub(lhs, rhs)?)
             }
 
             Let {
@@ -143,33 +231,61 @@ impl Interpreter {
             } => {
                 // We evaluate the initializer before entering the scope of the let expression, so
                 // that the initializer can use the variable in the parent scope.
-                let init = self.evaluate_expr(&init_expr)?;
-                self.enter_scope();
+                let init = self.evaluate_expr(init_expr)?;
+                self.enter_scope()?;
                 // We don't handle the result of `declare_name`, since in the current language, we
                 // cannot define variables with the same name in the same scope.
-                self.declare_name(&variable_name, init);
-                let result = self.evaluate_expr(&body)?;
+                self.declare_name(variable_name, init);
+                // The scope is also exited on errors, so that the enclosing expressions are traced
+                // with their own environment.
+                let result = self.evaluate_expr(body);
                 self.exit_scope();
-                Ok(result)
+                result
             }
         }
     }
 }
 
-pub fn interp_expr(expr: &Expr) -> Result<i64, InterpreterError> {
-    Interpreter::new().evaluate_expr(expr)
+pub fn interp_expr(
+    expr: &Expr,
+    input: &mut dyn InputSource,
+) -> Result<Evaluation, InterpreterError> {
+    interp_expr_with_options(expr, input, InterpOptions::default())
+}
+
+pub fn interp_expr_with_options<'i>(
+    expr: &Expr,
+    input: &'i mut dyn InputSource,
+    options: InterpOptions<'i>,
+) -> Result<Evaluation, InterpreterError> {
+    let mut interpreter = Interpreter::new(input, options);
+    let value = interpreter.evaluate_expr(expr)?;
+    Ok(Evaluation {
+        value,
+        inputs_consumed: interpreter.inputs_consumed,
+    })
 }
 
 #[cfg(test)]
 mod test {
+    use std::collections::VecDeque;
+
     use super::*;
 
+    fn interp(expr: &Expr) -> Result<i64, InterpreterError> {
+        interp_expr(expr, &mut VecDeque::new()).map(|evaluation| evaluation.value)
+    }
+
     #[test]
     fn interp_test() {
-        assert_eq!(interp_expr(&Expr::Integer(255)), Ok(255));
+        assert_eq!(interp(&Expr::Integer(255)), Ok(255));
+        assert_eq!(
+            interp(&crate::parse_expr("-9223372036854775808").unwrap()),
+            Ok(i64::MIN)
+        );
 
         assert_eq!(
-            interp_expr(&Expr::UnaryOperation {
+            interp(&Expr::UnaryOperation {
                 kind: UnaryOpKind::Minus,
                 operand: Box::new(Expr::Integer(3))
             }),
@@ -177,7 +293,7 @@ mod test {
         );
 
         assert_eq!(
-            interp_expr(&Expr::UnaryOperation {
+            interp(&Expr::UnaryOperation {
                 kind: UnaryOpKind::Minus,
                 operand: Box::new(Expr::UnaryOperation {
                     kind: UnaryOpKind::Minus,
@@ -188,7 +304,7 @@ mod test {
         );
 
         assert_eq!(
-            interp_expr(&Expr::BinaryOperation {
+            interp(&Expr::BinaryOperation {
                 kind: BinaryOpKind::Add,
                 left_operand: Box::new(Expr::Integer(1)),
                 right_operand: Box::new(Expr::Integer(2))
@@ -197,7 +313,7 @@ mod test {
         );
 
         assert_eq!(
-            interp_expr(&Expr::BinaryOperation {
+            interp(&Expr::BinaryOperation {
                 kind: BinaryOpKind::Add,
                 left_operand: Box::new(Expr::Integer(10)),
                 right_operand: Box::new(Expr::UnaryOperation {
@@ -213,7 +329,7 @@ mod test {
         );
 
         assert_eq!(
-            interp_expr(&Expr::BinaryOperation {
+            interp(&Expr::BinaryOperation {
                 kind: BinaryOpKind::Sub,
                 left_operand: Box::new(Expr::UnaryOperation {
                     kind: UnaryOpKind::Minus,
@@ -235,7 +351,7 @@ mod test {
     #[test]
     fn interp_variable() {
         assert_eq!(
-            interp_expr(&Expr::Let {
+            interp(&Expr::Let {
                 variable_name: "x".to_string(),
                 init_expr: Box::new(Expr::Integer(1)),
                 body: Box::new(Expr::Identifier("x".to_string()))
@@ -244,7 +360,7 @@ mod test {
         );
 
         assert_eq!(
-            interp_expr(&Expr::Let {
+            interp(&Expr::Let {
                 variable_name: "x".to_string(),
                 init_expr: Box::new(Expr::Integer(32)),
                 body: Box::new(Expr::BinaryOperation {
@@ -261,7 +377,7 @@ mod test {
         );
 
         assert_eq!(
-            interp_expr(&Expr::Let {
+            interp(&Expr::Let {
                 variable_name: "x".to_string(),
                 init_expr: Box::new(Expr::Integer(32)),
                 body: Box::new(Expr::BinaryOperation {
@@ -278,15 +394,71 @@ mod test {
         );
     }
 
+    #[test]
+    fn interp_multiple_bindings() {
+        use crate::parse_expr;
+
+        assert_eq!(
+            interp(&parse_expr("(let ([x 1]) (let ([x 2] [y x]) (- x y)))").unwrap()),
+            Ok(1)
+        );
+
+        assert_eq!(
+            interp(&parse_expr("(let ([x 1]) (let* ([x 2] [y x]) (- x y)))").unwrap()),
+            Ok(0)
+        );
+
+        assert_eq!(
+            interp(&parse_expr("(let ([x 5] [y 7]) (let ([x y] [y x]) (- x y)))").unwrap()),
+            Ok(2)
+        );
+
+        assert_eq!(
+            interp(&parse_expr("(let ([x 1]) x 2 (+ x 40))").unwrap()),
+            Ok(41)
+        );
+    }
+
+    #[test]
+    fn interp_variadic() {
+        use crate::parse_expr;
+
+        assert_eq!(interp(&parse_expr("(+)").unwrap()), Ok(0));
+        assert_eq!(interp(&parse_expr("(+ 1 2 3 4)").unwrap()), Ok(10));
+        assert_eq!(interp(&parse_expr("(- 10 1 2 3)").unwrap()), Ok(4));
+    }
+
+    #[test]
+    fn interp_read() {
+        use crate::parse_expr;
+
+        let expr = parse_expr("(let ([x read] [y read]) (- x y))").unwrap();
+
+        let mut input = VecDeque::from([10, 3, 7]);
+        assert_eq!(
+            interp_expr(&expr, &mut input),
+            Ok(Evaluation {
+                value: 7,
+                inputs_consumed: 2
+            })
+        );
+        assert_eq!(input, VecDeque::from([7]));
+
+        assert_eq!(
+            interp_expr(&expr, &mut input),
+            Err(InterpreterError::EndOfInput { consumed: 1 })
+        );
+    }
+
     #[test]
     fn interp_error() {
         assert!(matches!(
-            interp_expr(&Expr::Integer(u64::MAX)),
+            interp(&Expr::Integer(u64::MAX)),
             Err(InterpreterError::IntegerConversionError(_))
         ));
 
         assert_eq!(
-            interp_expr(&Expr::UnaryOperation {
+            interp(&Expr::UnaryOperation {
                 kind: UnaryOpKind::Minus,
                 operand: Box::new(Expr::BinaryOperation {
                     kind: BinaryOpKind::Sub,
@@ -303,7 +475,7 @@ mod test {
         );
 
         assert_eq!(
-            interp_expr(&Expr::BinaryOperation {
+            interp(&Expr::BinaryOperation {
                 kind: BinaryOpKind::Sub,
                 left_operand: Box::new(Expr::BinaryOperation {
                     kind: BinaryOpKind::Sub,
@@ -321,7 +493,7 @@ mod test {
         );
 
         assert_eq!(
-            interp_expr(&Expr::BinaryOperation {
+            interp(&Expr::BinaryOperation {
                 kind: BinaryOpKind::Add,
                 left_operand: Box::new(Expr::Integer(i64::MAX as u64)),
                 right_operand: Box::new(Expr::Integer(1))
@@ -332,7 +504,7 @@ mod test {
         );
 
         assert_eq!(
-            interp_expr(&Expr::Let {
+            interp(&Expr::Let {
                 variable_name: "x1".to_string(),
                 init_expr: Box::new(Expr::Integer(1)),
                 body: Box::new(Expr::Identifier("x".to_string()))
@@ -340,4 +512,100 @@ mod test {
             Err(InterpreterError::UnknownIdentifier("x".to_string()))
         );
     }
+
+    #[test]
+    fn interp_limits() {
+        use crate::parse_expr;
+
+        let expr = parse_expr("(let ([x 1]) (let ([y 2]) (+ x y)))").unwrap();
+        let interp_with = |fuel, max_scope_depth| {
+            let options = InterpOptions {
+                fuel,
+                max_scope_depth,
+                ..InterpOptions::default()
+            };
+            interp_expr_with_options(&expr, &mut VecDeque::new(), options)
+                .map(|evaluation| evaluation.value)
+        };
+
+        // The program evaluates 7 expressions in 2 nested scopes.
+        assert_eq!(interp_with(Some(7), Some(2)), Ok(3));
+        assert_eq!(
+            interp_with(Some(6), None),
+            Err(InterpreterError::StepLimitExceeded)
+        );
+        assert_eq!(
+            interp_with(None, Some(1)),
+            Err(InterpreterError::ScopeDepthExceeded)
+        );
+    }
+
+    #[test]
+    fn interp_trace() {
+        use crate::parse_expr;
+
+        let expr = parse_expr("(let ([x read]) (let ([x (- x)]) (+ x y)))").unwrap();
+        let mut events = Vec::new();
+        let mut trace = |expr: &Expr, env: &Environment<'_>, result: &Result<i64, _>| {
+            let bindings: Vec<_> = env
+                .bindings()
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            events.push(format!(
+                "{} [{}] -> {:?}",
+                expr,
+                bindings.join(", "),
+                result
+            ));
+        };
+        let options = InterpOptions {
+            trace: Some(&mut trace),
+            ..InterpOptions::default()
+        };
+
+        assert_eq!(
+            interp_expr_with_options(&expr, &mut VecDeque::from([5]), options),
+            Err(InterpreterError::UnknownIdentifier("y".to_string()))
+        );
+        assert_eq!(
+            events.join("\n"),
+            r#"
+read [] -> Ok(5)
+x [x=5] -> Ok(5)
+(- x) [x=5] -> Ok(-5)
+x [x=-5] -> Ok(-5)
+y [x=-5] -> Err(UnknownIdentifier("y"))
+(+ x y) [x=-5] -> Err(UnknownIdentifier("y"))
+(let ([x (- x)]) (+ x y)) [x=5] -> Err(UnknownIdentifier("y"))
+(let ([x read]) (let ([x (- x)]) (+ x y))) [] -> Err(UnknownIdentifier("y"))
+"#
+            .trim()
+        );
+    }
+
+    #[test]
+    fn interp_semantics() {
+        use crate::parse_expr;
+
+        let expr = parse_expr("(- (+ 9223372036854775807 1) 1)").unwrap();
+        let interp_with = |semantics| {
+            let options = InterpOptions {
+                semantics,
+                ..InterpOptions::default()
+            };
+            interp_expr_with_options(&expr, &mut VecDeque::new(), options)
+                .map(|evaluation| evaluation.value)
+        };
+
+        assert_eq!(interp_with(ArithmeticSemantics::Wrapping), Ok(i64::MAX));
+        for semantics in [ArithmeticSemantics::Checked, ArithmeticSemantics::Trapping] {
+            assert_eq!(
+                interp_with(semantics),
+                Err(InterpreterError::ArithmeticOverflow(
+                    OverflowKind::AddOverflow(i64::MAX, 1)
+                ))
+            );
+        }
+    }
 }
@@ -1,33 +1,71 @@
-use std::{iter::Enumerate, str::Bytes};
+use std::str::CharIndices;
 
 use crate::token::{Token, TokenKind};
 
-pub(crate) struct Lexer<'a> {
-    cur: Enumerate<Bytes<'a>>,
+/// Splits source code into tokens.
+///
+/// All locations are byte offsets into the whole source code, even if the lexer is resumed from
+/// the middle of it with [`Lexer::new_at`].
+pub struct Lexer<'a> {
+    cur: CharIndices<'a>,
     code: &'a str,
+    // The offset in `code` where `cur` starts.
+    start_offset: usize,
+    keep_trivia: bool,
 }
 
 impl<'a> Lexer<'a> {
-    pub(crate) fn new(code: &'a str) -> Self {
+    pub fn new(code: &'a str) -> Self {
         Self {
-            cur: code.bytes().enumerate(),
+            cur: code.char_indices(),
             code,
+            start_offset: 0,
+            keep_trivia: false,
         }
     }
 
-    fn cur_value(&self) -> Option<(usize, u8)> {
-        self.cur.clone().next()
+    /// Creates a lexer that starts lexing `code` at `offset`. Since the lexer keeps no state
+    /// between tokens, resuming at the start location of a token produces exactly the same tokens
+    /// as lexing the whole code. Returns `None` if `offset` is not a character boundary of `code`.
+    pub fn new_at(code: &'a str, offset: usize) -> Option<Self> {
+        Some(Self {
+            cur: code.get(offset..)?.char_indices(),
+            code,
+            start_offset: offset,
+            keep_trivia: false,
+        })
+    }
+
+    /// Makes the lexer also produce whitespace and comment tokens, so that the tokens cover the
+    /// whole source code.
+    pub fn with_trivia(mut self) -> Self {
+        self.keep_trivia = true;
+        self
+    }
+
+    /// Returns the offset where the next token starts, or the offset of the trivia before it.
+    pub fn offset(&self) -> usize {
+        self.cur_index()
+    }
+
+    fn cur_value(&self) -> Option<(usize, char)> {
+        self.cur
+            .clone()
+            .next()
+            .map(|(index, ch)| (self.start_offset + index, ch))
     }
 
-    fn cur_value_and_consume(&mut self) -> Option<(usize, u8)> {
-        self.cur.next()
+    fn cur_value_and_consume(&mut self) -> Option<(usize, char)> {
+        self.cur
+            .next()
+            .map(|(index, ch)| (self.start_offset + index, ch))
     }
 
     fn consume(&mut self) {
         self.cur.next();
     }
 
-    fn consume_while(&mut self, mut pred: impl FnMut(u8) -> bool) {
+    fn consume_while(&mut self, mut pred: impl FnMut(char) -> bool) {
         while let Some((_, ch)) = self.cur_value() {
             if !pred(ch) {
                 break;
@@ -36,6 +74,10 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    fn cur_index(&self) -> usize {
+        self.start_offset + self.cur.offset()
+    }
+
     fn new_token(&self, kind: TokenKind, start_index: usize, len: usize) -> Token<'a> {
         Token {
             kind,
@@ -44,51 +86,113 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn handle_integer_literal(&mut self, start_index: usize) -> (TokenKind, usize) {
-        self.consume_while(|ch| ch.is_ascii_digit());
-        (
-            TokenKind::Integer,
-            self.cur_value().unwrap_or((self.code.len(), 0)).0 - start_index,
-        )
+    /// Checks whether `ch` terminates a symbol or a number, following the rules of the Racket
+    /// reader.
+    fn is_delimiter(ch: char) -> bool {
+        ch.is_whitespace()
+            || matches!(
+                ch,
+                '(' | ')' | '[' | ']' | '{' | '}' | '"' | ',' | '\'' | '`' | ';'
+            )
     }
 
-    fn handle_identifier(&mut self, start_index: usize) -> (TokenKind, usize) {
-        self.consume_while(|ch| ch.is_ascii_alphanumeric());
-
-        let end_index = self.cur_value().unwrap_or((self.code.len(), 0)).0;
-        (
-            match &self.code[start_index..end_index] {
-                "program" => TokenKind::Program,
-                "read" => TokenKind::Read,
-                "let" => TokenKind::Let,
-                _ => TokenKind::Identifier,
-            },
-            end_index - start_index,
-        )
+    /// Checks whether `ch` has a special meaning in the Racket reader that we don't support. Such
+    /// characters always form a token on their own.
+    fn is_unsupported(ch: char) -> bool {
+        matches!(ch, '{' | '}' | '"' | ',' | '\'' | '`' | '|' | '\\')
     }
 
-    pub(crate) fn next_token(&mut self) -> Token<'a> {
-        // Consume the whitespaces.
-        self.consume_while(|ch| ch.is_ascii_whitespace());
+    fn is_integer_literal(spelling: &str) -> bool {
+        let digits = spelling
+            .strip_prefix(|ch| ch == '+' || ch == '-')
+            .unwrap_or(spelling);
+        !digits.is_empty() && digits.bytes().all(|ch| ch.is_ascii_digit())
+    }
 
+    fn handle_atom(&mut self, start_index: usize) -> (TokenKind, usize) {
+        // Like in Racket, a number or a symbol extends to the next delimiter, so that `my-var`,
+        // `done?` and `x*` are single identifiers. Other number syntaxes such as `1.5` are not
+        // supported and are read as identifiers.
+        self.consume_while(|ch| !Self::is_delimiter(ch) && !Self::is_unsupported(ch));
+
+        let end_index = self.cur_index();
+        let kind = match &self.code[start_index..end_index] {
+            "+" => TokenKind::Plus,
+            "-" => TokenKind::Minus,
+            "program" => TokenKind::Program,
+            "read" => TokenKind::Read,
+            "let" => TokenKind::Let,
+            "let*" => TokenKind::LetStar,
+            spelling if Self::is_integer_literal(spelling) => TokenKind::Integer,
+            // Reader syntax such as `#t` or `#:keyword`, and the single dot used in pairs.
+            spelling if spelling.starts_with('#') || spelling == "." => TokenKind::Unknown,
+            _ => TokenKind::Identifier,
+        };
+        (kind, end_index - start_index)
+    }
+
+    fn handle_block_comment(&mut self, start_index: usize) -> (TokenKind, usize) {
+        // eat the '|' of `#|`
+        self.consume();
+
+        // Block comments can be nested.
+        let mut depth = 1;
+        while depth > 0 {
+            match self.cur_value_and_consume() {
+                Some((_, '#')) if matches!(self.cur_value(), Some((_, '|'))) => {
+                    self.consume();
+                    depth += 1;
+                }
+                Some((_, '|')) if matches!(self.cur_value(), Some((_, '#'))) => {
+                    self.consume();
+                    depth -= 1;
+                }
+                Some(_) => (),
+                // The comment is not terminated.
+                None => return (TokenKind::Unknown, self.code.len() - start_index),
+            }
+        }
+
+        (TokenKind::Comment, self.cur_index() - start_index)
+    }
+
+    /// Produces the next token, including whitespace and comments.
+    fn next_raw_token(&mut self) -> Token<'a> {
         match self.cur_value_and_consume() {
-            None => self.new_token(TokenKind::EOF, self.code.len(), 0),
+            None => self.new_token(TokenKind::Eof, self.code.len(), 0),
             Some((index, ch)) => {
                 let (kind, len) = match ch {
-                    b'(' => (TokenKind::LParen, 1),
-                    b')' => (TokenKind::RParen, 1),
-                    b'+' => (TokenKind::Plus, 1),
-                    b'-' => (TokenKind::Minus, 1),
-                    b'[' => (TokenKind::LSquare, 1),
-                    b']' => (TokenKind::RSquare, 1),
-                    ch if ch.is_ascii_digit() => self.handle_integer_literal(index),
-                    ch if ch.is_ascii_alphabetic() => self.handle_identifier(index),
-                    _ => (TokenKind::Unknown, 1),
+                    '(' => (TokenKind::LParen, 1),
+                    ')' => (TokenKind::RParen, 1),
+                    '[' => (TokenKind::LSquare, 1),
+                    ']' => (TokenKind::RSquare, 1),
+                    ch if ch.is_whitespace() => {
+                        self.consume_while(char::is_whitespace);
+                        (TokenKind::Whitespace, self.cur_index() - index)
+                    }
+                    ';' => {
+                        self.consume_while(|ch| ch != '\n');
+                        (TokenKind::Comment, self.cur_index() - index)
+                    }
+                    '#' if matches!(self.cur_value(), Some((_, '|'))) => {
+                        self.handle_block_comment(index)
+                    }
+                    ch if Self::is_unsupported(ch) => (TokenKind::Unknown, ch.len_utf8()),
+                    _ => self.handle_atom(index),
                 };
                 self.new_token(kind, index, len)
             }
         }
     }
+
+    pub fn next_token(&mut self) -> Token<'a> {
+        loop {
+            let token = self.next_raw_token();
+            if self.keep_trivia || !token.token_kind().is_trivia() {
+                return token;
+            }
+        }
+    }
 }
 
 impl<'a> Iterator for Lexer<'a> {
@@ -97,7 +201,7 @@ impl<'a> Iterator for Lexer<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         match self.next_token() {
             Token {
-                kind: TokenKind::EOF,
+                kind: TokenKind::Eof,
                 ..
             } => None,
             other => Some(other),
@@ -111,7 +215,7 @@ mod test {
 
     #[test]
     fn lex() {
-        let code = "1 +23program-7";
+        let code = "1 + 23(program)-7";
         let mut lexer = Lexer::new(code);
 
         assert_eq!(
@@ -137,7 +241,16 @@ mod test {
             Token {
                 kind: TokenKind::Integer,
                 spelling: "23",
-                location: 3,
+                location: 4,
+            }
+        );
+
+        assert_eq!(
+            lexer.next_token(),
+            Token {
+                kind: TokenKind::LParen,
+                spelling: "(",
+                location: 6,
             }
         );
 
@@ -146,16 +259,16 @@ mod test {
             Token {
                 kind: TokenKind::Program,
                 spelling: "program",
-                location: 5,
+                location: 7,
             }
         );
 
         assert_eq!(
             lexer.next_token(),
             Token {
-                kind: TokenKind::Minus,
-                spelling: "-",
-                location: 12,
+                kind: TokenKind::RParen,
+                spelling: ")",
+                location: 14,
             }
         );
 
@@ -163,24 +276,24 @@ mod test {
             lexer.next_token(),
             Token {
                 kind: TokenKind::Integer,
-                spelling: "7",
-                location: 13,
+                spelling: "-7",
+                location: 15,
             }
         );
 
         assert_eq!(
             lexer.next_token(),
             Token {
-                kind: TokenKind::EOF,
+                kind: TokenKind::Eof,
                 spelling: "",
-                location: 14,
+                location: 17,
             }
         );
     }
 
     #[test]
     fn integers() {
-        let code = " 12 3 -3 256";
+        let code = " 12 3 -3 +256 1+ -1-";
         let lexer = Lexer::new(code);
 
         let result_tokens: Vec<_> = lexer.into_iter().collect();
@@ -193,13 +306,14 @@ mod test {
             vec![
                 TokenKind::Integer,
                 TokenKind::Integer,
-                TokenKind::Minus,
                 TokenKind::Integer,
                 TokenKind::Integer,
+                TokenKind::Identifier,
+                TokenKind::Identifier,
             ]
         );
 
-        let spellings = vec!["12", "3", "-", "3", "256"];
+        let spellings = vec!["12", "3", "-3", "+256", "1+", "-1-"];
         assert_eq!(
             result_tokens
                 .iter()
@@ -208,7 +322,7 @@ mod test {
             spellings
         );
 
-        let start_locations = vec![1, 4, 6, 7, 9];
+        let start_locations = vec![1, 4, 6, 9, 14, 17];
         assert_eq!(
             result_tokens
                 .iter()
@@ -255,19 +369,18 @@ mod test {
             vec![
                 TokenKind::RParen,
                 TokenKind::LParen,
-                TokenKind::Plus,
-                TokenKind::Minus,
+                TokenKind::Identifier,
                 TokenKind::RParen,
                 TokenKind::Minus,
                 TokenKind::LSquare,
-                TokenKind::Unknown,
+                TokenKind::Identifier,
                 TokenKind::LSquare,
                 TokenKind::RSquare,
                 TokenKind::RSquare,
             ]
         );
 
-        let spellings = vec![")", "(", "+", "-", ")", "-", "[", "*", "[", "]", "]"];
+        let spellings = vec![")", "(", "+-", ")", "-", "[", "*", "[", "]", "]"];
         assert_eq!(
             result_tokens
                 .iter()
@@ -276,7 +389,7 @@ mod test {
             spellings
         );
 
-        let lens = vec![1; 11];
+        let lens: Vec<_> = spellings.iter().map(|spelling| spelling.len()).collect();
         assert_eq!(
             result_tokens
                 .iter()
@@ -285,7 +398,7 @@ mod test {
             lens
         );
 
-        let start_locations = vec![0, 1, 2, 3, 5, 7, 8, 9, 11, 13, 14];
+        let start_locations = vec![0, 1, 2, 5, 7, 8, 9, 11, 13, 14];
         assert_eq!(
             result_tokens
                 .iter()
@@ -294,7 +407,11 @@ mod test {
             start_locations
         );
 
-        let end_locations: Vec<_> = start_locations.iter().map(|loc| loc + 1).collect();
+        let end_locations: Vec<_> = start_locations
+            .iter()
+            .zip(lens.iter())
+            .map(|(loc, len)| loc + len)
+            .collect();
         assert_eq!(
             result_tokens
                 .iter()
@@ -306,7 +423,7 @@ mod test {
 
     #[test]
     fn identifiers() {
-        let code = "program read reAD  Program pRogram xxx let arg";
+        let code = "program read reAD  Program pRogram xxx let arg let*";
         let lexer = Lexer::new(code);
 
         let result_tokens: Vec<_> = lexer.into_iter().collect();
@@ -325,11 +442,12 @@ mod test {
                 TokenKind::Identifier,
                 TokenKind::Let,
                 TokenKind::Identifier,
+                TokenKind::LetStar,
             ]
         );
 
         let spellings = vec![
-            "program", "read", "reAD", "Program", "pRogram", "xxx", "let", "arg",
+            "program", "read", "reAD", "Program", "pRogram", "xxx", "let", "arg", "let*",
         ];
         assert_eq!(
             result_tokens
@@ -348,7 +466,7 @@ mod test {
             lens
         );
 
-        let start_locations = vec![0, 8, 13, 19, 27, 35, 39, 43];
+        let start_locations = vec![0, 8, 13, 19, 27, 35, 39, 43, 47];
         assert_eq!(
             result_tokens
                 .iter()
@@ -370,4 +488,155 @@ mod test {
             end_locations
         );
     }
+
+    #[test]
+    fn racket_identifiers() {
+        let code = "my-var done? set-x! x* λ a.b 1+ #t . {x} |a|";
+        let lexer = Lexer::new(code);
+
+        assert_eq!(
+            lexer
+                .into_iter()
+                .map(|token| (token.token_kind(), token.spelling()))
+                .collect::<Vec<_>>(),
+            vec![
+                (TokenKind::Identifier, "my-var"),
+                (TokenKind::Identifier, "done?"),
+                (TokenKind::Identifier, "set-x!"),
+                (TokenKind::Identifier, "x*"),
+                (TokenKind::Identifier, "λ"),
+                (TokenKind::Identifier, "a.b"),
+                (TokenKind::Identifier, "1+"),
+                (TokenKind::Unknown, "#t"),
+                (TokenKind::Unknown, "."),
+                (TokenKind::Unknown, "{"),
+                (TokenKind::Identifier, "x"),
+                (TokenKind::Unknown, "}"),
+                (TokenKind::Unknown, "|"),
+                (TokenKind::Identifier, "a"),
+                (TokenKind::Unknown, "|"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unicode() {
+        let code = "(λ\u{3000}变量 «1»)";
+        let lexer = Lexer::new(code);
+
+        assert_eq!(
+            lexer
+                .into_iter()
+                .map(|token| (token.token_kind(), token.spelling(), token.start_location()))
+                .collect::<Vec<_>>(),
+            vec![
+                (TokenKind::LParen, "(", 0),
+                (TokenKind::Identifier, "λ", 1),
+                (TokenKind::Identifier, "变量", 6),
+                (TokenKind::Identifier, "«1»", 13),
+                (TokenKind::RParen, ")", 18),
+            ]
+        );
+    }
+
+    #[test]
+    fn trivia() {
+        let code = "(+ 1 ; one\n #| a #| nested |# comment |#2)\t#| unterminated";
+
+        assert_eq!(
+            Lexer::new(code)
+                .map(|token| (token.token_kind(), token.spelling()))
+                .collect::<Vec<_>>(),
+            vec![
+                (TokenKind::LParen, "("),
+                (TokenKind::Plus, "+"),
+                (TokenKind::Integer, "1"),
+                (TokenKind::Integer, "2"),
+                (TokenKind::RParen, ")"),
+                (TokenKind::Unknown, "#| unterminated"),
+            ]
+        );
+
+        let tokens: Vec<_> = Lexer::new(code).with_trivia().collect();
+        assert_eq!(
+            tokens
+                .iter()
+                .map(|token| (token.token_kind(), token.spelling()))
+                .collect::<Vec<_>>(),
+            vec![
+                (TokenKind::LParen, "("),
+                (TokenKind::Plus, "+"),
+                (TokenKind::Whitespace, " "),
+                (TokenKind::Integer, "1"),
+                (TokenKind::Whitespace, " "),
+                (TokenKind::Comment, "; one"),
+                (TokenKind::Whitespace, "\n "),
+                (TokenKind::Comment, "#| a #| nested |# comment |#"),
+                (TokenKind::Integer, "2"),
+                (TokenKind::RParen, ")"),
+                (TokenKind::Whitespace, "\t"),
+                (TokenKind::Unknown, "#| unterminated"),
+            ]
+        );
+
+        // The tokens cover the whole code without gaps.
+        assert_eq!(
+            tokens
+                .iter()
+                .map(|token| token.spelling())
+                .collect::<String>(),
+            code
+        );
+        assert!(tokens
+            .windows(2)
+            .all(|pair| pair[0].span().end == pair[1].span().start));
+    }
+
+    #[test]
+    fn resume() {
+        let code = "(let ([λ 1]) ; comment\n  (+ λ -2))";
+        let tokens: Vec<_> = Lexer::new(code).with_trivia().collect();
+
+        // Resuming at the start of any token produces the remaining tokens.
+        for (index, token) in tokens.iter().enumerate() {
+            let resumed_tokens: Vec<_> = Lexer::new_at(code, token.start_location())
+                .unwrap()
+                .with_trivia()
+                .collect();
+            assert_eq!(resumed_tokens, tokens[index..]);
+        }
+
+        let mut lexer = Lexer::new_at(code, 25).unwrap();
+        assert_eq!(lexer.offset(), 25);
+        assert_eq!(
+            lexer.next_token(),
+            Token {
+                kind: TokenKind::LParen,
+                spelling: "(",
+                location: 26,
+            }
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Token {
+                kind: TokenKind::Plus,
+                spelling: "+",
+                location: 27,
+            }
+        );
+        assert_eq!(lexer.offset(), 28);
+
+        assert_eq!(
+            Lexer::new_at(code, code.len()).unwrap().next_token(),
+            Token {
+                kind: TokenKind::Eof,
+                spelling: "",
+                location: code.len(),
+            }
+        );
+
+        // Offsets inside a character or past the end of the code are rejected.
+        assert!(Lexer::new_at(code, 8).is_none());
+        assert!(Lexer::new_at(code, code.len() + 1).is_none());
+    }
 }
@@ -1,9 +1,31 @@
+mod arithmetic;
 mod ast;
+#[cfg(test)]
+mod fuzz;
+mod generator;
+mod input;
 mod interpreter;
 mod lexer;
 mod parser;
+mod range;
+mod rng;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 mod token;
 
+pub use arithmetic::{checked_add, checked_neg, checked_sub, ArithmeticSemantics};
 pub use ast::{BinaryOpKind, Expr, Program, UnaryOpKind};
-pub use interpreter::{interp_expr, InterpreterError, OverflowKind};
-pub use parser::{parse_expr, ParseError, ParseErrorKind};
+pub use generator::{shrink_expr, ExprGenerator, GeneratorConfig};
+pub use input::{BufReadInput, FnInput, InputSource};
+pub use interpreter::{
+    interp_expr, interp_expr_with_options, Environment, Evaluation, InterpOptions,
+    InterpreterError, OverflowKind, TraceFn,
+};
+pub use lexer::Lexer;
+pub use parser::{
+    parse_expr, parse_expr_with_mode, ParseError, ParseErrorKind, ParseMode, MAX_NESTING_DEPTH,
+};
+pub use range::{
+    analyze_ranges, Interval, IntervalResult, OverflowRisk, OverflowWarning, RangeAnalysis,
+};
+pub use token::{Token, TokenKind};
@@ -12,24 +12,58 @@ pub enum ParseErrorKind {
     InvalidOperandCount(usize),
     MismatchedOpenParen,
     UnexpectedToken(String),
+    DuplicateBinding(String),
+    // An operator or a keyword is used outside a parenthesized form. Only reported in strict mode.
+    UnparenthesizedForm(String),
+    // A parenthesized form does not start with an operator or a keyword. Only reported in strict
+    // mode.
+    ExpectedOperator(String),
+    // The expressions are nested deeper than `MAX_NESTING_DEPTH`, either in the source code or
+    // after desugaring.
+    NestingTooDeep,
 }
 
+/// The maximum depth of the parsed expressions. Every pass over an `Expr` is recursive, so deeper
+/// expressions could overflow the stack, even when they are just dropped.
+pub const MAX_NESTING_DEPTH: usize = 256;
+
 #[derive(Debug, Eq, PartialEq)]
 pub struct ParseError {
     pub kind: ParseErrorKind,
     pub location: usize,
 }
 
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ParseMode {
+    // Only accepts valid Racket code, where every application and special form is written as
+    // exactly one parenthesized form, e.g. `(+ 1 2)` and `(read)`.
+    Strict,
+    // Also accepts unparenthesized forms such as `+ 1 2`, `- 3`, `read` and `let ([x 1]) x`, as
+    // well as redundant grouping parentheses such as `((1))`.
+    #[default]
+    Lenient,
+}
+
 struct Parser<'a> {
     lexer: Lexer<'a>,
     cur_token: Token<'a>,
+    mode: ParseMode,
+    fresh_name_index: u32,
+    // The number of `parse_expr` calls that are currently running.
+    depth: usize,
 }
 
 impl<'a> Parser<'a> {
-    fn new(code: &'a str) -> Self {
+    fn new(code: &'a str, mode: ParseMode) -> Self {
         let mut lexer = Lexer::new(code);
         let cur_token = lexer.next_token();
-        Parser { lexer, cur_token }
+        Parser {
+            lexer,
+            cur_token,
+            mode,
+            fresh_name_index: 0,
+            depth: 0,
+        }
     }
 
     fn consume_token(&mut self) {
@@ -68,13 +102,52 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_integer(&mut self) -> Result<u64, ParseError> {
+    /// Checks that wrapping the deepest of `exprs` in `wrappers` more expressions does not exceed
+    /// `MAX_NESTING_DEPTH`. This must be checked before the expression is built.
+    fn check_desugared_depth<'e>(
+        exprs: impl IntoIterator<Item = &'e Expr>,
+        wrappers: usize,
+        location: usize,
+    ) -> Result<(), ParseError> {
+        let depth = exprs.into_iter().map(Expr::depth).max().unwrap_or(0) + wrappers;
+        if depth > MAX_NESTING_DEPTH {
+            Err(ParseError {
+                kind: ParseErrorKind::NestingTooDeep,
+                location,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn parse_integer(&mut self) -> Result<Expr, ParseError> {
         // eat the integer token
         let token = self.current_token_and_consume();
 
+        // A negative literal such as `-3` is represented as the negation of its absolute value.
         let spelling = token.spelling();
-        match spelling.parse() {
-            Ok(result) => Ok(result),
+        let (is_negative, digits) = match spelling.as_bytes()[0] {
+            b'-' => (true, &spelling[1..]),
+            b'+' => (false, &spelling[1..]),
+            _ => (false, spelling),
+        };
+
+        let negate = |operand| Expr::UnaryOperation {
+            kind: UnaryOpKind::Minus,
+            operand: Box::new(operand),
+        };
+        match digits.parse::<u64>() {
+            // The absolute value of `i64::MIN` does not fit in an `i64`, so it is computed as
+            // `(- (- 9223372036854775807) 1)`, which never overflows.
+            Ok(result) if is_negative && result == i64::MIN.unsigned_abs() => {
+                Ok(Expr::BinaryOperation {
+                    kind: BinaryOpKind::Sub,
+                    left_operand: Box::new(negate(Expr::Integer(i64::MAX as u64))),
+                    right_operand: Box::new(Expr::Integer(1)),
+                })
+            }
+            Ok(result) if is_negative => Ok(negate(Expr::Integer(result))),
+            Ok(result) => Ok(Expr::Integer(result)),
             Err(e) => Err(ParseError {
                 kind: ParseErrorKind::ParseIntegerError(e),
                 location: token.start_location(),
@@ -87,61 +160,74 @@ impl<'a> Parser<'a> {
         let operator_token = self.current_token_and_consume();
 
         let mut operands = Vec::new();
-        while let Ok(expr) = self.parse_expr() {
-            operands.push(expr);
+        while !self.at_form_end() {
+            operands.push(self.parse_expr()?);
         }
 
-        match operator_token.token_kind() {
-            TokenKind::Plus if operands.len() == 2 => unsafe {
-                let right_operand = operands.pop().unwrap_unchecked();
-                let left_operand = operands.pop().unwrap_unchecked();
+        // `(+ e1 e2 ... en)` and `(- e1 e2 ... en)` are desugared into left-associative binary
+        // operations, i.e. `(+ (+ e1 e2) ... en)`, so that the operands are still evaluated from
+        // left to right.
+        let kind = match operator_token.token_kind() {
+            TokenKind::Plus if operands.is_empty() => return Ok(Expr::Integer(0)),
+            TokenKind::Plus => BinaryOpKind::Add,
 
-                Ok(Expr::BinaryOperation {
-                    kind: BinaryOpKind::Add,
-                    left_operand: Box::new(left_operand),
-                    right_operand: Box::new(right_operand),
-                })
-            },
-
-            TokenKind::Minus if operands.len() == 2 => unsafe {
-                let right_operand = operands.pop().unwrap_unchecked();
-                let left_operand = operands.pop().unwrap_unchecked();
-                Ok(Expr::BinaryOperation {
-                    kind: BinaryOpKind::Sub,
-                    left_operand: Box::new(left_operand),
-                    right_operand: Box::new(right_operand),
-                })
-            },
-
-            TokenKind::Minus if operands.len() == 1 => unsafe {
-                Ok(Expr::UnaryOperation {
+            TokenKind::Minus if operands.len() == 1 => {
+                return Ok(Expr::UnaryOperation {
                     kind: UnaryOpKind::Minus,
-                    operand: Box::new(operands.pop().unwrap_unchecked()),
+                    operand: Box::new(operands.pop().unwrap()),
                 })
-            },
+            }
+            TokenKind::Minus if !operands.is_empty() => BinaryOpKind::Sub,
 
-            _ => Err(ParseError {
-                kind: ParseErrorKind::InvalidOperandCount(operands.len()),
-                location: operator_token.start_location(),
-            }),
-        }
+            _ => {
+                return Err(ParseError {
+                    kind: ParseErrorKind::InvalidOperandCount(operands.len()),
+                    location: operator_token.start_location(),
+                })
+            }
+        };
+
+        Self::check_desugared_depth(
+            &operands,
+            operands.len() - 1,
+            operator_token.start_location(),
+        )?;
+
+        let mut operands = operands.into_iter();
+        let first_operand = operands.next().unwrap();
+        Ok(operands.fold(first_operand, |left_operand, right_operand| {
+            Expr::BinaryOperation {
+                kind: kind.clone(),
+                left_operand: Box::new(left_operand),
+                right_operand: Box::new(right_operand),
+            }
+        }))
     }
 
     fn parse_paren_expr(&mut self) -> Result<Expr, ParseError> {
         // eat the '('
         let lparen_token = self.current_token_and_consume();
         // Parse the body.
-        let body = self.parse_expr();
+        let body = match self.mode {
+            ParseMode::Lenient => self.parse_expr(),
+            ParseMode::Strict if Self::starts_form(self.cur_token.token_kind()) => {
+                self.parse_form()
+            }
+            ParseMode::Strict => {
+                return Err(ParseError {
+                    kind: ParseErrorKind::ExpectedOperator(self.cur_token.spelling().to_string()),
+                    location: self.cur_token.start_location(),
+                })
+            }
+        }?;
         // eat the ')'
         self.expect_closing_paren_and_consume(TokenKind::RParen, &lparen_token)?;
-        body
+        Ok(body)
     }
 
-    fn parse_variable_declaration(&mut self) -> Result<(&'a str, Expr), ParseError> {
-        // Parse the `([var exp])` structure.
+    fn parse_variable_declaration(&mut self) -> Result<(Token<'a>, Expr), ParseError> {
+        // Parse the `[var exp]` structure.
 
-        // eat the '('
-        let lparen_token = self.expect_and_consume(TokenKind::LParen)?;
         // eat the '['
         let lsquare_token = self.expect_and_consume(TokenKind::LSquare)?;
 
@@ -152,42 +238,228 @@ impl<'a> Parser<'a> {
 
         // eat the ']'
         let _ = self.expect_closing_paren_and_consume(TokenKind::RSquare, &lsquare_token)?;
+
+        Ok((variable_token, initializer_expr))
+    }
+
+    fn parse_variable_declarations(
+        &mut self,
+        allow_duplicates: bool,
+    ) -> Result<Vec<(&'a str, Expr)>, ParseError> {
+        // Parse the `([var exp] ...)` structure.
+
+        // eat the '('
+        let lparen_token = self.expect_and_consume(TokenKind::LParen)?;
+
+        let mut declarations: Vec<(&'a str, Expr)> = Vec::new();
+        while self.cur_token.token_kind() == TokenKind::LSquare {
+            let (variable_token, init_expr) = self.parse_variable_declaration()?;
+            let variable_name = variable_token.spelling();
+
+            if !allow_duplicates && declarations.iter().any(|(name, _)| *name == variable_name) {
+                return Err(ParseError {
+                    kind: ParseErrorKind::DuplicateBinding(variable_name.to_string()),
+                    location: variable_token.start_location(),
+                });
+            }
+
+            declarations.push((variable_name, init_expr));
+        }
+
         // eat the ')'
         let _ = self.expect_closing_paren_and_consume(TokenKind::RParen, &lparen_token)?;
 
-        Ok((variable_token.spelling(), initializer_expr))
+        Ok(declarations)
+    }
+
+    fn parse_let_body(&mut self) -> Result<Vec<Expr>, ParseError> {
+        // The body contains at least one expression, and extends to the end of the enclosing form.
+        let mut body = vec![self.parse_expr()?];
+        while !self.at_form_end() {
+            body.push(self.parse_expr()?);
+        }
+        Ok(body)
     }
 
     fn parse_let_expr(&mut self) -> Result<Expr, ParseError> {
-        // eat the 'let' keyword
-        let _ = self.current_token_and_consume();
-        // Parse the variable declaration of the expression.
-        let (variable_name, init_expr) = self.parse_variable_declaration()?;
+        // eat the 'let' or 'let*' keyword
+        let let_token = self.current_token_and_consume();
+        let sequential = let_token.token_kind() == TokenKind::LetStar;
+        // Parse the variable declarations of the expression. Only `let*` can bind the same name
+        // more than once.
+        let declarations = self.parse_variable_declarations(sequential)?;
         // Parse the body of the let expression.
-        let body = self.parse_expr()?;
-
-        Ok(Expr::Let {
-            variable_name: variable_name.to_string(),
-            init_expr: Box::new(init_expr),
-            body: Box::new(body),
+        let body = self.parse_let_body()?;
+
+        // Every expression of the body except the last one and every declaration become a `let`,
+        // and a parallel `let` may bind every declaration twice.
+        Self::check_desugared_depth(
+            declarations
+                .iter()
+                .map(|(_, init_expr)| init_expr)
+                .chain(&body),
+            body.len() - 1 + declarations.len() * if sequential { 1 } else { 2 },
+            let_token.start_location(),
+        )?;
+
+        let body = self.desugar_body(body);
+        Ok(if sequential {
+            Self::desugar_sequential_let(declarations, body)
+        } else {
+            self.desugar_parallel_let(declarations, body)
         })
     }
 
-    fn parse_expr(&mut self) -> Result<Expr, ParseError> {
-        let token = self.cur_token.clone();
+    /// Generates a variable name that is not mentioned in `scope` and is not one of `declared`,
+    /// so that binding it around `scope` never captures or shadows a variable of the program. The
+    /// name is an ordinary identifier, so that the desugared expression can be printed and parsed
+    /// again.
+    fn generate_fresh_name(&mut self, scope: &[&Expr], declared: &[&str]) -> String {
+        loop {
+            let name = format!("tmp{}", self.fresh_name_index);
+            self.fresh_name_index += 1;
+            if !declared.contains(&name.as_str()) && scope.iter().all(|expr| !expr.mentions(&name))
+            {
+                return name;
+            }
+        }
+    }
 
-        match token.token_kind() {
-            TokenKind::Integer => Ok(Expr::Integer(self.parse_integer()?)),
+    fn desugar_body(&mut self, mut body: Vec<Expr>) -> Expr {
+        // `e1 e2 ... en` evaluates every expression in order and produces the value of `en`, so
+        // it is equivalent to binding the results of `e1` to `en-1` to unused variables.
+        let result = body.pop().unwrap();
+        let scope: Vec<Expr> = body.iter().cloned().chain([result.clone()]).collect();
+        let scope: Vec<&Expr> = scope.iter().collect();
+        let bindings: Vec<_> = body
+            .into_iter()
+            .map(|expr| (self.generate_fresh_name(&scope, &[]), expr))
+            .collect();
+        bindings
+            .into_iter()
+            .rev()
+            .fold(result, |result, (variable_name, expr)| Expr::Let {
+                variable_name,
+                init_expr: Box::new(expr),
+                body: Box::new(result),
+            })
+    }
+
+    fn desugar_sequential_let(declarations: Vec<(&'a str, Expr)>, body: Expr) -> Expr {
+        // `(let* ([x1 e1] [x2 e2]) body)` is exactly `(let ([x1 e1]) (let ([x2 e2]) body))`.
+        declarations
+            .into_iter()
+            .rev()
+            .fold(body, |body, (variable_name, init_expr)| Expr::Let {
+                variable_name: variable_name.to_string(),
+                init_expr: Box::new(init_expr),
+                body: Box::new(body),
+            })
+    }
+
+    fn desugar_parallel_let(&mut self, declarations: Vec<(&'a str, Expr)>, body: Expr) -> Expr {
+        // In `(let ([x1 e1] [x2 e2]) body)`, `e2` must not see `x1`. If a later initializer
+        // mentions a variable declared before it, we first bind the value of that variable to a
+        // fresh name, and only bind the variable itself after all initializers are evaluated:
+        //
+        // (let ([tmp0 e1]) (let ([x2 e2]) (let ([x1 tmp0]) body)))
+        //
+        // This keeps the evaluation order of the initializers.
+        let mut bindings = Vec::new();
+        let mut renamed_bindings = Vec::new();
+        let scope: Vec<Expr> = declarations
+            .iter()
+            .map(|(_, init_expr)| init_expr.clone())
+            .chain([body.clone()])
+            .collect();
+        let scope: Vec<&Expr> = scope.iter().collect();
+        let declared: Vec<&str> = declarations.iter().map(|(name, _)| *name).collect();
+
+        let is_mentioned_later: Vec<_> = (0..declarations.len())
+            .map(|index| {
+                declarations[index + 1..]
+                    .iter()
+                    .any(|(_, later_init)| later_init.mentions(declarations[index].0))
+            })
+            .collect();
+
+        for ((variable_name, init_expr), is_mentioned_later) in
+            declarations.into_iter().zip(is_mentioned_later)
+        {
+            if is_mentioned_later {
+                let fresh_name = self.generate_fresh_name(&scope, &declared);
+                bindings.push((fresh_name.clone(), init_expr));
+                renamed_bindings.push((variable_name.to_string(), Expr::Identifier(fresh_name)));
+            } else {
+                bindings.push((variable_name.to_string(), init_expr));
+            }
+        }
+
+        bindings.into_iter().chain(renamed_bindings).rev().fold(
+            body,
+            |body, (variable_name, init_expr)| Expr::Let {
+                variable_name,
+                init_expr: Box::new(init_expr),
+                body: Box::new(body),
+            },
+        )
+    }
+
+    /// Checks whether a token of `kind` starts an application or a special form.
+    fn starts_form(kind: TokenKind) -> bool {
+        matches!(
+            kind,
+            TokenKind::Read
+                | TokenKind::Plus
+                | TokenKind::Minus
+                | TokenKind::Let
+                | TokenKind::LetStar
+        )
+    }
+
+    fn parse_form(&mut self) -> Result<Expr, ParseError> {
+        // Parse an application or a special form, without the parentheses around it.
+        match self.cur_token.token_kind() {
             TokenKind::Read => {
                 self.consume_token();
                 Ok(Expr::Read)
             }
+            TokenKind::Plus | TokenKind::Minus => self.parse_multi_operands_expr(),
+            TokenKind::Let | TokenKind::LetStar => self.parse_let_expr(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        if self.depth == MAX_NESTING_DEPTH {
+            return Err(ParseError {
+                kind: ParseErrorKind::NestingTooDeep,
+                location: self.cur_token.start_location(),
+            });
+        }
+
+        self.depth += 1;
+        let result = self.parse_expr_impl();
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_expr_impl(&mut self) -> Result<Expr, ParseError> {
+        let token = self.cur_token.clone();
+
+        match token.token_kind() {
+            TokenKind::Integer => self.parse_integer(),
             TokenKind::Identifier => Ok(Expr::Identifier(
                 self.current_token_and_consume().spelling().to_string(),
             )),
-            TokenKind::Plus | TokenKind::Minus => self.parse_multi_operands_expr(),
             TokenKind::LParen => self.parse_paren_expr(),
-            TokenKind::Let => self.parse_let_expr(),
+            kind if Self::starts_form(kind) => match self.mode {
+                ParseMode::Lenient => self.parse_form(),
+                ParseMode::Strict => Err(ParseError {
+                    kind: ParseErrorKind::UnparenthesizedForm(token.spelling().to_string()),
+                    location: token.start_location(),
+                }),
+            },
             _ => Err(ParseError {
                 kind: ParseErrorKind::UnexpectedToken(String::from(token.spelling())),
                 location: token.start_location(),
@@ -195,13 +467,25 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Checks whether the current token ends the enclosing form.
+    fn at_form_end(&self) -> bool {
+        matches!(
+            self.cur_token.token_kind(),
+            TokenKind::RParen | TokenKind::RSquare | TokenKind::Eof
+        )
+    }
+
     fn parse_finished(&self) -> bool {
-        self.cur_token.token_kind() == TokenKind::EOF
+        self.cur_token.token_kind() == TokenKind::Eof
     }
 }
 
 pub fn parse_expr(code: &str) -> Result<Expr, ParseError> {
-    let mut parser = Parser::new(code);
+    parse_expr_with_mode(code, ParseMode::Lenient)
+}
+
+pub fn parse_expr_with_mode(code: &str, mode: ParseMode) -> Result<Expr, ParseError> {
+    let mut parser = Parser::new(code, mode);
     let result = parser.parse_expr()?;
 
     if parser.parse_finished() {
@@ -240,6 +524,11 @@ mod test {
             })
         );
 
+        assert_eq!(
+            parse_expr("-9223372036854775808").unwrap().to_string(),
+            "(- (- 9223372036854775807) 1)"
+        );
+
         assert_eq!(
             parse_expr("(( (+ 1 (3) )))"),
             Ok(Expr::BinaryOperation {
@@ -285,6 +574,30 @@ mod test {
         );
     }
 
+    #[test]
+    fn parse_variadic_expr() {
+        assert_eq!(parse_expr("(+)"), Ok(Expr::Integer(0)));
+
+        assert_eq!(parse_expr("(+ read)"), Ok(Expr::Read));
+
+        assert_eq!(
+            parse_expr("(+ 1 2 3)").unwrap().to_string(),
+            "(+ (+ 1 2) 3)".to_string()
+        );
+
+        assert_eq!(
+            parse_expr("- 3 3 1").unwrap().to_string(),
+            "(- (- 3 3) 1)".to_string()
+        );
+
+        assert_eq!(
+            parse_expr("(- read (+ 1 2 3 4) (- 5) x)")
+                .unwrap()
+                .to_string(),
+            "(- (- (- read (+ (+ (+ 1 2) 3) 4)) (- 5)) x)".to_string()
+        );
+    }
+
     #[test]
     fn parse_variable() {
         assert_eq!(
@@ -361,43 +674,211 @@ mod test {
     }
 
     #[test]
-    fn parse_error() {
-        assert!(matches!(
-            parse_expr("18446744073709551616"),
+    fn parse_racket_identifiers() {
+        assert_eq!(
+            parse_expr("(let ([my-var -5] [λ +2]) (- my-var λ))"),
+            Ok(Expr::Let {
+                variable_name: "my-var".to_string(),
+                init_expr: Box::new(Expr::UnaryOperation {
+                    kind: UnaryOpKind::Minus,
+                    operand: Box::new(Expr::Integer(5))
+                }),
+                body: Box::new(Expr::Let {
+                    variable_name: "λ".to_string(),
+                    init_expr: Box::new(Expr::Integer(2)),
+                    body: Box::new(Expr::BinaryOperation {
+                        kind: BinaryOpKind::Sub,
+                        left_operand: Box::new(Expr::Identifier("my-var".to_string())),
+                        right_operand: Box::new(Expr::Identifier("λ".to_string()))
+                    })
+                })
+            })
+        );
+
+        assert_eq!(
+            parse_expr("(+ 变量 1)"),
+            Ok(Expr::BinaryOperation {
+                kind: BinaryOpKind::Add,
+                left_operand: Box::new(Expr::Identifier("变量".to_string())),
+                right_operand: Box::new(Expr::Integer(1))
+            })
+        );
+
+        assert_eq!(
+            parse_expr("λ #t"),
             Err(ParseError {
-                kind: ParseErrorKind::ParseIntegerError(_),
-                location: _
+                kind: ParseErrorKind::UnexpectedToken("#t".to_string()),
+                location: 3
             })
-        ));
+        );
+    }
+
+    #[test]
+    fn parse_comments() {
+        assert_eq!(
+            parse_expr_with_mode(
+                r#"; The answer.
+                (let ([x 40]) #| the initializer |#
+                  (+ x ; the variable
+                     2))"#,
+                ParseMode::Strict
+            ),
+            parse_expr("let ([x 40]) (+ x 2)")
+        );
+    }
 
+    #[test]
+    fn parse_multiple_bindings() {
         assert_eq!(
-            parse_expr(" + 3"),
+            parse_expr("(let ([x 1] [y 2]) (+ x y))")
+                .unwrap()
+                .to_string(),
+            "(let ([x 1]) (let ([y 2]) (+ x y)))"
+        );
+
+        // The initializer of `y` refers to the `x` in the parent scope.
+        assert_eq!(
+            parse_expr("(let ([x 1]) (let ([x 2] [y x]) (+ x y)))")
+                .unwrap()
+                .to_string(),
+            "(let ([x 1]) (let ([tmp0 2]) (let ([y x]) (let ([x tmp0]) (+ x y)))))"
+        );
+
+        // The fresh names do not capture the variables of the program.
+        assert_eq!(
+            parse_expr("(let ([tmp0 1] [tmp1 2]) (let ([tmp0 tmp1] [tmp1 tmp0]) tmp0 tmp1))")
+                .unwrap()
+                .to_string(),
+            "(let ([tmp0 1]) (let ([tmp1 2]) (let ([tmp3 tmp1]) (let ([tmp1 tmp0]) \
+             (let ([tmp0 tmp3]) (let ([tmp2 tmp0]) tmp1))))))"
+        );
+
+        assert_eq!(
+            parse_expr("(let* ([x 1] [y x] [x (+ x y)]) x)")
+                .unwrap()
+                .to_string(),
+            "(let ([x 1]) (let ([y x]) (let ([x (+ x y)]) x)))"
+        );
+
+        assert_eq!(
+            parse_expr("(let () 10)").unwrap().to_string(),
+            "10".to_string()
+        );
+
+        assert_eq!(
+            parse_expr("(let ([x read]) read (- x) x)")
+                .unwrap()
+                .to_string(),
+            "(let ([x read]) (let ([tmp0 read]) (let ([tmp1 (- x)]) x)))"
+        );
+
+        // The desugared expressions can be printed and parsed again.
+        for code in [
+            "(let ([x 1]) (let ([x 2] [y x]) y))",
+            "(let ([x read]) read (- x) x)",
+            "(let ([tmp0 1] [tmp1 2]) (let ([tmp0 tmp1] [tmp1 tmp0]) tmp0 tmp1))",
+        ] {
+            let expr = parse_expr(code).unwrap();
+            assert_eq!(parse_expr(&expr.to_string()), Ok(expr));
+        }
+    }
+
+    #[test]
+    fn parse_strict() {
+        let parse_strict = |code| parse_expr_with_mode(code, ParseMode::Strict);
+
+        assert_eq!(parse_strict("42"), Ok(Expr::Integer(42)));
+        assert_eq!(parse_strict("(read)"), Ok(Expr::Read));
+        assert_eq!(parse_strict("(+ 1 2)"), parse_expr("+ 1 2"));
+        assert_eq!(parse_strict("(- 3)"), parse_expr("-3"));
+        assert_eq!(
+            parse_strict("(let ([x (read)] [y 2]) (+ x (- y)))"),
+            parse_expr("let ([x read] [y 2]) (+ x (- y))")
+        );
+        assert_eq!(
+            parse_strict("(let* ([x 1] [y x]) (let () y))"),
+            parse_expr("let* ([x 1] [y x]) y")
+        );
+
+        assert_eq!(
+            parse_strict("+ 1 2"),
             Err(ParseError {
-                kind: ParseErrorKind::InvalidOperandCount(1),
-                location: 1
+                kind: ParseErrorKind::UnparenthesizedForm("+".to_string()),
+                location: 0
             })
         );
 
         assert_eq!(
-            parse_expr(" + 3 3 1"),
+            parse_strict("(+ 1 - 3)"),
             Err(ParseError {
-                kind: ParseErrorKind::InvalidOperandCount(3),
-                location: 1
+                kind: ParseErrorKind::UnparenthesizedForm("-".to_string()),
+                location: 5
+            })
+        );
+
+        assert_eq!(
+            parse_strict("(+ read 1)"),
+            Err(ParseError {
+                kind: ParseErrorKind::UnparenthesizedForm("read".to_string()),
+                location: 3
             })
         );
 
         assert_eq!(
-            parse_expr("- 3 3 1"),
+            parse_strict("let ([x 1]) x"),
             Err(ParseError {
-                kind: ParseErrorKind::InvalidOperandCount(3),
+                kind: ParseErrorKind::UnparenthesizedForm("let".to_string()),
                 location: 0
             })
         );
 
         assert_eq!(
-            parse_expr(" * 3 3 1"),
+            parse_strict("(((1)))"),
+            Err(ParseError {
+                kind: ParseErrorKind::ExpectedOperator("(".to_string()),
+                location: 1
+            })
+        );
+
+        assert_eq!(
+            parse_strict("(let ([x 1]) (x))"),
+            Err(ParseError {
+                kind: ParseErrorKind::ExpectedOperator("x".to_string()),
+                location: 14
+            })
+        );
+    }
+
+    #[test]
+    fn parse_error() {
+        assert!(matches!(
+            parse_expr("18446744073709551616"),
+            Err(ParseError {
+                kind: ParseErrorKind::ParseIntegerError(_),
+                location: _
+            })
+        ));
+
+        assert_eq!(
+            parse_expr(" (-)"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidOperandCount(0),
+                location: 2
+            })
+        );
+
+        assert_eq!(
+            parse_expr("+ 1 (- 2) #t"),
             Err(ParseError {
-                kind: ParseErrorKind::UnexpectedToken("*".to_string()),
+                kind: ParseErrorKind::UnexpectedToken("#t".to_string()),
+                location: 10
+            })
+        );
+
+        assert_eq!(
+            parse_expr(" ' 3 3 1"),
+            Err(ParseError {
+                kind: ParseErrorKind::UnexpectedToken("'".to_string()),
                 location: 1
             })
         );
@@ -449,5 +930,73 @@ mod test {
                 location: 5
             })
         );
+
+        assert_eq!(
+            parse_expr("(let ([x 1] [y 2] [x 3]) x)"),
+            Err(ParseError {
+                kind: ParseErrorKind::DuplicateBinding("x".to_string()),
+                location: 19
+            })
+        );
+
+        assert_eq!(
+            parse_expr("let ([x 1] 2) x"),
+            Err(ParseError {
+                kind: ParseErrorKind::MismatchedOpenParen,
+                location: 4
+            })
+        );
+
+        assert_eq!(
+            parse_expr("(let ([x 1]))"),
+            Err(ParseError {
+                kind: ParseErrorKind::UnexpectedToken(")".to_string()),
+                location: 12
+            })
+        );
+    }
+
+    #[test]
+    fn parse_nesting_limit() {
+        let nested = |depth| "(".repeat(depth) + "1" + &")".repeat(depth);
+        assert_eq!(
+            parse_expr(&nested(MAX_NESTING_DEPTH - 1)),
+            Ok(Expr::Integer(1))
+        );
+        assert_eq!(
+            parse_expr(&nested(MAX_NESTING_DEPTH)),
+            Err(ParseError {
+                kind: ParseErrorKind::NestingTooDeep,
+                location: MAX_NESTING_DEPTH
+            })
+        );
+
+        // The left-associative desugaring of a long application is as deep as the number of
+        // operands.
+        let operands = |count| format!("(+{})", " 1".repeat(count));
+        assert_eq!(
+            parse_expr(&operands(MAX_NESTING_DEPTH)).map(|expr| expr.depth()),
+            Ok(MAX_NESTING_DEPTH)
+        );
+        assert_eq!(
+            parse_expr(&operands(MAX_NESTING_DEPTH + 1)),
+            Err(ParseError {
+                kind: ParseErrorKind::NestingTooDeep,
+                location: 1
+            })
+        );
+
+        let declarations = |count| format!("(let* ({}) x)", "[x 1]".repeat(count));
+        assert_eq!(
+            parse_expr(&declarations(MAX_NESTING_DEPTH - 1)).map(|expr| expr.depth()),
+            Ok(MAX_NESTING_DEPTH)
+        );
+        assert_eq!(
+            parse_expr(&declarations(MAX_NESTING_DEPTH)),
+            Err(ParseError {
+                kind: ParseErrorKind::NestingTooDeep,
+                location: 1
+            })
+        );
     }
 }
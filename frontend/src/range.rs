@@ -0,0 +1,342 @@
+use std::fmt::Display;
+
+use crate::{ArithmeticSemantics, BinaryOpKind, Expr, UnaryOpKind};
+
+/// The integers from `min` to `max`, both included.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Interval {
+    pub min: i64,
+    pub max: i64,
+}
+
+/// Whether an operation on the values of intervals overflows.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OverflowRisk {
+    Never,
+    // The operation overflows for some of the values, but not for all of them.
+    Possible,
+    Certain,
+}
+
+/// The abstract result of an operation on intervals.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct IntervalResult {
+    // The values that the evaluation can continue with, or `None` if it always stops at the
+    // operation.
+    pub value: Option<Interval>,
+    pub risk: OverflowRisk,
+}
+
+impl Interval {
+    pub const FULL: Interval = Interval {
+        min: i64::MIN,
+        max: i64::MAX,
+    };
+
+    pub fn constant(value: i64) -> Self {
+        Self {
+            min: value,
+            max: value,
+        }
+    }
+
+    pub fn contains(&self, value: i64) -> bool {
+        self.min <= value && value <= self.max
+    }
+
+    /// Returns the smallest interval that contains both intervals.
+    pub fn join(self, other: Interval) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn neg(self, semantics: ArithmeticSemantics) -> IntervalResult {
+        exact_result(-(self.max as i128), -(self.min as i128), semantics)
+    }
+
+    pub fn add(self, other: Interval, semantics: ArithmeticSemantics) -> IntervalResult {
+        exact_result(
+            self.min as i128 + other.min as i128,
+            self.max as i128 + other.max as i128,
+            semantics,
+        )
+    }
+
+    pub fn sub(self, other: Interval, semantics: ArithmeticSemantics) -> IntervalResult {
+        exact_result(
+            self.min as i128 - other.max as i128,
+            self.max as i128 - other.min as i128,
+            semantics,
+        )
+    }
+}
+
+/// Converts the exact results `min..=max` of an operation, which may not fit in an `i64`, into
+/// the values that the evaluation continues with under `semantics`.
+fn exact_result(min: i128, max: i128, semantics: ArithmeticSemantics) -> IntervalResult {
+    let (lowest, highest) = (i64::MIN as i128, i64::MAX as i128);
+    let risk = if lowest <= min && max <= highest {
+        OverflowRisk::Never
+    } else if max < lowest || highest < min {
+        OverflowRisk::Certain
+    } else {
+        OverflowRisk::Possible
+    };
+
+    let value = match (risk, semantics) {
+        (OverflowRisk::Never, _) => Some(Interval {
+            min: min as i64,
+            max: max as i64,
+        }),
+        // The results that fit, since the evaluation stops at the others.
+        (OverflowRisk::Possible, ArithmeticSemantics::Checked | ArithmeticSemantics::Trapping) => {
+            Some(Interval {
+                min: min.max(lowest) as i64,
+                max: max.min(highest) as i64,
+            })
+        }
+        (OverflowRisk::Certain, ArithmeticSemantics::Checked | ArithmeticSemantics::Trapping) => {
+            None
+        }
+        // The results that are too large wrap around to the other end of the range.
+        (OverflowRisk::Possible, ArithmeticSemantics::Wrapping) => Some(Interval::FULL),
+        // The results are all on the same side of the range, so they wrap around by the same
+        // amount.
+        (OverflowRisk::Certain, ArithmeticSemantics::Wrapping) => Some(Interval {
+            min: min as i64,
+            max: max as i64,
+        }),
+    };
+
+    IntervalResult { value, risk }
+}
+
+/// An operation that overflows whenever it is evaluated.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OverflowWarning {
+    pub expr: Expr,
+}
+
+impl Display for OverflowWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` always overflows", self.expr)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RangeAnalysis {
+    // The values that the program can produce, or `None` if it never produces one.
+    pub value: Option<Interval>,
+    // The operations that overflow whenever they are evaluated, in evaluation order.
+    pub warnings: Vec<OverflowWarning>,
+}
+
+struct RangeAnalysisImpl {
+    semantics: ArithmeticSemantics,
+    // The scopes from the outermost to the innermost one.
+    scopes: Vec<(String, Interval)>,
+    warnings: Vec<OverflowWarning>,
+}
+
+impl RangeAnalysisImpl {
+    fn lookup(&self, name: &str) -> Interval {
+        // An unknown identifier is reported by the interpreter, and by `uniquify`.
+        self.scopes
+            .iter()
+            .rev()
+            .find(|(variable, _)| variable == name)
+            .map_or(Interval::FULL, |(_, interval)| *interval)
+    }
+
+    fn check(&mut self, expr: &Expr, result: IntervalResult) -> Option<Interval> {
+        if result.risk == OverflowRisk::Certain {
+            self.warnings.push(OverflowWarning { expr: expr.clone() });
+        }
+        result.value
+    }
+
+    /// Computes the values of `expr`, or `None` if its evaluation never produces a value. The
+    /// operands are analyzed in evaluation order, and the analysis stops where the evaluation
+    /// does, so that no warning is given for an operation that is never evaluated.
+    fn analyze(&mut self, expr: &Expr) -> Option<Interval> {
+        match expr {
+            // An integer that does not fit is reported by the interpreter, and by the verifier.
+            Expr::Integer(value) => i64::try_from(*value).ok().map(Interval::constant),
+            Expr::Read => Some(Interval::FULL),
+            Expr::Identifier(name) => Some(self.lookup(name)),
+            Expr::UnaryOperation {
+                kind: UnaryOpKind::Minus,
+                operand,
+            } => {
+                let operand = self.analyze(operand)?;
+                let result = operand.neg(self.semantics);
+                self.check(expr, result)
+            }
+            Expr::BinaryOperation {
+                kind,
+                left_operand,
+                right_operand,
+            } => {
+                let lhs = self.analyze(left_operand)?;
+                let rhs = self.analyze(right_operand)?;
+                let result = match kind {
+                    BinaryOpKind::Add => lhs.add(rhs, self.semantics),
+                    BinaryOpKind::Sub => lhs.sub(rhs, self.semantics),
+                };
+                self.check(expr, result)
+            }
+            Expr::Let {
+                variable_name,
+                init_expr,
+                body,
+            } => {
+                let init = self.analyze(init_expr)?;
+                self.scopes.push((variable_name.clone(), init));
+                let result = self.analyze(body);
+                self.scopes.pop();
+                result
+            }
+        }
+    }
+}
+
+/// Computes the values that `expr` and its operations can produce under `semantics`, where
+/// `read` can produce any `i64`, and finds the operations that always overflow.
+pub fn analyze_ranges(expr: &Expr, semantics: ArithmeticSemantics) -> RangeAnalysis {
+    let mut analysis = RangeAnalysisImpl {
+        semantics,
+        scopes: Vec::new(),
+        warnings: Vec::new(),
+    };
+    let value = analysis.analyze(expr);
+    RangeAnalysis {
+        value,
+        warnings: analysis.warnings,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use crate::{
+        interp_expr_with_options, parse_expr, ExprGenerator, GeneratorConfig, InterpOptions,
+    };
+
+    use super::*;
+
+    fn warnings(code: &str, semantics: ArithmeticSemantics) -> Vec<String> {
+        analyze_ranges(&parse_expr(code).unwrap(), semantics)
+            .warnings
+            .iter()
+            .map(OverflowWarning::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn interval_arithmetic() {
+        use ArithmeticSemantics::*;
+
+        let interval = |min, max| Interval { min, max };
+        assert_eq!(
+            interval(-3, 5).sub(interval(1, 2), Checked),
+            IntervalResult {
+                value: Some(interval(-5, 4)),
+                risk: OverflowRisk::Never
+            }
+        );
+        assert_eq!(
+            Interval::FULL.neg(Trapping),
+            IntervalResult {
+                value: Some(interval(-i64::MAX, i64::MAX)),
+                risk: OverflowRisk::Possible
+            }
+        );
+        assert_eq!(Interval::FULL.neg(Wrapping).value, Some(Interval::FULL));
+
+        let max = Interval::constant(i64::MAX);
+        assert_eq!(
+            max.add(interval(1, 2), Checked),
+            IntervalResult {
+                value: None,
+                risk: OverflowRisk::Certain
+            }
+        );
+        assert_eq!(
+            max.add(interval(1, 2), Wrapping).value,
+            Some(interval(i64::MIN, i64::MIN + 1))
+        );
+    }
+
+    #[test]
+    fn warn_about_overflows() {
+        use ArithmeticSemantics::*;
+
+        assert_eq!(
+            warnings(
+                "(let ([x 9223372036854775800]) (+ (+ x 5) (+ x 2)))",
+                Checked
+            ),
+            ["`(+ (+ x 5) (+ x 2))` always overflows"]
+        );
+        // The sum of `read`s may or may not overflow.
+        assert!(warnings("(+ read (+ read read))", Checked).is_empty());
+        assert!(warnings("(- (- 0 read) 9223372036854775807)", Trapping).is_empty());
+        assert_eq!(
+            warnings("(+ read (+ 9223372036854775807 1))", Trapping),
+            ["`(+ 9223372036854775807 1)` always overflows"]
+        );
+
+        // The evaluation stops at the first overflow, unless arithmetic wraps around.
+        let code = "(+ (- (- 9223372036854775807) 2) (- (- 9223372036854775807) 2))";
+        assert_eq!(
+            warnings(code, Checked),
+            ["`(- (- 9223372036854775807) 2)` always overflows"]
+        );
+        assert_eq!(
+            warnings(code, Wrapping),
+            [
+                "`(- (- 9223372036854775807) 2)` always overflows",
+                "`(- (- 9223372036854775807) 2)` always overflows",
+                "`(+ (- (- 9223372036854775807) 2) (- (- 9223372036854775807) 2))` always \
+                 overflows"
+            ]
+        );
+    }
+
+    #[test]
+    fn analysis_is_sound() {
+        let mut generator = ExprGenerator::new(7, GeneratorConfig::default());
+        for _ in 0..500 {
+            let expr = generator.generate();
+            let inputs = generator.generate_inputs();
+            for semantics in [
+                ArithmeticSemantics::Wrapping,
+                ArithmeticSemantics::Checked,
+                ArithmeticSemantics::Trapping,
+            ] {
+                let analysis = analyze_ranges(&expr, semantics);
+                let options = InterpOptions {
+                    semantics,
+                    ..InterpOptions::default()
+                };
+                if let Ok(evaluation) =
+                    interp_expr_with_options(&expr, &mut VecDeque::from(inputs.clone()), options)
+                {
+                    assert!(
+                        analysis
+                            .value
+                            .is_some_and(|value| value.contains(evaluation.value)),
+                        "{} evaluates to {} under {:?}, which is not in {:?}",
+                        expr,
+                        evaluation.value,
+                        semantics,
+                        analysis.value
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+/// A small deterministic pseudo-random number generator (SplitMix64), so that randomized testing
+/// does not need external crates and every run can be reproduced from its seed.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut result = self.state;
+        result = (result ^ (result >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        result = (result ^ (result >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        result ^ (result >> 31)
+    }
+
+    /// Returns a number in `0..bound`. `bound` must not be zero.
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    pub(crate) fn chance(&mut self, percent: u32) -> bool {
+        self.below(100) < u64::from(percent)
+    }
+
+    /// Returns the index of an item chosen with probability proportional to its weight.
+    pub(crate) fn weighted_index(&mut self, weights: &[u32]) -> Option<usize> {
+        let total: u64 = weights.iter().map(|&weight| u64::from(weight)).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut target = self.below(total);
+        weights.iter().position(|&weight| {
+            if target < u64::from(weight) {
+                true
+            } else {
+                target -= u64::from(weight);
+                false
+            }
+        })
+    }
+}
@@ -0,0 +1,11 @@
+//! Helpers for the tests of this crate and of the crates that depend on it, which enable the
+//! `test-support` feature in their dev-dependencies.
+
+/// Reads a number from the environment variable `name`, e.g. the seed or the number of iterations
+/// of a fuzzer, or returns `default` if it is not set or not a number.
+pub fn env_or(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
@@ -1,10 +1,17 @@
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub(crate) enum TokenKind {
-    EOF,
+use std::ops::Range;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum TokenKind {
+    Eof,
     Unknown,
     Integer,
     Identifier,
 
+    // Trivia, which is only produced if the lexer is asked to keep it.
+    Whitespace,
+    Comment, // `; ...` or `#| ... |#`
+
     LParen,  // (
     RParen,  // )
     Plus,    // +
@@ -15,33 +22,50 @@ pub(crate) enum TokenKind {
     Program, // keyword `program`
     Read,    // keyword `read`
     Let,     // keyword `let`
+    LetStar, // keyword `let*`
+}
+
+impl TokenKind {
+    /// Checks whether tokens of this kind carry no meaning for the parser.
+    pub fn is_trivia(self) -> bool {
+        matches!(self, TokenKind::Whitespace | TokenKind::Comment)
+    }
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
-pub(crate) struct Token<'a> {
+pub struct Token<'a> {
     pub(crate) kind: TokenKind,
     pub(crate) spelling: &'a str,
     pub(crate) location: usize,
 }
 
 impl<'a> Token<'a> {
-    pub(crate) fn token_kind(&self) -> TokenKind {
+    pub fn token_kind(&self) -> TokenKind {
         self.kind
     }
 
-    pub(crate) fn spelling(&self) -> &'a str {
+    pub fn spelling(&self) -> &'a str {
         self.spelling
     }
 
-    pub(crate) fn start_location(&self) -> usize {
+    pub fn start_location(&self) -> usize {
         self.location
     }
 
-    pub(crate) fn end_location(&self) -> usize {
+    pub fn end_location(&self) -> usize {
         self.start_location() + self.len()
     }
 
-    pub(crate) fn len(&self) -> usize {
+    pub fn len(&self) -> usize {
         self.spelling.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.spelling.is_empty()
+    }
+
+    /// Returns the byte range of the token in the source code.
+    pub fn span(&self) -> Range<usize> {
+        self.start_location()..self.end_location()
+    }
 }